@@ -1,37 +1,60 @@
+use crate::audit::{AuditEntry, AuditLog};
+use crate::bulk::BulkRun;
+use crate::config::Config;
+use crate::gameini::{IniEditor, IniUpdate};
+use crate::db::{sibling_path, MemoryStore, ServerStore};
+use crate::history::History;
+use crate::logtail::LogTail;
+use crate::poller::StatusPoller;
+use crate::workshop::{ModScan, ModScanOutcome};
+use crate::service::{BackendKind, Backends, ServiceAction, ServiceOutcome};
+
 use chrono::prelude::*;
+use crossterm::event::KeyEvent;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use thiserror::Error;
-use crossterm::{
-    event::{self, Event as CEvent, KeyCode},
-    terminal::{disable_raw_mode, enable_raw_mode},
-};
-use tui::{
-    backend::CrosstermBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
-    text::{Span, Spans},
-    widgets::{
-        Block, BorderType, Borders, ListState, Paragraph, Tabs, TableState,
-    },
-    Terminal,
-};
+use tui::widgets::{ListState, TableState};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("error reading the DB file: {0}")]
     ReadDBError(#[from] io::Error),
     #[error("error parsing the DB file: {0}")]
     ParseDBError(#[from] serde_json::Error),
+    #[error("error, the DB file {0} is locked by another arkmanager process")]
+    LockDBError(String),
+    #[error("error, the DB file uses schema version {0} which is newer than this arkmanager supports")]
+    SchemaVersionError(u64),
+    #[error("{0}")]
+    ConfigError(String),
+    #[error("error, {0}")]
+    DuplicateIdError(String),
+    #[error("error converting server definitions: {0}")]
+    FormatError(String),
+    #[error("invalid {0}: {1}")]
+    ValidationError(&'static str, String),
+    #[error("error, {0}")]
+    ServiceError(String),
+    #[error("error, could not run {command}: {source}")]
+    SpawnError { command: String, source: io::Error },
+    #[error("error, {command} exited with {}: {stderr}", .code.map_or("a signal".to_string(), |c| format!("status {}", c)))]
+    CommandFailedError { command: String, code: Option<i32>, stderr: String },
+    #[error("error, {command} was denied: {stderr} (run arkmanager with sudo, or allow it through polkit or the docker group)")]
+    PermissionDeniedError { command: String, stderr: String },
     #[error("error, invalid selection")]
     SelectionError,
-    #[error("error, rendering failed")]
-    RenderError,
 }
 
 pub enum Event<I> {
     Input(I),
     Tick,
+    ServiceDone(Box<ServiceOutcome>),
+    ModScanDone(Box<ModScanOutcome>),
 }
 
 pub enum InputEvent {
@@ -44,22 +67,22 @@ pub struct ArkServerMod {
     pub id: usize,
     pub name: String,
     pub category: String,
-    pub descripton: String,
+    pub description: String,
     pub enabled: bool,
     pub age: usize,
     pub created_at: DateTime<Utc>,
+    //What the last scan of the server's Workshop content found on disk, see workshop.rs.
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub version: String,
 }
 
 impl ArkServerMod {
     pub fn named(name: &str) -> ArkServerMod {
         ArkServerMod {
-            id: 0,
             name: name.to_string(),
-            category: "".to_string(),
-            descripton: "".to_string(),
-            enabled: false,
-            age: 0,
-            created_at: Utc::now(),
+            ..ArkServerMod::new()
         }
     }
     pub fn new() -> ArkServerMod {
@@ -67,10 +90,12 @@ impl ArkServerMod {
             id: 0,
             name: "".to_string(),
             category: "".to_string(),
-            descripton: "".to_string(),
-            enabled: false,
+            description: "".to_string(),
+            enabled: true,
             age: 0,
             created_at: Utc::now(),
+            size: 0,
+            version: "".to_string(),
         }
     }
 }
@@ -84,18 +109,43 @@ pub struct ArkServer {
     pub created_at: DateTime<Utc>,
     pub mods: Vec<ArkServerMod>,
     pub service_name: String,
+    #[serde(default)]
+    pub backend: BackendKind,
+    //What the supervisor backend runs, e.g. .../ShooterGame/Binaries/Linux/ShooterGameServer.
+    #[serde(default)]
+    pub executable: String,
+    #[serde(default)]
+    pub arguments: String,
+    //What the docker and podman backends manage, and the image to create the container from if it is missing.
+    #[serde(default)]
+    pub container: String,
+    #[serde(default)]
+    pub image: String,
+    //The machine the server runs on, as given to ssh. Empty for this machine.
+    #[serde(default)]
+    pub host: String,
+    //Where the server's GameUserSettings.ini and Game.ini live on its host,
+    //e.g. .../ShooterGame/Saved/Config/LinuxServer.
+    #[serde(default)]
+    pub config_dir: String,
 }
 
 impl ArkServer {
+    //The mods the server loads, in load order.
+    pub fn active_mods(&self) -> Vec<&ArkServerMod> {
+        self.mods.iter().filter(|m| m.enabled).collect()
+    }
+
+    //An ID for a mod whose Workshop ID is not known yet: the lowest one not on the server. Real Workshop IDs are
+    //far larger, so it stands out until it is replaced.
+    pub fn placeholder_mod_id(&self) -> usize {
+        (1..).find(|id| !self.mods.iter().any(|m| m.id == *id)).expect("a server has fewer than usize::MAX mods")
+    }
+
     pub fn named(name: &str) -> ArkServer {
         ArkServer {
-            id: 0,
             name: name.to_string(),
-            category: "".to_string(),
-            age: 0,
-            created_at: Utc::now(),
-            mods: Vec::new(),
-            service_name: "".to_string(),
+            ..ArkServer::new()
         }
     }
     pub fn new() -> ArkServer {
@@ -107,6 +157,13 @@ impl ArkServer {
             created_at: Utc::now(),
             mods: Vec::new(),
             service_name: "".to_string(),
+            backend: BackendKind::default(),
+            executable: "".to_string(),
+            arguments: "".to_string(),
+            container: "".to_string(),
+            image: "".to_string(),
+            host: "".to_string(),
+            config_dir: "".to_string(),
         }
     }
 }
@@ -120,6 +177,9 @@ pub enum MenuItem {
     ViewMod,
     EditServer,
     EditMod,
+    Audit,
+    Logs,
+    GameConfig,
 }
 
 impl From<MenuItem> for usize {
@@ -132,16 +192,30 @@ impl From<MenuItem> for usize {
             MenuItem::ViewMod => 4,
             MenuItem::EditMod => 5,
             MenuItem::EditServer=> 6,
+            MenuItem::Audit => 3,
+            MenuItem::Logs => 1,
+            MenuItem::GameConfig => 1,
         }
     }
 }
 
-#[derive(Clone, Debug)]
 pub struct ProgState<'a> {
+    pub config: Config,
+    pub store: Box<dyn ServerStore>,
+    pub fleet: MemoryStore,
+    pub dirty: bool,
+    pub history: History,
+    pub audit: AuditLog,
+    pub audit_entries: Vec<AuditEntry>,
+    pub audit_filter: String,
+    pub editing_audit_filter: bool,
+    pub audit_list_state: TableState,
     pub editing_mod: bool,
     pub editing_server: bool,
     pub tmp_mod_field: String,
     pub tmp_server_field: String,
+    //Why the value being edited was rejected, shown next to it until the edit is fixed or abandoned.
+    pub edit_error: Option<String>,
     pub menu_titles: Vec<&'a str>,
     pub active_menu_item: MenuItem,
     pub active_menu_highlight: MenuItem,
@@ -151,15 +225,71 @@ pub struct ProgState<'a> {
     pub ark_server_mod_list_edit_state: TableState,
     pub num_ark_server_properties: usize,
     pub num_ark_server_mod_properties: usize,
+    pub notification: Option<String>,
+    pub backends: Arc<Backends>,
+    //Server statuses, refreshed on a worker thread so rendering never waits on a backend.
+    pub status_poller: Arc<StatusPoller>,
+    //Service actions running on worker threads, by server ID.
+    pub pending_actions: HashMap<usize, ServiceAction>,
+    //Servers marked in the server list for a bulk action, by ID.
+    pub marked_servers: HashSet<usize>,
+    //The bulk action working through the fleet, and the last finished one until its summary is dismissed.
+    pub bulk: Option<BulkRun>,
+    pub bulk_summary: Option<BulkRun>,
+    //A GameUserSettings.ini rewrite shown as a diff until it is confirmed or dropped.
+    pub ini_update: Option<IniUpdate>,
+    //The INI file open in the Config view, kept with any unsaved edits until another one is opened.
+    pub ini_editor: Option<IniEditor>,
+    //What the last scan of a server's Workshop content found, for flagging missing and unlisted mods.
+    pub mod_scan: Option<ModScan>,
+    //The server whose Workshop content is being scanned on a worker thread.
+    pub scanning_mods: Option<usize>,
+    pub editing_ini: bool,
+    pub tmp_ini_field: String,
+    //Where worker threads post their results. None outside the TUI, where actions run inline.
+    pub events: Option<Sender<Event<KeyEvent>>>,
+    //Advanced on every tick to animate the spinner on servers with a pending action.
+    pub spinner_frame: usize,
+    //The log followed by the Logs view, stopped when the view is left.
+    pub log_tail: Option<LogTail>,
+    //The end of the selected server's log for the server view, followed while it is open.
+    pub recent_logs: Option<LogTail>,
+    //Index of the top line shown when not following the end of the log.
+    pub log_scroll: usize,
+    pub log_follow: bool,
+    //What was on screen when the view was paused. The tail keeps collecting in the meantime.
+    pub log_paused: Option<Vec<String>>,
+    pub log_search: String,
+    pub editing_log_search: bool,
+    //The line of the search match last jumped to.
+    pub log_match: Option<usize>,
+    //Lines that fit in the Logs view, noted on every render for paging.
+    pub log_height: usize,
+    //Set by the first q while service actions are running, the second one quits.
+    pub confirm_quit: bool,
 }
 
 impl<'a> ProgState<'a> {
-    pub fn new() -> ProgState<'a> {
+    pub fn new(config: Config, store: Box<dyn ServerStore>) -> ProgState<'a> {
+        let backends = Arc::new(Backends::new(sibling_path(&config.db_path, "run")));
         let mut rs = ProgState {
+             audit: AuditLog::for_db(&config.db_path),
+             status_poller: Arc::new(StatusPoller::new(config.status_interval, Arc::clone(&backends))),
+             backends,
+             config,
+             store,
+             fleet: MemoryStore::default(),
+             dirty: false,
+             history: History::default(),
+             audit_entries: Vec::new(),
+             audit_filter: "".to_string(),
+             editing_audit_filter: false,
+             audit_list_state: TableState::default(),
              editing_mod: false,
              editing_server: false,
              tmp_mod_field: "".to_string(),
              tmp_server_field: "".to_string(),
+             edit_error: None,
              menu_titles: vec!["Home", "List Servers", "Quit"],
              active_menu_item: MenuItem::Home,
              active_menu_highlight: MenuItem::Home,
@@ -167,25 +297,65 @@ impl<'a> ProgState<'a> {
              ark_server_list_edit_state: TableState::default(),
              ark_server_mod_list_state: ListState::default(),
              ark_server_mod_list_edit_state: TableState::default(),
-             num_ark_server_properties: 5,
+             num_ark_server_properties: 12,
              num_ark_server_mod_properties: 4,
+             notification: None,
+             pending_actions: HashMap::new(),
+             marked_servers: HashSet::new(),
+             bulk: None,
+             bulk_summary: None,
+             ini_update: None,
+             ini_editor: None,
+             mod_scan: None,
+             scanning_mods: None,
+             editing_ini: false,
+             tmp_ini_field: "".to_string(),
+             events: None,
+             spinner_frame: 0,
+             log_tail: None,
+             recent_logs: None,
+             log_scroll: 0,
+             log_follow: true,
+             log_paused: None,
+             log_search: "".to_string(),
+             editing_log_search: false,
+             log_match: None,
+             log_height: 0,
+             confirm_quit: false,
         };
         rs.ark_server_list_state.select(Some(0));
         rs.ark_server_list_edit_state.select(Some(0));
         rs.ark_server_mod_list_state.select(Some(0));
         rs.ark_server_mod_list_edit_state.select(Some(0));
-        return rs;
+        rs.audit_list_state.select(Some(0));
+        rs
+    }
+
+    //Replace the working copy with the contents of the store.
+    pub fn load(&mut self) -> Result<(), Error> {
+        self.fleet = self.store.load()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    //Write the working copy back to the store if anything changed since the last load or save.
+    pub fn save(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            self.store.save(&self.fleet)?;
+            self.dirty = false;
+        }
+        Ok(())
     }
 
     pub fn get_server_edit_index(&self) -> usize {
-        return self.ark_server_list_edit_state
+        self.ark_server_list_edit_state
             .selected()
-            .unwrap();
+            .unwrap()
     }
 
     pub fn get_mod_edit_index(&self) -> usize {
-        return self.ark_server_mod_list_edit_state
+        self.ark_server_mod_list_edit_state
             .selected()
-            .unwrap();
+            .unwrap()
     }
 }
@@ -0,0 +1,158 @@
+use crate::ark::Error;
+use crate::db::sibling_path;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+//One line of the audit log. DB changes fill in before/after, service actions fill in output.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub user: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(action: String) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            user: current_user(),
+            action,
+            before: None,
+            after: None,
+            output: None,
+        }
+    }
+
+    //Case insensitive substring match against everything shown in the audit view.
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        [
+            Some(self.user.clone()),
+            Some(self.action.clone()),
+            self.before.as_ref().map(Value::to_string),
+            self.after.as_ref().map(Value::to_string),
+            self.output.clone(),
+        ]
+        .iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(&filter))
+    }
+}
+
+//Append-only JSON lines file kept next to the DB, e.g. db.json.audit.jsonl.
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn for_db(db_path: &Path) -> AuditLog {
+        AuditLog { path: sibling_path(db_path, "audit.jsonl") }
+    }
+
+    pub fn record(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    //All entries, oldest first. Lines that fail to parse are skipped rather than hiding the rest of the log.
+    pub fn read_all(&self) -> Result<Vec<AuditEntry>, Error> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+//Entries matching the filter, newest first.
+pub fn filter_entries<'a>(entries: &'a [AuditEntry], filter: &str) -> Vec<&'a AuditEntry> {
+    entries.iter().rev().filter(|entry| entry.matches(filter)).collect()
+}
+
+//The person behind the change, looking through sudo if necessary.
+fn current_user() -> String {
+    ["SUDO_USER", "USER", "LOGNAME"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|user| !user.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn test_log(name: &str) -> (PathBuf, AuditLog) {
+        let dir = env::temp_dir().join(format!("ark-manager-audit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("can create test dir");
+        let log = AuditLog::for_db(&dir.join("db.json"));
+        (dir, log)
+    }
+
+    #[test]
+    fn record_and_read_all_round_trip() {
+        let (dir, log) = test_log("round-trip");
+        let added = AuditEntry { after: Some(json!({"id": 1, "name": "Island"})), ..AuditEntry::new("Added server Island".to_string()) };
+        let started = AuditEntry { output: Some("Started Island\n".to_string()), ..AuditEntry::new("Start Island".to_string()) };
+        log.record(&added).expect("records");
+        log.record(&started).expect("records");
+
+        let entries = log.read_all().expect("reads");
+        assert_eq!(entries.iter().map(|e| e.action.as_str()).collect::<Vec<_>>(), ["Added server Island", "Start Island"]);
+        assert_eq!(entries[0].timestamp, added.timestamp);
+        assert_eq!(entries[0].user, added.user);
+        assert_eq!(entries[0].before, None);
+        assert_eq!(entries[0].after, added.after);
+        assert_eq!(entries[1].output.as_deref(), Some("Started Island\n"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_all_without_a_log_is_empty() {
+        let (dir, log) = test_log("missing");
+        assert!(log.read_all().expect("reads").is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_all_skips_corrupt_lines() {
+        let (dir, log) = test_log("corrupt");
+        log.record(&AuditEntry::new("Added server Island".to_string())).expect("records");
+        let mut file = OpenOptions::new().append(true).open(&log.path).expect("opens");
+        file.write_all(b"{\"timestamp\": \"not a time\"\n{\"truncated\": \n").expect("writes");
+        log.record(&AuditEntry::new("Removed server Island".to_string())).expect("records");
+
+        let entries = log.read_all().expect("reads");
+        assert_eq!(entries.iter().map(|e| e.action.as_str()).collect::<Vec<_>>(), ["Added server Island", "Removed server Island"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn read_all_reports_unreadable_logs() {
+        let (dir, log) = test_log("unreadable");
+        fs::create_dir_all(&log.path).expect("can create dir in its place");
+        assert!(matches!(log.read_all(), Err(Error::ReadDBError(_))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
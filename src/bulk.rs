@@ -0,0 +1,114 @@
+use crate::ark::{Error, ProgState};
+use crate::db::ServerStore;
+use crate::service::{dispatch_service_action, ServiceAction, ServiceOutcome};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+//How one server fared in a bulk run.
+pub struct BulkResult {
+    pub server_name: String,
+    //What the backend printed, or why the action failed.
+    pub result: Result<String, String>,
+}
+
+//A service action applied to several servers, fed to dispatch_service_action a few at a time from the UI thread.
+//Restarts are rolling: at most concurrency servers restart at once, and each finished restart is followed by
+//a pause so the server can come back up before the next one goes down. Starts and stops all go at once.
+pub struct BulkRun {
+    pub action: ServiceAction,
+    queue: VecDeque<usize>,
+    running: Vec<usize>,
+    pub results: Vec<BulkResult>,
+    pub total: usize,
+    concurrency: usize,
+    delay: Duration,
+    not_before: Instant,
+}
+
+impl BulkRun {
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty() && self.running.is_empty()
+    }
+
+    //e.g. "rolling restart 2/5".
+    pub fn progress(&self) -> String {
+        let name = match self.action {
+            ServiceAction::Restart => "rolling restart".to_string(),
+            action => format!("{} all", action),
+        };
+        format!("{} {}/{}", name, self.results.len(), self.total)
+    }
+}
+
+//Queue an action for the given servers. Only one bulk run goes at a time.
+pub fn start_bulk(state: &mut ProgState, action: ServiceAction, server_ids: Vec<usize>) -> Result<(), Error> {
+    if let Some(bulk) = &state.bulk {
+        return Err(Error::ServiceError(format!("wait for the {} to finish first", bulk.progress())))
+    }
+    if server_ids.is_empty() {
+        return Err(Error::SelectionError)
+    }
+    let (concurrency, delay) = match action {
+        ServiceAction::Restart => (state.config.rolling_concurrency, state.config.rolling_delay),
+        _ => (server_ids.len(), Duration::from_secs(0)),
+    };
+    state.bulk = Some(BulkRun {
+        action,
+        total: server_ids.len(),
+        queue: server_ids.into_iter().collect(),
+        running: Vec::new(),
+        results: Vec::new(),
+        concurrency,
+        delay,
+        not_before: Instant::now(),
+    });
+    advance_bulk(state);
+    Ok(())
+}
+
+//Start as many queued servers as the concurrency limit and delay allow. Called on every tick.
+//Once every server has reported back the run moves to bulk_summary for the popup.
+pub fn advance_bulk(state: &mut ProgState) {
+    let mut bulk = match state.bulk.take() {
+        Some(bulk) => bulk,
+        None => return,
+    };
+    while bulk.running.len() < bulk.concurrency && Instant::now() >= bulk.not_before {
+        let server_id = match bulk.queue.pop_front() {
+            Some(server_id) => server_id,
+            None => break,
+        };
+        match dispatch_service_action(state, server_id, bulk.action) {
+            Ok(()) => bulk.running.push(server_id),
+            Err(e) => {
+                let server_name = state.fleet.get_server(server_id).map_or(format!("server {}", server_id), |s| s.name);
+                bulk.results.push(BulkResult { server_name, result: Err(e.to_string()) });
+            }
+        }
+    }
+    if bulk.is_finished() {
+        state.bulk_summary = Some(bulk);
+    } else {
+        state.bulk = Some(bulk);
+    }
+}
+
+//Note the outcome of a service action if it belongs to the bulk run. Returns whether it did.
+pub fn record_bulk_outcome(state: &mut ProgState, outcome: &ServiceOutcome) -> bool {
+    let bulk = match &mut state.bulk {
+        Some(bulk) => bulk,
+        None => return false,
+    };
+    let position = match bulk.running.iter().position(|&id| id == outcome.ark_server.id) {
+        Some(position) => position,
+        None => return false,
+    };
+    bulk.running.remove(position);
+    bulk.results.push(BulkResult {
+        server_name: outcome.ark_server.name.clone(),
+        result: outcome.result.as_ref().map(|output| output.clone()).map_err(|e| e.to_string()),
+    });
+    bulk.not_before = Instant::now() + bulk.delay;
+    true
+}
@@ -0,0 +1,362 @@
+use crate::ark::Error;
+use crate::db::sibling_path;
+use crate::transfer::{Format, TransferCommand};
+
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+pub const DEFAULT_PROFILE: &str = "default";
+//Where the DB lived before profiles, relative to the directory arkmanager was started from.
+const LEGACY_DB_PATH: &str = "data/db.json";
+//How often server statuses are refreshed in the background.
+pub const DEFAULT_STATUS_INTERVAL: Duration = Duration::from_secs(5);
+//A rolling restart waits this long after each server is back before restarting the next, one at a time.
+pub const DEFAULT_ROLLING_DELAY: Duration = Duration::from_secs(30);
+pub const DEFAULT_ROLLING_CONCURRENCY: usize = 1;
+
+const USAGE: &str = "usage: ark-manager [--db <path>] [--profile <name>] [--status-interval <secs>]
+                   [--rolling-delay <secs>] [--rolling-concurrency <n>]
+       ark-manager export [--server <id>] [--format <fmt>] [--output <file>]
+       ark-manager import <file> [--format <fmt>] [--replace] [--dry-run]
+
+  --db <path>        use the DB file at <path> (env: ARKMANAGER_DB)
+  --profile <name>   use the named fleet profile (env: ARKMANAGER_PROFILE)
+  --status-interval <secs>
+                     seconds between server status checks, default 5 (env: ARKMANAGER_STATUS_INTERVAL)
+  --rolling-delay <secs>
+                     seconds a rolling restart waits between servers, default 30 (env: ARKMANAGER_ROLLING_DELAY)
+  --rolling-concurrency <n>
+                     servers a rolling restart restarts at once, default 1 (env: ARKMANAGER_ROLLING_CONCURRENCY)
+
+  export             write the fleet, or one server with --server, as json, toml or yaml
+  import             merge servers from a file by ID, printing conflicts first
+  --format <fmt>     json, toml or yaml (default: from the file extension, else json)
+  --replace          on import, remove servers that are not in the file
+  --dry-run          on import, only print what would change
+
+Profiles are stored in $XDG_DATA_HOME/arkmanager/profiles/<name>.json.
+Defaults can be set in $XDG_CONFIG_HOME/arkmanager/config.json.";
+
+//Optional settings read from $XDG_CONFIG_HOME/arkmanager/config.json.
+#[derive(Deserialize, Default)]
+struct ConfigFile {
+    db: Option<PathBuf>,
+    profile: Option<String>,
+    status_interval: Option<u64>,
+    rolling_delay: Option<u64>,
+    rolling_concurrency: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub db_path: PathBuf,
+    //None when the DB was given as an explicit path rather than a profile name.
+    pub profile: Option<String>,
+    pub data_dir: PathBuf,
+    pub status_interval: Duration,
+    pub rolling_delay: Duration,
+    pub rolling_concurrency: usize,
+    //Profiles to offer on the Home tab, read when the config is made or refresh_profiles is called so drawing the
+    //tab does not read the profiles directory every frame.
+    pub profiles: Vec<String>,
+    //Something to tell the user once at startup, e.g. that the legacy DB was copied into the default profile.
+    pub notice: Option<String>,
+}
+
+impl Config {
+    //Resolve the DB location from, in order: command line flags, environment variables, the config file,
+    //and finally the default profile. Also returns the import or export to run instead of the TUI, if any.
+    pub fn from_env() -> Result<(Config, Option<TransferCommand>), Error> {
+        let mut db_flag = None;
+        let mut profile_flag = None;
+        let mut interval_flag = None;
+        let mut delay_flag = None;
+        let mut concurrency_flag = None;
+        let mut subcommand = None;
+        let mut file = None;
+        let mut format = None;
+        let mut server_id = None;
+        let mut output = None;
+        let mut replace = false;
+        let mut dry_run = false;
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--db" => db_flag = Some(PathBuf::from(flag_value(&mut args, "--db")?)),
+                "--profile" => profile_flag = Some(flag_value(&mut args, "--profile")?),
+                "--status-interval" => interval_flag = Some(flag_value(&mut args, "--status-interval")?),
+                "--rolling-delay" => delay_flag = Some(flag_value(&mut args, "--rolling-delay")?),
+                "--rolling-concurrency" => concurrency_flag = Some(flag_value(&mut args, "--rolling-concurrency")?),
+                "--format" => format = Some(Format::from_name(&flag_value(&mut args, "--format")?)?),
+                "--server" => {
+                    let value = flag_value(&mut args, "--server")?;
+                    server_id = Some(value.parse::<usize>().map_err(|_| {
+                        Error::ConfigError(format!("--server needs a numeric server ID, got {}", value))
+                    })?);
+                }
+                "-o" | "--output" => output = Some(PathBuf::from(flag_value(&mut args, "--output")?)),
+                "--replace" => replace = true,
+                "--dry-run" => dry_run = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                "export" | "import" if subcommand.is_none() => subcommand = Some(arg),
+                _ if subcommand.as_deref() == Some("import") && file.is_none() && !arg.starts_with('-') => {
+                    file = Some(PathBuf::from(arg))
+                }
+                _ => return Err(Error::ConfigError(format!("unknown argument {}\n\n{}", arg, USAGE))),
+            }
+        }
+        let command = match subcommand.as_deref() {
+            Some("export") => Some(TransferCommand::Export { format, server_id, output }),
+            Some(_) => {
+                let path = file.ok_or_else(|| Error::ConfigError(format!("import needs a file\n\n{}", USAGE)))?;
+                Some(TransferCommand::Import { path, format, replace, dry_run })
+            }
+            None => None,
+        };
+        let mut config = Config::resolve(db_flag, profile_flag)?;
+        let file = read_config_file()?;
+        if let Some(seconds) = number_setting(interval_flag, "ARKMANAGER_STATUS_INTERVAL", file.status_interval, "status interval", 1)? {
+            config.status_interval = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = number_setting(delay_flag, "ARKMANAGER_ROLLING_DELAY", file.rolling_delay, "rolling restart delay", 0)? {
+            config.rolling_delay = Duration::from_secs(seconds);
+        }
+        if let Some(n) = number_setting(concurrency_flag, "ARKMANAGER_ROLLING_CONCURRENCY", file.rolling_concurrency, "rolling restart concurrency", 1)? {
+            config.rolling_concurrency = n as usize;
+        }
+        Ok((config, command))
+    }
+
+    fn resolve(db_flag: Option<PathBuf>, profile_flag: Option<String>) -> Result<Config, Error> {
+        let data_dir = xdg_dir("XDG_DATA_HOME", ".local/share")?.join("arkmanager");
+        if let Some(db_path) = db_flag {
+            return Ok(Config::for_path(data_dir, db_path))
+        }
+        if let Some(profile) = profile_flag {
+            return Config::for_resolved_profile(data_dir, &profile)
+        }
+        if let Some(db_path) = env::var_os("ARKMANAGER_DB") {
+            return Ok(Config::for_path(data_dir, PathBuf::from(db_path)))
+        }
+        if let Ok(profile) = env::var("ARKMANAGER_PROFILE") {
+            return Config::for_resolved_profile(data_dir, &profile)
+        }
+        let file = read_config_file()?;
+        if let Some(db_path) = file.db {
+            return Ok(Config::for_path(data_dir, db_path))
+        }
+        let profile = file.profile.unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        Config::for_resolved_profile(data_dir, &profile)
+    }
+
+    //The profile picked at startup. The default profile takes over the legacy DB if it has none of its own yet.
+    fn for_resolved_profile(data_dir: PathBuf, profile: &str) -> Result<Config, Error> {
+        let mut config = Config::for_profile(data_dir, profile)?;
+        if profile == DEFAULT_PROFILE {
+            config.notice = adopt_legacy_db(Path::new(LEGACY_DB_PATH), &config.db_path)?;
+        }
+        Ok(config)
+    }
+
+    pub fn for_path(data_dir: PathBuf, db_path: PathBuf) -> Config {
+        let mut config = Config {
+            db_path,
+            profile: None,
+            data_dir,
+            status_interval: DEFAULT_STATUS_INTERVAL,
+            rolling_delay: DEFAULT_ROLLING_DELAY,
+            rolling_concurrency: DEFAULT_ROLLING_CONCURRENCY,
+            profiles: Vec::new(),
+            notice: None,
+        };
+        config.refresh_profiles();
+        config
+    }
+
+    pub fn for_profile(data_dir: PathBuf, profile: &str) -> Result<Config, Error> {
+        let mut config = Config {
+            db_path: profile_path(&data_dir, profile)?,
+            profile: Some(profile.to_string()),
+            data_dir,
+            status_interval: DEFAULT_STATUS_INTERVAL,
+            rolling_delay: DEFAULT_ROLLING_DELAY,
+            rolling_concurrency: DEFAULT_ROLLING_CONCURRENCY,
+            profiles: Vec::new(),
+            notice: None,
+        };
+        config.refresh_profiles();
+        Ok(config)
+    }
+
+    //The same settings, pointed at another profile's DB.
+    pub fn with_profile(&self, profile: &str) -> Result<Config, Error> {
+        let for_profile = Config::for_profile(self.data_dir.clone(), profile)?;
+        Ok(Config { db_path: for_profile.db_path, profile: for_profile.profile, profiles: for_profile.profiles, notice: None, ..self.clone() })
+    }
+
+    //Read again which profiles there are, e.g. before cycling through them.
+    pub fn refresh_profiles(&mut self) {
+        self.profiles = self.list_profiles();
+    }
+
+    //Every profile with a DB file on disk, plus the active one even if it has not been saved yet.
+    fn list_profiles(&self) -> Vec<String> {
+        let mut profiles: Vec<String> = fs::read_dir(self.data_dir.join("profiles"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                    .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if let Some(profile) = &self.profile {
+            if !profiles.contains(profile) {
+                profiles.push(profile.clone());
+            }
+        }
+        profiles.sort();
+        profiles
+    }
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::ConfigError(format!("{} needs a value\n\n{}", flag, USAGE)))
+}
+
+//A whole number setting, from the flag, then the environment variable, then the config file, like the DB location.
+//None if it is not set anywhere.
+fn number_setting(flag: Option<String>, var: &str, file_value: Option<u64>, name: &str, min: u64) -> Result<Option<u64>, Error> {
+    let value = match flag.or_else(|| env::var(var).ok()) {
+        Some(value) => value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| Error::ConfigError(format!("the {} must be a whole number, got {}", name, value)))?,
+        None => match file_value {
+            Some(value) => value,
+            None => return Ok(None),
+        },
+    };
+    if value < min {
+        return Err(Error::ConfigError(format!("the {} must be at least {}", name, min)))
+    }
+    Ok(Some(value))
+}
+
+//The name becomes a file name, so it must not be able to point outside the profiles directory.
+fn profile_path(data_dir: &Path, profile: &str) -> Result<PathBuf, Error> {
+    if profile.is_empty() || profile.contains('/') || profile.contains('\\') || profile.contains("..") {
+        return Err(Error::ConfigError(format!(
+            "invalid profile name {:?}, it must not be empty or contain /, \\ or ..",
+            profile
+        )))
+    }
+    Ok(data_dir.join("profiles").join(format!("{}.json", profile)))
+}
+
+//Copy the legacy DB, its audit log and the PID files of servers started from it to db_path, unless db_path already
+//exists. The legacy files are left alone in case an older arkmanager still has them open.
+fn adopt_legacy_db(legacy: &Path, db_path: &Path) -> Result<Option<String>, Error> {
+    if db_path.exists() || !legacy.is_file() {
+        return Ok(None)
+    }
+    let run_dir = sibling_path(db_path, "run");
+    fs::create_dir_all(&run_dir)?;
+    let audit_log = sibling_path(legacy, "audit.jsonl");
+    if audit_log.is_file() {
+        fs::copy(&audit_log, sibling_path(db_path, "audit.jsonl"))?;
+    }
+    if let Ok(entries) = fs::read_dir(sibling_path(legacy, "run")) {
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if let (Some(name), Some("pid")) = (path.file_name(), path.extension().and_then(|ext| ext.to_str())) {
+                fs::copy(&path, run_dir.join(name))?;
+            }
+        }
+    }
+    //The DB goes last, so a failure above leaves nothing that would stop the next start from trying again.
+    fs::copy(legacy, db_path)?;
+    Ok(Some(format!(
+        "Copied the DB from {} to {}, the old file is no longer used",
+        legacy.display(),
+        db_path.display()
+    )))
+}
+
+//$XDG_*_HOME if set and absolute, otherwise the spec's fallback under $HOME.
+fn xdg_dir(var: &str, fallback: &str) -> Result<PathBuf, Error> {
+    if let Some(dir) = env::var_os(var).map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        return Ok(dir)
+    }
+    env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(fallback))
+        .ok_or_else(|| Error::ConfigError(format!("neither {} nor HOME is set", var)))
+}
+
+fn read_config_file() -> Result<ConfigFile, Error> {
+    let path = xdg_dir("XDG_CONFIG_HOME", ".config")?.join("arkmanager").join("config.json");
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| Error::ConfigError(format!("{} is not valid: {}", path.display(), e))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(ConfigFile::default()),
+        Err(e) => Err(Error::ConfigError(format!("cannot read {}: {}", path.display(), e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adopts_legacy_db_once() {
+        let dir = env::temp_dir().join(format!("ark-manager-config-{}-legacy", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let legacy = dir.join("data").join("db.json");
+        fs::create_dir_all(sibling_path(&legacy, "run")).expect("legacy run dir created");
+        fs::write(&legacy, "[]").expect("legacy DB written");
+        fs::write(sibling_path(&legacy, "audit.jsonl"), "{}\n").expect("legacy audit log written");
+        fs::write(sibling_path(&legacy, "run").join("0.pid"), "42 1000\n").expect("legacy PID file written");
+        fs::write(sibling_path(&legacy, "run").join("0.log"), "started\n").expect("legacy log written");
+        let db_path = profile_path(&dir.join("arkmanager"), DEFAULT_PROFILE).expect("valid profile");
+
+        assert!(adopt_legacy_db(&legacy, &db_path).expect("adopted").is_some());
+        assert_eq!(fs::read_to_string(&db_path).expect("DB copied"), "[]");
+        assert_eq!(fs::read_to_string(sibling_path(&db_path, "audit.jsonl")).expect("audit log copied"), "{}\n");
+        assert_eq!(fs::read_to_string(sibling_path(&db_path, "run").join("0.pid")).expect("PID file copied"), "42 1000\n");
+        assert!(!sibling_path(&db_path, "run").join("0.log").exists());
+        assert!(legacy.exists());
+
+        fs::write(&legacy, "[{}]").expect("legacy DB changed");
+        assert!(adopt_legacy_db(&legacy, &db_path).expect("nothing to adopt").is_none());
+        assert_eq!(fs::read_to_string(&db_path).expect("DB kept"), "[]");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ignores_missing_legacy_db() {
+        let dir = env::temp_dir().join(format!("ark-manager-config-{}-no-legacy", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let db_path = profile_path(&dir, DEFAULT_PROFILE).expect("valid profile");
+        assert!(adopt_legacy_db(&dir.join("db.json"), &db_path).expect("nothing to adopt").is_none());
+        assert!(!db_path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_profile_names_outside_the_profiles_directory() {
+        let data_dir = Path::new("/data");
+        assert_eq!(profile_path(data_dir, "test-1").expect("valid profile"), Path::new("/data/profiles/test-1.json"));
+        for profile in ["", "a/b", "/etc/passwd", "a\\b", "..", "../other", "a..b"] {
+            assert!(matches!(profile_path(data_dir, profile), Err(Error::ConfigError(_))), "{:?}", profile);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::ark::{ArkServer, Error};
+use crate::host::on_host;
+use crate::service::{command_error, run_command, spawn_command, ServiceBackend, ServiceState, ServiceStatus};
+
+use chrono::prelude::*;
+use serde_json::Value;
+use std::process::Command;
+
+//Runs servers as containers through the docker or podman CLI, given as a name to look up on PATH or a path.
+//If the container does not exist yet and the server names an image, start creates it from that image.
+pub struct ContainerBackend {
+    cli: String,
+}
+
+impl ContainerBackend {
+    pub fn new<S: Into<String>>(cli: S) -> ContainerBackend {
+        ContainerBackend { cli: cli.into() }
+    }
+
+    fn container<'a>(&self, ark_server: &'a ArkServer) -> Result<&'a str, Error> {
+        match ark_server.container.trim() {
+            "" => Err(Error::ServiceError(format!("server {} has no container name", ark_server.name))),
+            name => Ok(name),
+        }
+    }
+
+    //The docker or podman CLI on the server's host.
+    fn cli(&self, ark_server: &ArkServer, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.cli);
+        command.args(args);
+        on_host(&ark_server.host, command)
+    }
+
+    fn run(&self, ark_server: &ArkServer, args: &[&str]) -> Result<String, Error> {
+        run_command(&mut self.cli(ark_server, args))
+    }
+
+    //The inspect document for the container, or None if there is no such container.
+    //Any other failure, e.g. no access to the daemon, is an error rather than a missing container.
+    fn inspect(&self, ark_server: &ArkServer, container: &str) -> Result<Option<Value>, Error> {
+        let mut command = self.cli(ark_server, &["inspect", "--type", "container", container]);
+        let output = spawn_command(&mut command)?;
+        if !output.status.success() {
+            return match is_no_such_container(&String::from_utf8_lossy(&output.stderr)) {
+                true => Ok(None),
+                false => Err(command_error(&command, &output)),
+            }
+        }
+        let doc: Value = serde_json::from_slice(&output.stdout)?;
+        Ok(doc.get(0).cloned())
+    }
+}
+
+//docker says "No such container: x" or "No such object: x", podman "no such container".
+fn is_no_such_container(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("no such container") || stderr.contains("no such object")
+}
+
+impl ServiceBackend for ContainerBackend {
+    fn start(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        let container = self.container(ark_server)?;
+        if self.inspect(ark_server, container)?.is_some() {
+            return self.run(ark_server, &["start", container])
+        }
+        match ark_server.image.trim() {
+            "" => Err(Error::ServiceError(format!("container {} does not exist and server {} has no image to create it from", container, ark_server.name))),
+            image => self.run(ark_server, &["run", "--detach", "--name", container, image]),
+        }
+    }
+
+    fn stop(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.run(ark_server, &["stop", self.container(ark_server)?])
+    }
+
+    fn restart(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.run(ark_server, &["restart", self.container(ark_server)?])
+    }
+
+    fn status(&self, ark_server: &ArkServer) -> Result<ServiceState, Error> {
+        let inspected = match self.inspect(ark_server, self.container(ark_server)?)? {
+            Some(inspected) => inspected,
+            None => return Ok(ServiceState::new(ServiceStatus::Unknown("no container".to_string()))),
+        };
+        let state = &inspected["State"];
+        let status = match state["Status"].as_str().unwrap_or("") {
+            "running" => ServiceStatus::Active,
+            "created" | "exited" => ServiceStatus::Inactive,
+            "restarting" => ServiceStatus::Activating,
+            "removing" | "stopping" => ServiceStatus::Deactivating,
+            "dead" => ServiceStatus::Failed,
+            other => ServiceStatus::Unknown(other.to_string()),
+        };
+        Ok(ServiceState {
+            main_pid: state["Pid"].as_u64().map(|pid| pid as u32).filter(|pid| *pid != 0),
+            since: state["StartedAt"].as_str().and_then(|s| DateTime::parse_from_rfc3339(s).ok()).map(|t| t.with_timezone(&Utc)),
+            restarts: inspected["RestartCount"].as_u64().map(|n| n as u32),
+            //podman before 4.0 calls it Healthcheck.
+            health: state["Health"]["Status"]
+                .as_str()
+                .or_else(|| state["Healthcheck"]["Status"].as_str())
+                .filter(|health| !health.is_empty())
+                .map(str::to_string),
+            ..ServiceState::new(status)
+        })
+    }
+
+    fn logs(&self, ark_server: &ArkServer, lines: usize) -> Result<String, Error> {
+        let lines = lines.to_string();
+        let mut command = self.cli(ark_server, &["logs", "--tail", &lines, self.container(ark_server)?]);
+        let output = spawn_command(&mut command)?;
+        if !output.status.success() {
+            return Err(command_error(&command, &output))
+        }
+        //The server's stdout and stderr come back on ours, interleaving is lost either way.
+        Ok(String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr))
+    }
+
+    //Without a container name the polled logs explain what is missing.
+    fn follow(&self, ark_server: &ArkServer, lines: usize) -> Option<Command> {
+        let container = self.container(ark_server).ok()?;
+        Some(self.cli(ark_server, &["logs", "--follow", "--tail", &lines.to_string(), container]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Once;
+
+    //Answers like docker for a few container names: "running" exists, "denied" cannot be inspected for lack of
+    //access to the daemon and anything else does not exist. Other commands print their arguments.
+    const FAKE_DOCKER: &str = r#"#!/bin/sh
+if [ "$1" = inspect ]; then
+    case "$4" in
+        running) echo '[{"State": {"Status": "running", "Pid": 4242, "StartedAt": "2021-03-26T12:21:56.137815227Z", "Health": {"Status": "healthy"}}, "RestartCount": 2}]' ;;
+        denied) echo "Got permission denied while trying to connect to the Docker daemon socket" >&2; exit 1 ;;
+        *) echo "Error: No such object: $4" >&2; exit 1 ;;
+    esac
+else
+    echo "$@"
+fi
+"#;
+
+    static FAKE_DOCKER_WRITTEN: Once = Once::new();
+
+    //A backend that runs the fake docker, written once for all tests.
+    fn fake_docker() -> ContainerBackend {
+        let dir = env::temp_dir().join(format!("ark-manager-container-{}", std::process::id()));
+        let script = dir.join("docker");
+        FAKE_DOCKER_WRITTEN.call_once(|| {
+            fs::create_dir_all(&dir).expect("can create fake docker dir");
+            fs::write(&script, FAKE_DOCKER).expect("can write fake docker");
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("can make fake docker executable");
+        });
+        ContainerBackend::new(script.to_string_lossy())
+    }
+
+    fn server(container: &str, image: &str) -> ArkServer {
+        ArkServer { container: container.to_string(), image: image.to_string(), ..ArkServer::named("Test") }
+    }
+
+    #[test]
+    fn inspect_parses_existing_container() {
+        let backend = fake_docker();
+        let inspected = backend.inspect(&server("running", ""), "running").expect("inspects").expect("exists");
+        assert_eq!(inspected["State"]["Pid"], 4242);
+    }
+
+    #[test]
+    fn inspect_missing_container_is_none() {
+        let backend = fake_docker();
+        assert!(backend.inspect(&server("gone", ""), "gone").expect("inspects").is_none());
+    }
+
+    #[test]
+    fn inspect_surfaces_other_failures() {
+        let backend = fake_docker();
+        let error = backend.inspect(&server("denied", ""), "denied").expect_err("fails");
+        assert!(matches!(error, Error::PermissionDeniedError { .. }), "{:?}", error);
+    }
+
+    #[test]
+    fn status_reads_state() {
+        let backend = fake_docker();
+        let state = backend.status(&server("running", "")).expect("has status");
+        assert_eq!(state.status, ServiceStatus::Active);
+        assert_eq!(state.main_pid, Some(4242));
+        assert_eq!(state.restarts, Some(2));
+        assert_eq!(state.health.as_deref(), Some("healthy"));
+        assert!(state.since.is_some());
+    }
+
+    #[test]
+    fn status_of_missing_container() {
+        let backend = fake_docker();
+        let state = backend.status(&server("gone", "")).expect("has status");
+        assert_eq!(state.status, ServiceStatus::Unknown("no container".to_string()));
+    }
+
+    #[test]
+    fn start_existing_container() {
+        let backend = fake_docker();
+        assert_eq!(backend.start(&server("running", "")).expect("starts").trim(), "start running");
+    }
+
+    #[test]
+    fn start_creates_missing_container_from_image() {
+        let backend = fake_docker();
+        let output = backend.start(&server("gone", "ark:latest")).expect("starts");
+        assert_eq!(output.trim(), "run --detach --name gone ark:latest");
+    }
+
+    #[test]
+    fn start_missing_container_without_image() {
+        let backend = fake_docker();
+        assert!(matches!(backend.start(&server("gone", "")), Err(Error::ServiceError(_))));
+    }
+
+    #[test]
+    fn start_does_not_create_container_it_cannot_inspect() {
+        let backend = fake_docker();
+        assert!(matches!(backend.start(&server("denied", "ark:latest")), Err(Error::PermissionDeniedError { .. })));
+    }
+}
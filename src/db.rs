@@ -1,135 +1,594 @@
-use crate::ark::{ArkServer, ArkServerMod, Error, ProgState};
-use crate::DB_PATH;
+use crate::ark::{ArkServer, ArkServerMod, Error, MenuItem, ProgState};
+use crate::audit::{AuditEntry, AuditLog};
+use crate::history::{Change, History};
+use crate::service::Backends;
+use crate::migrate::{migrate, schema_version, SCHEMA_VERSION};
+use crate::validate::{set_mod_field, set_server_field};
 
-use std::fs;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-pub fn get_servers() -> Result<Vec<ArkServer>, Error> {
-    let db_content = fs::read_to_string(DB_PATH)?;
-    let parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-    Ok(parsed)
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+//Storage backend for the server fleet.
+//Servers are addressed by the ID the store allocated for them, mods by their Steam Workshop ID.
+pub trait ServerStore {
+    fn load(&self) -> Result<MemoryStore, Error>;
+    fn save(&mut self, db: &MemoryStore) -> Result<(), Error>;
+    fn list_servers(&self) -> Result<Vec<ArkServer>, Error>;
+    fn get_server(&self, server_id: usize) -> Result<ArkServer, Error>;
+    fn insert_server(&mut self, index: usize, ark_server: ArkServer) -> Result<usize, Error>;
+    fn update_server(&mut self, server_id: usize, ark_server: ArkServer) -> Result<(), Error>;
+    fn delete_server(&mut self, server_id: usize) -> Result<ArkServer, Error>;
+    fn list_mods(&self, server_id: usize) -> Result<Vec<ArkServerMod>, Error>;
+    fn get_mod(&self, server_id: usize, mod_id: usize) -> Result<ArkServerMod, Error>;
+    fn insert_mod(&mut self, server_id: usize, index: usize, ark_server_mod: ArkServerMod) -> Result<(), Error>;
+    fn update_mod(&mut self, server_id: usize, mod_id: usize, ark_server_mod: ArkServerMod) -> Result<(), Error>;
+    fn delete_mod(&mut self, server_id: usize, mod_id: usize) -> Result<ArkServerMod, Error>;
 }
 
-pub fn get_server(state: &ProgState) -> Result<ArkServer, Error> {
-    let ark_server_list = get_servers().expect("can fetch ark_server list");
-    let selected_ark_server = ark_server_list
-        .get(
-            state.ark_server_list_state
-            .selected()
-            .expect("there is always a selected ark_server"),
-            )
-        .expect("exists")
-        .clone();
-    return Ok(selected_ark_server);
+//Keeps the fleet in memory only. Used for tests and as the loaded working copy held by ProgState.
+#[derive(Clone)]
+pub struct MemoryStore {
+    servers: Vec<ArkServer>,
+    next_server_id: usize,
 }
 
-pub fn get_num_servers() -> Result<usize, Error> {
-    let ark_server_list = get_servers().expect("can fetch ark_server list");
-    let num_ark_servers = ark_server_list.len();
-    return Ok(num_ark_servers);
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new(Vec::new(), 1)
+    }
 }
 
-pub fn get_num_server_mods(state: &ProgState) -> Result<usize, Error> {
-    let selected_ark_server = get_server(state).expect("Can get seleted server");
-    let num_ark_server_mods = selected_ark_server.mods.len();
-    return Ok(num_ark_server_mods);
+impl MemoryStore {
+    //next_server_id is raised past any ID already in use, so a stale counter can never hand out a duplicate.
+    pub fn new(servers: Vec<ArkServer>, next_server_id: usize) -> MemoryStore {
+        let max_id = servers.iter().map(|s| s.id).max().unwrap_or(0);
+        MemoryStore { servers, next_server_id: next_server_id.max(max_id + 1) }
+    }
+
+    pub fn servers(&self) -> &[ArkServer] {
+        &self.servers
+    }
+
+    //Reserve a fresh server ID. IDs are never handed out twice, even after the server is deleted.
+    pub fn allocate_server_id(&mut self) -> usize {
+        self.next_server_id += 1;
+        self.next_server_id - 1
+    }
+
+    fn server_index(&self, server_id: usize) -> Result<usize, Error> {
+        self.servers
+            .iter()
+            .position(|s| s.id == server_id)
+            .ok_or(Error::SelectionError)
+    }
+
+    fn server_mut(&mut self, server_id: usize) -> Result<&mut ArkServer, Error> {
+        let index = self.server_index(server_id)?;
+        Ok(&mut self.servers[index])
+    }
 }
 
-pub fn add_ark_server_to_db() -> Result<Vec<ArkServer>, Error> {
-    let db_content = fs::read_to_string(DB_PATH)?;
-    let mut parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-    parsed.push(ArkServer::named("New Server"));
-    fs::write(DB_PATH, &serde_json::to_vec(&parsed)?)?;
-    Ok(parsed)
+//Workshop IDs identify a mod, so a server can only list each one once.
+fn check_mod_id(ark_server: &ArkServer, mod_id: usize, ignore: Option<usize>) -> Result<(), Error> {
+    if ark_server.mods.iter().any(|m| m.id == mod_id && Some(m.id) != ignore) {
+        return Err(Error::DuplicateIdError(format!("mod {} is already on server {}", mod_id, ark_server.name)))
+    }
+    Ok(())
 }
 
-pub fn remove_ark_server_at_index(state: &mut ProgState) -> Result<(), Error> {
-    if let Some(selected) = state.ark_server_list_state.selected() {
-        let db_content = fs::read_to_string(DB_PATH)?;
-        let mut parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-        parsed.remove(selected);
-        fs::write(DB_PATH, &serde_json::to_vec(&parsed)?)?;
-        if selected > 0 {
-            state.ark_server_list_state.select(Some(selected - 1));
+impl ServerStore for MemoryStore {
+    fn load(&self) -> Result<MemoryStore, Error> {
+        Ok(self.clone())
+    }
+
+    fn save(&mut self, db: &MemoryStore) -> Result<(), Error> {
+        *self = db.clone();
+        Ok(())
+    }
+
+    fn list_servers(&self) -> Result<Vec<ArkServer>, Error> {
+        Ok(self.servers.clone())
+    }
+
+    fn get_server(&self, server_id: usize) -> Result<ArkServer, Error> {
+        Ok(self.servers[self.server_index(server_id)?].clone())
+    }
+
+    //A server with ID 0 gets a fresh one, otherwise its ID is kept so undo and redo restore the same record.
+    fn insert_server(&mut self, index: usize, mut ark_server: ArkServer) -> Result<usize, Error> {
+        if ark_server.id == 0 {
+            ark_server.id = self.allocate_server_id();
+        } else if self.server_index(ark_server.id).is_ok() {
+            return Err(Error::DuplicateIdError(format!("server ID {} is already in use", ark_server.id)))
         }
-        return Ok(())
+        self.next_server_id = self.next_server_id.max(ark_server.id + 1);
+        let server_id = ark_server.id;
+        self.servers.insert(index.min(self.servers.len()), ark_server);
+        Ok(server_id)
+    }
+
+    fn update_server(&mut self, server_id: usize, ark_server: ArkServer) -> Result<(), Error> {
+        if ark_server.id != server_id && self.server_index(ark_server.id).is_ok() {
+            return Err(Error::DuplicateIdError(format!("server ID {} is already in use", ark_server.id)))
+        }
+        self.next_server_id = self.next_server_id.max(ark_server.id + 1);
+        *self.server_mut(server_id)? = ark_server;
+        Ok(())
+    }
+
+    fn delete_server(&mut self, server_id: usize) -> Result<ArkServer, Error> {
+        let index = self.server_index(server_id)?;
+        Ok(self.servers.remove(index))
+    }
+
+    fn list_mods(&self, server_id: usize) -> Result<Vec<ArkServerMod>, Error> {
+        Ok(self.get_server(server_id)?.mods)
+    }
+
+    fn get_mod(&self, server_id: usize, mod_id: usize) -> Result<ArkServerMod, Error> {
+        self.list_mods(server_id)?
+            .into_iter()
+            .find(|m| m.id == mod_id)
+            .ok_or(Error::SelectionError)
+    }
+
+    fn insert_mod(&mut self, server_id: usize, index: usize, ark_server_mod: ArkServerMod) -> Result<(), Error> {
+        let ark_server = self.server_mut(server_id)?;
+        check_mod_id(ark_server, ark_server_mod.id, None)?;
+        ark_server.mods.insert(index.min(ark_server.mods.len()), ark_server_mod);
+        Ok(())
+    }
+
+    fn update_mod(&mut self, server_id: usize, mod_id: usize, ark_server_mod: ArkServerMod) -> Result<(), Error> {
+        let ark_server = self.server_mut(server_id)?;
+        check_mod_id(ark_server, ark_server_mod.id, Some(mod_id))?;
+        let selected = ark_server.mods
+            .iter_mut()
+            .find(|m| m.id == mod_id)
+            .ok_or(Error::SelectionError)?;
+        *selected = ark_server_mod;
+        Ok(())
+    }
+
+    fn delete_mod(&mut self, server_id: usize, mod_id: usize) -> Result<ArkServerMod, Error> {
+        let mods = &mut self.server_mut(server_id)?.mods;
+        let index = mods.iter().position(|m| m.id == mod_id).ok_or(Error::SelectionError)?;
+        Ok(mods.remove(index))
     }
-    return Err(Error::SelectionError)
 }
 
+//Layout of the DB file on disk. See migrate.rs for older layouts.
+#[derive(Serialize, Deserialize)]
+struct DbFile<S> {
+    schema_version: u64,
+    next_server_id: usize,
+    servers: S,
+}
+
+//Stores the fleet as a versioned JSON document on disk.
+//The record level calls read and rewrite the whole file, so the TUI works on a loaded copy instead.
+//An advisory lock on <path>.lock is held for as long as the store is open.
+//Opening a path that does not exist yet creates an empty DB there.
+pub struct JsonStore {
+    path: PathBuf,
+    _lock: File,
+}
 
-pub fn set_server_property(state: &mut ProgState) -> Result<(), Error> {
-    if let Some(selected_server) = state.ark_server_list_state.selected() {
-        let db_content = fs::read_to_string(DB_PATH)?;
-        let mut parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-        match state.ark_server_list_edit_state.selected().unwrap() {
-            0 => parsed[selected_server].id = state.tmp_server_field.parse::<usize>().unwrap(),
-            1 => parsed[selected_server].name = state.tmp_server_field.clone(),
-            2 => parsed[selected_server].category = state.tmp_server_field.clone(),
-            3 => parsed[selected_server].age = state.tmp_server_field.parse::<usize>().unwrap(),
-            4 => parsed[selected_server].service_name = state.tmp_server_field.clone(),
-            _ => {}
+impl JsonStore {
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<JsonStore, Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
         }
-        fs::write(DB_PATH, &serde_json::to_vec(&parsed)?)?;
-        if selected_server > 0 {
-            state.ark_server_list_state.select(Some(selected_server - 1));
+        let lock_path = sibling_path(&path, "lock");
+        let lock = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(Error::LockDBError(path.display().to_string())),
+            Err(TryLockError::Error(e)) => return Err(Error::ReadDBError(e)),
         }
-        return Ok(())
+        let store = JsonStore { path, _lock: lock };
+        if !store.path.exists() {
+            store.write(&MemoryStore::default())?;
+        }
+        store.upgrade()?;
+        Ok(store)
     }
-    return Err(Error::SelectionError)
-}
-
-
-pub fn set_server_mod_property(state: &mut ProgState) -> Result<(), Error> {
-    if let Some(selected_server) = state.ark_server_list_state.selected() {
-        if let Some(selected_mod) = state.ark_server_mod_list_state.selected() {
-            let db_content = fs::read_to_string(DB_PATH)?;
-            let mut parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-            match state.ark_server_mod_list_edit_state.selected().unwrap() {
-                0 => parsed[selected_server].mods[selected_mod].id = state.tmp_mod_field.parse::<usize>().unwrap(),
-                1 => parsed[selected_server].mods[selected_mod].name = state.tmp_mod_field.clone(),
-                2 => parsed[selected_server].mods[selected_mod].category = state.tmp_mod_field.clone(),
-                3 => parsed[selected_server].mods[selected_mod].age = state.tmp_mod_field.parse::<usize>().unwrap(),
-                _ => {}
-            }
-            fs::write(DB_PATH, &serde_json::to_vec(&parsed)?)?;
-            if selected_mod > 0 {
-                state.ark_server_mod_list_state.select(Some(selected_mod - 1));
-            }
+
+    //Rewrite a DB from an older schema in the current one, keeping the original as <path>.v<N>.bak.
+    fn upgrade(&self) -> Result<(), Error> {
+        let doc: Value = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        let version = schema_version(&doc);
+        if version >= SCHEMA_VERSION {
             return Ok(())
         }
+        fs::copy(&self.path, sibling_path(&self.path, &format!("v{}.bak", version)))?;
+        let parsed: DbFile<Vec<ArkServer>> = serde_json::from_value(migrate(doc)?)?;
+        self.write(&MemoryStore::new(parsed.servers, parsed.next_server_id))
+    }
+
+    fn read(&self) -> Result<MemoryStore, Error> {
+        let doc: Value = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        let parsed: DbFile<Vec<ArkServer>> = serde_json::from_value(migrate(doc)?)?;
+        Ok(MemoryStore::new(parsed.servers, parsed.next_server_id))
+    }
+
+    //Write to a temporary file and rename it over the DB so a crash never leaves a truncated file behind.
+    fn write(&self, db: &MemoryStore) -> Result<(), Error> {
+        let tmp_path = sibling_path(&self.path, "tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        let db_file = DbFile {
+            schema_version: SCHEMA_VERSION,
+            next_server_id: db.next_server_id,
+            servers: &db.servers,
+        };
+        tmp.write_all(&serde_json::to_vec(&db_file)?)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent_dir(&self.path)
+    }
+}
+
+//Build "<path>.<ext>", e.g. db.json.lock or db.json.v0.bak, so the helper files always sit next to the DB.
+pub fn sibling_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(ext);
+    path.with_file_name(name)
+}
+
+//The rename is only durable once the directory entry itself has been flushed.
+fn sync_parent_dir(path: &Path) -> Result<(), Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+impl ServerStore for JsonStore {
+    fn load(&self) -> Result<MemoryStore, Error> {
+        self.read()
+    }
+
+    fn save(&mut self, db: &MemoryStore) -> Result<(), Error> {
+        self.write(db)
+    }
+
+    fn list_servers(&self) -> Result<Vec<ArkServer>, Error> {
+        self.read()?.list_servers()
+    }
+
+    fn get_server(&self, server_id: usize) -> Result<ArkServer, Error> {
+        self.read()?.get_server(server_id)
+    }
+
+    fn insert_server(&mut self, index: usize, ark_server: ArkServer) -> Result<usize, Error> {
+        let mut db = self.read()?;
+        let server_id = db.insert_server(index, ark_server)?;
+        self.write(&db)?;
+        Ok(server_id)
+    }
+
+    fn update_server(&mut self, server_id: usize, ark_server: ArkServer) -> Result<(), Error> {
+        let mut db = self.read()?;
+        db.update_server(server_id, ark_server)?;
+        self.write(&db)
+    }
+
+    fn delete_server(&mut self, server_id: usize) -> Result<ArkServer, Error> {
+        let mut db = self.read()?;
+        let removed = db.delete_server(server_id)?;
+        self.write(&db)?;
+        Ok(removed)
+    }
+
+    fn list_mods(&self, server_id: usize) -> Result<Vec<ArkServerMod>, Error> {
+        self.read()?.list_mods(server_id)
+    }
+
+    fn get_mod(&self, server_id: usize, mod_id: usize) -> Result<ArkServerMod, Error> {
+        self.read()?.get_mod(server_id, mod_id)
+    }
+
+    fn insert_mod(&mut self, server_id: usize, index: usize, ark_server_mod: ArkServerMod) -> Result<(), Error> {
+        let mut db = self.read()?;
+        db.insert_mod(server_id, index, ark_server_mod)?;
+        self.write(&db)
+    }
+
+    fn update_mod(&mut self, server_id: usize, mod_id: usize, ark_server_mod: ArkServerMod) -> Result<(), Error> {
+        let mut db = self.read()?;
+        db.update_mod(server_id, mod_id, ark_server_mod)?;
+        self.write(&db)
+    }
+
+    fn delete_mod(&mut self, server_id: usize, mod_id: usize) -> Result<ArkServerMod, Error> {
+        let mut db = self.read()?;
+        let removed = db.delete_mod(server_id, mod_id)?;
+        self.write(&db)?;
+        Ok(removed)
+    }
+}
+
+//Save the current fleet and open the named profile in its place.
+//The current store stays open if the new one cannot be opened, e.g. because it is locked.
+pub fn switch_profile(state: &mut ProgState, profile: &str) -> Result<(), Error> {
+    //Their outcomes would be logged against the wrong fleet.
+    if !state.pending_actions.is_empty() || state.bulk.is_some() {
+        return Err(Error::ServiceError("wait for the running service actions to finish before switching profiles".to_string()))
+    }
+    if state.scanning_mods.is_some() {
+        return Err(Error::ServiceError("wait for the running mod scan to finish before switching profiles".to_string()))
+    }
+    let config = state.config.with_profile(profile)?;
+    //Opening the DB again would find it locked, by ourselves.
+    if config.db_path == state.config.db_path {
+        state.config = config;
+        return Ok(())
+    }
+    state.save()?;
+    let store = JsonStore::open(&config.db_path)?;
+    state.store = Box::new(store);
+    state.audit = AuditLog::for_db(&config.db_path);
+    state.backends = Arc::new(Backends::new(sibling_path(&config.db_path, "run")));
+    state.config = config;
+    state.load()?;
+    state.history = History::default();
+    reset_fleet_views(state);
+    state.status_poller.forget(&state.backends, state.fleet.servers());
+    Ok(())
+}
+
+//Drop everything shown about the previous fleet. Server and mod IDs are only unique within a profile, so any of it
+//left behind would be taken for the new fleet's servers.
+fn reset_fleet_views(state: &mut ProgState) {
+    state.marked_servers.clear();
+    state.bulk_summary = None;
+    state.mod_scan = None;
+    state.ini_update = None;
+    state.ini_editor = None;
+    state.editing_ini = false;
+    state.tmp_ini_field.clear();
+    state.log_tail = None;
+    state.recent_logs = None;
+    state.log_scroll = 0;
+    state.log_follow = true;
+    state.log_paused = None;
+    state.log_search.clear();
+    state.editing_log_search = false;
+    state.log_match = None;
+    state.audit_entries.clear();
+    state.audit_filter.clear();
+    state.editing_audit_filter = false;
+    state.audit_list_state.select(Some(0));
+    state.editing_mod = false;
+    state.editing_server = false;
+    state.tmp_mod_field.clear();
+    state.tmp_server_field.clear();
+    state.edit_error = None;
+    state.ark_server_list_state.select(Some(0));
+    state.ark_server_list_edit_state.select(Some(0));
+    state.ark_server_mod_list_state.select(Some(0));
+    state.ark_server_mod_list_edit_state.select(Some(0));
+}
+
+//Every mutation of the fleet goes through here so it can be undone, is saved straight away and ends up in the audit log.
+pub fn commit(state: &mut ProgState, change: Change) -> Result<(), Error> {
+    change.apply(&mut state.fleet)?;
+    let entry = change.audit_entry();
+    state.history.record(change);
+    state.dirty = true;
+    state.save()?;
+    state.audit.record(&entry)
+}
+
+pub fn undo(state: &mut ProgState) -> Result<(), Error> {
+    let (server_id, mod_id) = (selected_server_id(state).ok(), selected_mod_id(state).ok());
+    if let Some(applied) = state.history.undo(&mut state.fleet)? {
+        state.dirty = true;
+        state.save()?;
+        clamp_selection(state);
+        leave_removed_records(state, server_id, mod_id);
+        let entry = applied.audit_entry();
+        state.audit.record(&AuditEntry { action: format!("Undo: {}", entry.action), ..entry })?;
+    }
+    Ok(())
+}
+
+pub fn redo(state: &mut ProgState) -> Result<(), Error> {
+    let (server_id, mod_id) = (selected_server_id(state).ok(), selected_mod_id(state).ok());
+    if let Some(applied) = state.history.redo(&mut state.fleet)? {
+        state.dirty = true;
+        state.save()?;
+        clamp_selection(state);
+        leave_removed_records(state, server_id, mod_id);
+        let entry = applied.audit_entry();
+        state.audit.record(&AuditEntry { action: format!("Redo: {}", entry.action), ..entry })?;
+    }
+    Ok(())
+}
+
+//Go back to the list when the server or mod on screen was removed by an undo or redo, rather than showing
+//whichever record the cursor lands on next, or nothing at all.
+fn leave_removed_records(state: &mut ProgState, server_id: Option<usize>, mod_id: Option<usize>) {
+    let server_gone = server_id.is_none_or(|id| state.fleet.get_server(id).is_err());
+    let mod_gone = match (server_id, mod_id) {
+        (Some(server_id), Some(mod_id)) => state.fleet.get_mod(server_id, mod_id).is_err(),
+        _ => true,
+    };
+    state.active_menu_item = match state.active_menu_item {
+        MenuItem::ViewServer | MenuItem::EditServer | MenuItem::ServerMods | MenuItem::ViewMod | MenuItem::EditMod if server_gone => {
+            MenuItem::Servers
+        }
+        MenuItem::ViewMod | MenuItem::EditMod if mod_gone => MenuItem::ServerMods,
+        current => current,
+    };
+}
+
+//Keep the list cursors on an existing row after servers or mods disappear.
+fn clamp_selection(state: &mut ProgState) {
+    let num_servers = state.fleet.servers().len();
+    if let Some(selected) = state.ark_server_list_state.selected() {
+        state.ark_server_list_state.select(Some(selected.min(num_servers.saturating_sub(1))));
+    }
+    let num_mods = get_num_server_mods(state).unwrap_or(0);
+    if let Some(selected) = state.ark_server_mod_list_state.selected() {
+        state.ark_server_mod_list_state.select(Some(selected.min(num_mods.saturating_sub(1))));
+    }
+}
+
+//The ID of the server under the cursor in the server list.
+pub fn selected_server_id(state: &ProgState) -> Result<usize, Error> {
+    let selected = state.ark_server_list_state.selected().ok_or(Error::SelectionError)?;
+    state.fleet
+        .servers()
+        .get(selected)
+        .map(|s| s.id)
+        .ok_or(Error::SelectionError)
+}
+
+//The Workshop ID of the mod under the cursor in the mod list.
+pub fn selected_mod_id(state: &ProgState) -> Result<usize, Error> {
+    let selected = state.ark_server_mod_list_state.selected().ok_or(Error::SelectionError)?;
+    state.fleet
+        .get_server(selected_server_id(state)?)?
+        .mods
+        .get(selected)
+        .map(|m| m.id)
+        .ok_or(Error::SelectionError)
+}
+
+pub fn get_servers(state: &ProgState) -> Result<Vec<ArkServer>, Error> {
+    state.fleet.list_servers()
+}
+
+pub fn get_server(state: &ProgState) -> Result<ArkServer, Error> {
+    state.fleet.get_server(selected_server_id(state)?)
+}
+
+//Indexes into the fleet grouped by host, this machine first and then the others by name.
+//Servers keep their fleet order within a host.
+pub fn servers_by_host(state: &ProgState) -> Vec<(String, Vec<usize>)> {
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (i, ark_server) in state.fleet.servers().iter().enumerate() {
+        match groups.iter_mut().find(|(host, _)| *host == ark_server.host) {
+            Some((_, indexes)) => indexes.push(i),
+            None => groups.push((ark_server.host.clone(), vec![i])),
+        }
     }
-    return Err(Error::SelectionError)
+    groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+    groups
+}
+
+//The order servers are listed in, which the arrow keys follow.
+pub fn server_display_order(state: &ProgState) -> Vec<usize> {
+    servers_by_host(state).into_iter().flat_map(|(_, indexes)| indexes).collect()
+}
+
+pub fn get_num_servers(state: &ProgState) -> Result<usize, Error> {
+    let num_ark_servers = state.fleet.servers().len();
+    Ok(num_ark_servers)
+}
+
+pub fn get_num_server_mods(state: &ProgState) -> Result<usize, Error> {
+    let num_ark_server_mods = get_server(state)?.mods.len();
+    Ok(num_ark_server_mods)
+}
+
+pub fn add_ark_server_to_db(state: &mut ProgState) -> Result<Vec<ArkServer>, Error> {
+    let mut server = ArkServer::named("New Server");
+    server.id = state.fleet.allocate_server_id();
+    let index = state.fleet.servers().len();
+    commit(state, Change::InsertServer { index, server })?;
+    get_servers(state)
 }
 
+pub fn remove_ark_server(state: &mut ProgState, server_id: usize) -> Result<(), Error> {
+    let index = state.fleet
+        .servers()
+        .iter()
+        .position(|s| s.id == server_id)
+        .ok_or(Error::SelectionError)?;
+    let server = state.fleet.servers()[index].clone();
+    commit(state, Change::DeleteServer { index, server })?;
+    clamp_selection(state);
+    Ok(())
+}
 
 
-pub fn get_server_mods(state: &ProgState) -> Result<Vec<ArkServerMod>, Error> {
-    let selected_ark_server = get_server(state).expect("server exists");
-    return Ok(selected_ark_server.mods)
+pub fn set_server_property(state: &mut ProgState, server_id: usize) -> Result<(), Error> {
+    let before = state.fleet.get_server(server_id)?;
+    let mut ark_server = before.clone();
+    let field = state.ark_server_list_edit_state.selected().ok_or(Error::SelectionError)?;
+    set_server_field(&state.fleet, &mut ark_server, field, &state.tmp_server_field)?;
+    commit(state, Change::UpdateServer { before, after: ark_server })?;
+    Ok(())
 }
 
+
+pub fn set_server_mod_property(state: &mut ProgState, server_id: usize, mod_id: usize) -> Result<(), Error> {
+    let before = state.fleet.get_mod(server_id, mod_id)?;
+    let mut ark_server_mod = before.clone();
+    let field = state.ark_server_mod_list_edit_state.selected().ok_or(Error::SelectionError)?;
+    set_mod_field(&state.fleet.get_server(server_id)?, &mut ark_server_mod, field, &state.tmp_mod_field)?;
+    commit(state, Change::UpdateMod { server_id, before, after: ark_server_mod })?;
+    Ok(())
+}
+
+
+
+//The active mods, with a count of the disabled ones if there are any.
 pub fn get_server_mods_str(state: &ProgState) -> Result<String, Error> {
-    let selected_ark_server_mods = get_server_mods(state).expect("Mods exist");
-    let mods_str = selected_ark_server_mods.into_iter().map(|i| i.name + &", ".to_string()).collect::<String>();
-    return Ok(mods_str)
+    let selected_ark_server = get_server(state)?;
+    let active_mods = selected_ark_server.active_mods();
+    let mut mods_str = active_mods.iter().map(|m| m.name.clone() + ", ").collect::<String>();
+    let num_disabled = selected_ark_server.mods.len() - active_mods.len();
+    if num_disabled > 0 {
+        mods_str += &format!("({} disabled)", num_disabled);
+    }
+    Ok(mods_str)
+}
+
+pub fn toggle_server_mod(state: &mut ProgState, server_id: usize, mod_id: usize) -> Result<(), Error> {
+    let before = state.fleet.get_mod(server_id, mod_id)?;
+    let after = ArkServerMod { enabled: !before.enabled, ..before.clone() };
+    commit(state, Change::UpdateMod { server_id, before, after })
+}
+
+//Enable or disable every mod on the server as a single change, so one undo puts them all back.
+pub fn set_all_server_mods_enabled(state: &mut ProgState, server_id: usize, enabled: bool) -> Result<(), Error> {
+    let before = state.fleet.get_server(server_id)?;
+    if before.mods.iter().all(|m| m.enabled == enabled) {
+        return Ok(())
+    }
+    let mut after = before.clone();
+    for ark_mod in &mut after.mods {
+        ark_mod.enabled = enabled;
+    }
+    commit(state, Change::UpdateServer { before, after })
+}
+
+//Move a mod to another place in the server's load order, keeping it selected. Later mods override earlier ones.
+pub fn move_server_mod(state: &mut ProgState, server_id: usize, mod_id: usize, to: usize) -> Result<(), Error> {
+    let mods = state.fleet.get_server(server_id)?.mods;
+    let from = mods.iter().position(|m| m.id == mod_id).ok_or(Error::SelectionError)?;
+    let to = to.min(mods.len().saturating_sub(1));
+    if from != to {
+        commit(state, Change::MoveMod { server_id, ark_mod: mods[from].clone(), from, to })?;
+    }
+    state.ark_server_mod_list_state.select(Some(to));
+    Ok(())
 }
 
 pub fn get_server_mod(state: &ProgState) -> Result<ArkServerMod, Error> {
-    let selected_ark_server_mod = get_server_mods(state)
-        .unwrap()
-        .get(
-            state.ark_server_mod_list_state
-                .selected()
-                .expect("there is always a selected ark_server"),
-        )
-        .expect("exists")
-        .clone();
-    return Ok(selected_ark_server_mod)
-}
-
-pub fn get_server_service_name(state: &ProgState) -> Result<String, Error> {
-    let selected_ark_server = get_server(state).expect("Server has mod");
-    Ok(selected_ark_server.service_name.to_string())
+    state.fleet.get_mod(selected_server_id(state)?, selected_mod_id(state)?)
 }
 
 pub fn get_server_properties(state: &ProgState) -> Result<Vec<String>, Error> {
@@ -140,6 +599,13 @@ pub fn get_server_properties(state: &ProgState) -> Result<Vec<String>, Error> {
         selected_ark_server.category.to_string(),
         selected_ark_server.age.to_string(),
         selected_ark_server.service_name.to_string(),
+        selected_ark_server.backend.to_string(),
+        selected_ark_server.executable.to_string(),
+        selected_ark_server.arguments.to_string(),
+        selected_ark_server.container.to_string(),
+        selected_ark_server.image.to_string(),
+        selected_ark_server.host.to_string(),
+        selected_ark_server.config_dir.to_string(),
         selected_ark_server.created_at.to_string(),
     ];
     Ok(props)
@@ -179,29 +645,169 @@ pub fn get_server_property(state: &ProgState) -> Result<String, Error> {
 
 
 
-pub fn add_ark_server_mod_to_db(state: &ProgState) -> Result<Vec<ArkServer>, Error> {
-    if let Some(selected_server) = state.ark_server_list_state.selected() {
-        let db_content = fs::read_to_string(DB_PATH)?;
-        let mut parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-        parsed[selected_server].mods.push(ArkServerMod::named("New Mod"));
-        fs::write(DB_PATH, &serde_json::to_vec(&parsed)?)?;
-        return Ok(parsed)
-    }
-    return Err(Error::SelectionError)
+pub fn add_ark_server_mod_to_db(state: &mut ProgState, server_id: usize) -> Result<Vec<ArkServer>, Error> {
+    let ark_server = state.fleet.get_server(server_id)?;
+    let ark_mod = ArkServerMod { id: ark_server.placeholder_mod_id(), ..ArkServerMod::named("New Mod") };
+    state.notification = Some(format!("Added New Mod with placeholder ID {}, edit it to set the Workshop ID", ark_mod.id));
+    commit(state, Change::InsertMod { server_id, index: ark_server.mods.len(), ark_mod })?;
+    get_servers(state)
 }
 
-pub fn remove_ark_server_mod_at_index(state: &mut ProgState) -> Result<(), Error> {
-    if let Some(selected_server) = state.ark_server_list_state.selected() {
-        if let Some(selected_mod) = state.ark_server_mod_list_state.selected() {
-            let db_content = fs::read_to_string(DB_PATH)?;
-            let mut parsed: Vec<ArkServer> = serde_json::from_str(&db_content)?;
-            parsed[selected_server].mods.remove(selected_mod);
-            fs::write(DB_PATH, &serde_json::to_vec(&parsed)?)?;
-            if selected_mod > 0 {
-                state.ark_server_mod_list_state.select(Some(selected_mod - 1));
-            }
-            return Ok(())
-        }
+pub fn remove_ark_server_mod(state: &mut ProgState, server_id: usize, mod_id: usize) -> Result<(), Error> {
+    let mods = state.fleet.get_server(server_id)?.mods;
+    let index = mods.iter().position(|m| m.id == mod_id).ok_or(Error::SelectionError)?;
+    commit(state, Change::DeleteMod { server_id, index, ark_mod: mods[index].clone() })?;
+    clamp_selection(state);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::workshop::ModScan;
+
+    fn server(id: usize, name: &str) -> ArkServer {
+        ArkServer { id, ..ArkServer::named(name) }
+    }
+
+    fn ark_mod(id: usize) -> ArkServerMod {
+        ArkServerMod { id, ..ArkServerMod::named("Mod") }
+    }
+
+    fn ids(fleet: &MemoryStore) -> Vec<usize> {
+        fleet.servers().iter().map(|s| s.id).collect()
+    }
+
+    #[test]
+    fn new_raises_next_id_past_existing_ones() {
+        let mut fleet = MemoryStore::new(vec![server(7, "Island")], 1);
+        assert_eq!(fleet.allocate_server_id(), 8);
+        assert_eq!(fleet.allocate_server_id(), 9);
+    }
+
+    #[test]
+    fn insert_server_allocates_or_keeps_ids() {
+        let mut fleet = MemoryStore::default();
+        assert_eq!(fleet.insert_server(0, server(0, "Island")).expect("inserts"), 1);
+        assert_eq!(fleet.insert_server(0, server(5, "Ragnarok")).expect("inserts"), 5);
+        assert_eq!(fleet.insert_server(9, server(0, "Valguero")).expect("inserts"), 6);
+        assert_eq!(ids(&fleet), vec![5, 1, 6]);
+        assert!(matches!(fleet.insert_server(0, server(5, "Again")), Err(Error::DuplicateIdError(_))));
+    }
+
+    #[test]
+    fn deleted_server_ids_are_not_reused() {
+        let mut fleet = MemoryStore::default();
+        let id = fleet.insert_server(0, server(0, "Island")).expect("inserts");
+        assert_eq!(fleet.delete_server(id).expect("deletes").name, "Island");
+        assert_ne!(fleet.insert_server(0, server(0, "Ragnarok")).expect("inserts"), id);
+    }
+
+    #[test]
+    fn update_server() {
+        let mut fleet = MemoryStore::new(vec![server(1, "Island"), server(2, "Ragnarok")], 3);
+        fleet.update_server(1, server(1, "The Island")).expect("updates");
+        assert_eq!(fleet.get_server(1).expect("exists").name, "The Island");
+        fleet.update_server(1, server(10, "The Island")).expect("changes ID");
+        assert_eq!(ids(&fleet), vec![10, 2]);
+        assert_eq!(fleet.allocate_server_id(), 11);
+        assert!(matches!(fleet.update_server(10, server(2, "Clash")), Err(Error::DuplicateIdError(_))));
+    }
+
+    #[test]
+    fn missing_server_is_selection_error() {
+        let mut fleet = MemoryStore::new(vec![server(1, "Island")], 2);
+        assert!(matches!(fleet.get_server(2), Err(Error::SelectionError)));
+        assert!(matches!(fleet.update_server(2, server(2, "Ragnarok")), Err(Error::SelectionError)));
+        assert!(matches!(fleet.delete_server(2), Err(Error::SelectionError)));
+        assert!(matches!(fleet.insert_mod(2, 0, ark_mod(1)), Err(Error::SelectionError)));
+        assert_eq!(ids(&fleet), vec![1]);
+    }
+
+    #[test]
+    fn insert_update_and_delete_mods() {
+        let mut fleet = MemoryStore::new(vec![server(1, "Island")], 2);
+        fleet.insert_mod(1, 0, ark_mod(731604991)).expect("inserts");
+        fleet.insert_mod(1, 0, ark_mod(889745138)).expect("inserts");
+        fleet.insert_mod(1, 99, ark_mod(1404697612)).expect("inserts at the end");
+        let mod_ids = |fleet: &MemoryStore| fleet.list_mods(1).expect("exists").iter().map(|m| m.id).collect::<Vec<_>>();
+        assert_eq!(mod_ids(&fleet), vec![889745138, 731604991, 1404697612]);
+        assert!(matches!(fleet.insert_mod(1, 0, ark_mod(731604991)), Err(Error::DuplicateIdError(_))));
+
+        fleet.update_mod(1, 731604991, ArkServerMod { enabled: false, ..ark_mod(731604991) }).expect("updates");
+        assert!(!fleet.get_mod(1, 731604991).expect("exists").enabled);
+        assert!(matches!(fleet.update_mod(1, 731604991, ark_mod(889745138)), Err(Error::DuplicateIdError(_))));
+
+        assert_eq!(fleet.delete_mod(1, 889745138).expect("deletes").id, 889745138);
+        assert_eq!(mod_ids(&fleet), vec![731604991, 1404697612]);
+    }
+
+    #[test]
+    fn missing_mod_is_selection_error() {
+        let mut fleet = MemoryStore::new(vec![server(1, "Island")], 2);
+        fleet.insert_mod(1, 0, ark_mod(731604991)).expect("inserts");
+        assert!(matches!(fleet.get_mod(1, 1), Err(Error::SelectionError)));
+        assert!(matches!(fleet.update_mod(1, 1, ark_mod(1)), Err(Error::SelectionError)));
+        assert!(matches!(fleet.delete_mod(1, 1), Err(Error::SelectionError)));
+    }
+
+    #[test]
+    fn save_and_load_replace_the_whole_fleet() {
+        let mut store = MemoryStore::default();
+        let fleet = MemoryStore::new(vec![server(3, "Island")], 4);
+        store.save(&fleet).expect("saves");
+        let loaded = store.load().expect("loads");
+        assert_eq!(ids(&loaded), vec![3]);
+    }
+
+    #[test]
+    fn json_store_records_are_saved_to_disk() {
+        let path = std::env::temp_dir().join(format!("ark-manager-db-{}-records", std::process::id())).join("db.json");
+        let _ = fs::remove_dir_all(path.parent().expect("has a parent"));
+        let mut store = JsonStore::open(&path).expect("opens");
+        let island = store.insert_server(0, server(0, "Island")).expect("inserts");
+        let ragnarok = store.insert_server(1, server(0, "Ragnarok")).expect("inserts");
+        store.insert_mod(island, 0, ark_mod(731604991)).expect("inserts mod");
+        store.update_server(ragnarok, server(ragnarok, "Ragnarok PvE")).expect("updates");
+        drop(store);
+
+        let mut store = JsonStore::open(&path).expect("reopens");
+        assert_eq!(store.list_servers().expect("lists").iter().map(|s| s.id).collect::<Vec<_>>(), vec![island, ragnarok]);
+        assert_eq!(store.get_server(ragnarok).expect("gets").name, "Ragnarok PvE");
+        assert_eq!(store.get_mod(island, 731604991).expect("gets mod").id, 731604991);
+        assert_eq!(store.delete_mod(island, 731604991).expect("deletes mod").id, 731604991);
+        assert_eq!(store.delete_server(island).expect("deletes").name, "Island");
+        assert!(matches!(store.get_server(island), Err(Error::SelectionError)));
+        assert_eq!(ids(&store.load().expect("loads")), vec![ragnarok]);
+        drop(store);
+        let _ = fs::remove_dir_all(path.parent().expect("has a parent"));
+    }
+
+    #[test]
+    fn switch_profile_drops_the_old_fleets_views() {
+        let data_dir = std::env::temp_dir().join(format!("ark-manager-db-{}-switch", std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        let config = Config::for_profile(data_dir.clone(), "first").expect("valid profile");
+        let store = JsonStore::open(&config.db_path).expect("opens");
+        let mut state = ProgState::new(config, Box::new(store));
+        state.load().expect("loads");
+        state.fleet.insert_server(0, server(0, "Island")).expect("inserts");
+        state.marked_servers.insert(1);
+        state.mod_scan = Some(ModScan { server_id: 1, missing: vec![731604991], unlisted: Vec::new() });
+        state.audit_entries.push(AuditEntry::new("Added server Island".to_string()));
+        state.log_search = "error".to_string();
+        state.edit_error = Some("invalid port".to_string());
+
+        switch_profile(&mut state, "second").expect("switches");
+        assert_eq!(state.config.profile.as_deref(), Some("second"));
+        assert!(state.fleet.servers().is_empty());
+        assert!(state.marked_servers.is_empty());
+        assert!(state.mod_scan.is_none());
+        assert!(state.audit_entries.is_empty());
+        assert!(state.log_search.is_empty());
+        assert!(state.edit_error.is_none());
+        drop(state);
+        let _ = fs::remove_dir_all(&data_dir);
     }
-    return Err(Error::SelectionError)
 }
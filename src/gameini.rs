@@ -0,0 +1,537 @@
+use crate::ark::{ArkServer, Error, MenuItem, ProgState};
+use crate::audit::AuditEntry;
+use crate::db::{get_server, ServerStore};
+use crate::host;
+
+use crate::ini::{parse_entry, Entry, EntryOp, IniDocument};
+
+use std::path::PathBuf;
+use tui::widgets::{ListState, TableState};
+
+//Unchanged lines shown around each change in the preview.
+const DIFF_CONTEXT: usize = 2;
+//Most changed lines a diff preview lines up, beyond that the whole changed part is shown replaced.
+const MAX_DIFF_EDITS: isize = 1000;
+
+//One line of a diff preview.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+    //Unchanged lines left out between two changes.
+    Skipped(usize),
+}
+
+//The settings files of a server that can be edited from the Config view.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConfigFile {
+    GameUserSettings,
+    Game,
+}
+
+impl ConfigFile {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ConfigFile::GameUserSettings => "GameUserSettings.ini",
+            ConfigFile::Game => "Game.ini",
+        }
+    }
+
+    pub fn other(self) -> ConfigFile {
+        match self {
+            ConfigFile::GameUserSettings => ConfigFile::Game,
+            ConfigFile::Game => ConfigFile::GameUserSettings,
+        }
+    }
+
+    //Where keys added to a file with no sections yet go.
+    fn default_section(self) -> &'static str {
+        match self {
+            ConfigFile::GameUserSettings => "ServerSettings",
+            ConfigFile::Game => "/script/shootergame.shootergamemode",
+        }
+    }
+}
+
+//A rewrite of one of a server's INI files waiting for the user to confirm it.
+pub struct IniUpdate {
+    pub server_id: usize,
+    pub server_name: String,
+    pub host: String,
+    pub path: PathBuf,
+    //What is being written, for the audit log, e.g. "Write ActiveMods".
+    pub action: String,
+    //What the file held when the preview was made, None if it did not exist yet.
+    pub before: Option<String>,
+    pub after: String,
+    pub diff: Vec<DiffLine>,
+}
+
+//One of a server's INI files open in the Config view. Edits are made to the parsed copy and only reach the
+//server once they are written, through the same diff preview as ActiveMods.
+pub struct IniEditor {
+    pub server_id: usize,
+    pub server_name: String,
+    pub host: String,
+    pub path: PathBuf,
+    pub file: ConfigFile,
+    //What the file held when it was loaded, None if it does not exist yet.
+    pub loaded: Option<String>,
+    pub doc: IniDocument,
+    pub section_state: ListState,
+    pub entry_state: TableState,
+    //Whether Up and Down move through the keys of the section rather than the sections.
+    pub entries_focused: bool,
+    //Set while a new Key=Value entry is typed in, rather than an existing value edited.
+    pub adding: bool,
+}
+
+impl IniEditor {
+    pub fn open(ark_server: &ArkServer, file: ConfigFile) -> Result<IniEditor, Error> {
+        let path = config_file_path(ark_server, file)?;
+        let loaded = host::read_file(&ark_server.host, &path)?;
+        let mut editor = IniEditor {
+            server_id: ark_server.id,
+            server_name: ark_server.name.clone(),
+            host: ark_server.host.clone(),
+            doc: IniDocument::parse(loaded.as_deref().unwrap_or("")),
+            path,
+            file,
+            loaded,
+            section_state: ListState::default(),
+            entry_state: TableState::default(),
+            entries_focused: false,
+            adding: false,
+        };
+        editor.section_state.select(Some(0));
+        editor.entry_state.select(Some(0));
+        Ok(editor)
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.doc.to_text() != self.loaded.as_deref().unwrap_or("")
+    }
+
+    pub fn selected_section(&self) -> Option<String> {
+        self.section_state.selected().and_then(|i| self.doc.sections().into_iter().nth(i))
+    }
+
+    pub fn selected_entry(&self) -> Option<Entry> {
+        let section = self.selected_section()?;
+        self.entry_state.selected().and_then(|i| self.doc.entries(&section).into_iter().nth(i))
+    }
+}
+
+pub fn config_file_path(ark_server: &ArkServer, file: ConfigFile) -> Result<PathBuf, Error> {
+    if ark_server.config_dir.is_empty() {
+        return Err(Error::ConfigError(format!("set the config dir of server {} first", ark_server.name)))
+    }
+    Ok(PathBuf::from(&ark_server.config_dir).join(file.file_name()))
+}
+
+//The value ARK expects: the enabled mods' IDs in load order, separated by commas.
+pub fn active_mods_value(ark_server: &ArkServer) -> String {
+    ark_server.active_mods()
+        .iter()
+        .map(|m| m.id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+//The INI text with ActiveMods in [ServerSettings] set to the value, and every other line left exactly as it was.
+pub fn set_active_mods(content: &str, value: &str) -> String {
+    let mut doc = IniDocument::parse(content);
+    doc.set("ServerSettings", "ActiveMods", value);
+    doc.to_text()
+}
+
+//The lines that differ between two texts, with a little unchanged context around each change.
+//Lines both texts start or end with are matched up front, so the Myers search in diff_lines only walks the part
+//that changed, usually a few lines even in a large Game.ini.
+pub fn diff(before: &str, after: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+    let mut full: Vec<DiffLine> = old[..prefix].iter().map(|line| DiffLine::Same(line.to_string())).collect();
+    full.extend(diff_lines(&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]));
+    full.extend(old[old.len() - suffix..].iter().map(|line| DiffLine::Same(line.to_string())));
+
+    //Every line within DIFF_CONTEXT of a change is shown.
+    let mut near_change = vec![false; full.len()];
+    for (i, line) in full.iter().enumerate() {
+        if !matches!(line, DiffLine::Same(_)) {
+            let last = (i + DIFF_CONTEXT).min(full.len() - 1);
+            near_change[i.saturating_sub(DIFF_CONTEXT)..=last].iter_mut().for_each(|near| *near = true);
+        }
+    }
+    let mut shown = Vec::new();
+    let mut skipped = 0;
+    for (line, near) in full.into_iter().zip(near_change) {
+        if near {
+            if skipped > 0 {
+                shown.push(DiffLine::Skipped(skipped));
+                skipped = 0;
+            }
+            shown.push(line);
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 && !shown.is_empty() {
+        shown.push(DiffLine::Skipped(skipped));
+    }
+    shown
+}
+
+//Line by line diff of the changed middle of two texts, following Myers' shortest edit script. The work grows with
+//the number of changed lines rather than the size of the file, so a few edits spread over a large Game.ini stay
+//cheap. Past MAX_DIFF_EDITS the old lines are shown removed and the new ones added.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    //trace[d][k + d] is how far along the old text the furthest path with d edits gets on diagonal k = x - y.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut found = false;
+    for d in 0..=(n + m).min(MAX_DIFF_EDITS) {
+        let mut furthest = vec![0; 2 * d as usize + 1];
+        for k in (-d..=d).step_by(2) {
+            let mut x = match trace.last() {
+                None => 0,
+                Some(prev) if takes_insertion(prev, d, k) => prev[(k + d) as usize],
+                Some(prev) => prev[(k + d - 2) as usize] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[(k + d) as usize] = x;
+            if x >= n && y >= m {
+                found = true;
+                break
+            }
+        }
+        trace.push(furthest);
+        if found {
+            break
+        }
+    }
+    if !found {
+        return old.iter()
+            .map(|line| DiffLine::Removed(line.to_string()))
+            .chain(new.iter().map(|line| DiffLine::Added(line.to_string())))
+            .collect()
+    }
+
+    //Walk back from the end, one edit per step, collecting the lines in reverse.
+    let mut lines = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let k = x - y;
+        let prev_k = if takes_insertion(prev, d, k) { k + 1 } else { k - 1 };
+        let prev_x = prev[(prev_k + d - 1) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            lines.push(DiffLine::Same(old[x as usize].to_string()));
+        }
+        if x == prev_x {
+            lines.push(DiffLine::Added(new[prev_y as usize].to_string()));
+        } else {
+            lines.push(DiffLine::Removed(old[prev_x as usize].to_string()));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        lines.push(DiffLine::Same(old[x as usize].to_string()));
+    }
+    lines.reverse();
+    lines
+}
+
+//Whether the furthest path onto diagonal k with d edits comes down from diagonal k + 1, adding a line, rather than
+//across from k - 1, removing one. prev holds the paths with d - 1 edits.
+fn takes_insertion(prev: &[isize], d: isize, k: isize) -> bool {
+    k == -d || (k != d && prev[(k - 1 + d - 1) as usize] < prev[(k + 1 + d - 1) as usize])
+}
+
+//Work out the new GameUserSettings.ini for the selected server and hold it for confirmation.
+pub fn preview_active_mods(state: &mut ProgState) -> Result<(), Error> {
+    let ark_server = get_server(state)?;
+    let path = config_file_path(&ark_server, ConfigFile::GameUserSettings)?;
+    let before = host::read_file(&ark_server.host, &path)?;
+    let after = set_active_mods(before.as_deref().unwrap_or(""), &active_mods_value(&ark_server));
+    if before.as_deref() == Some(after.as_str()) {
+        state.notification = Some(format!("ActiveMods in {} is already up to date", path.display()));
+        return Ok(())
+    }
+    state.ini_update = Some(IniUpdate {
+        server_id: ark_server.id,
+        server_name: ark_server.name,
+        host: ark_server.host,
+        action: "Write ActiveMods".to_string(),
+        diff: diff(before.as_deref().unwrap_or(""), &after),
+        path,
+        before,
+        after,
+    });
+    Ok(())
+}
+
+//Open the selected server's GameUserSettings.ini in the Config view. Unsaved edits to the file already open are
+//kept when coming back to the same server, and block opening another one.
+pub fn open_ini_editor(state: &mut ProgState) -> Result<(), Error> {
+    let ark_server = get_server(state)?;
+    match &state.ini_editor {
+        Some(editor) if editor.server_id == ark_server.id
+            && editor.host == ark_server.host
+            && config_file_path(&ark_server, editor.file).ok().as_ref() == Some(&editor.path) => {}
+        Some(editor) if editor.is_modified() => {
+            return Err(Error::ConfigError(format!(
+                "{} of server {} has unsaved edits, write or reload it first",
+                editor.file.file_name(),
+                editor.server_name
+            )))
+        }
+        _ => state.ini_editor = Some(IniEditor::open(&ark_server, ConfigFile::GameUserSettings)?),
+    }
+    state.active_menu_item = MenuItem::GameConfig;
+    Ok(())
+}
+
+//Switch between GameUserSettings.ini and Game.ini.
+pub fn switch_ini_file(state: &mut ProgState) -> Result<(), Error> {
+    let editor = state.ini_editor.as_ref().ok_or(Error::SelectionError)?;
+    if editor.is_modified() {
+        return Err(Error::ConfigError(format!("{} has unsaved edits, write or reload it first", editor.file.file_name())))
+    }
+    let file = editor.file.other();
+    let ark_server = state.fleet.get_server(editor.server_id)?;
+    state.ini_editor = Some(IniEditor::open(&ark_server, file)?);
+    Ok(())
+}
+
+//Load the file again, dropping any edits.
+pub fn reload_ini_editor(state: &mut ProgState) -> Result<(), Error> {
+    let editor = state.ini_editor.as_ref().ok_or(Error::SelectionError)?;
+    let file = editor.file;
+    let ark_server = state.fleet.get_server(editor.server_id)?;
+    state.ini_editor = Some(IniEditor::open(&ark_server, file)?);
+    Ok(())
+}
+
+//Start typing a new value for the selected key, or a new Key=Value entry for the selected section.
+pub fn start_ini_edit(state: &mut ProgState, adding: bool) -> Result<(), Error> {
+    let editor = state.ini_editor.as_mut().ok_or(Error::SelectionError)?;
+    state.tmp_ini_field = if adding {
+        String::new()
+    } else {
+        let entry = editor.selected_entry().ok_or(Error::SelectionError)?;
+        if entry.op == EntryOp::Clear {
+            return Err(Error::ConfigError(format!("!{} clears the array and has no value to edit", entry.key)))
+        }
+        entry.value
+    };
+    editor.adding = adding;
+    state.editing_ini = true;
+    Ok(())
+}
+
+//Apply what was typed to the parsed copy of the file.
+pub fn finish_ini_edit(state: &mut ProgState) -> Result<(), Error> {
+    let editor = state.ini_editor.as_mut().ok_or(Error::SelectionError)?;
+    if !editor.adding {
+        let entry = editor.selected_entry().ok_or(Error::SelectionError)?;
+        editor.doc.set_value(entry.line, &state.tmp_ini_field);
+        return Ok(())
+    }
+    let (op, key, value) = parse_entry(&state.tmp_ini_field)
+        .ok_or_else(|| Error::ValidationError("entry", "expected Key=Value, optionally with a + - . or ! in front".to_string()))?;
+    let section = editor.selected_section().unwrap_or_else(|| editor.file.default_section().to_string());
+    editor.doc.insert(&section, op, &key, &value);
+    let sections = editor.doc.sections();
+    editor.section_state.select(sections.iter().position(|s| s.eq_ignore_ascii_case(&section)));
+    //Entries are listed in file order, and the new one goes after the rest of its section.
+    editor.entry_state.select(Some(editor.doc.entries(&section).len().saturating_sub(1)));
+    editor.entries_focused = true;
+    editor.adding = false;
+    Ok(())
+}
+
+pub fn delete_ini_entry(state: &mut ProgState) -> Result<(), Error> {
+    let editor = state.ini_editor.as_mut().ok_or(Error::SelectionError)?;
+    let entry = editor.selected_entry().ok_or(Error::SelectionError)?;
+    editor.doc.remove(entry.line);
+    if let (Some(section), Some(selected)) = (editor.selected_section(), editor.entry_state.selected()) {
+        let num_entries = editor.doc.entries(&section).len();
+        editor.entry_state.select(Some(selected.min(num_entries.saturating_sub(1))));
+    }
+    Ok(())
+}
+
+//Show the edits made in the Config view as a diff, y in the preview writes them.
+pub fn preview_ini_edits(state: &mut ProgState) -> Result<(), Error> {
+    let editor = state.ini_editor.as_ref().ok_or(Error::SelectionError)?;
+    if !editor.is_modified() {
+        state.notification = Some(format!("No edits to write to {}", editor.file.file_name()));
+        return Ok(())
+    }
+    let after = editor.doc.to_text();
+    state.ini_update = Some(IniUpdate {
+        server_id: editor.server_id,
+        server_name: editor.server_name.clone(),
+        host: editor.host.clone(),
+        path: editor.path.clone(),
+        action: format!("Edit {}", editor.file.file_name()),
+        diff: diff(editor.loaded.as_deref().unwrap_or(""), &after),
+        before: editor.loaded.clone(),
+        after,
+    });
+    Ok(())
+}
+
+//Write the previewed file, unless it was changed by someone else since the preview was made.
+pub fn apply_ini_update(state: &mut ProgState) -> Result<(), Error> {
+    let update = match state.ini_update.take() {
+        Some(update) => update,
+        None => return Ok(()),
+    };
+    if host::read_file(&update.host, &update.path)? != update.before {
+        return Err(Error::ConfigError(format!("{} changed since the preview, nothing was written", update.path.display())))
+    }
+    if let Some(dir) = update.path.parent() {
+        host::create_dir_all(&update.host, dir)?;
+    }
+    host::write_file(&update.host, &update.path, &update.after)?;
+    let entry = AuditEntry {
+        output: Some(diff_text(&update.diff)),
+        ..AuditEntry::new(format!("{} for server {} ({}) to {}", update.action, update.server_name, update.server_id, update.path.display()))
+    };
+    state.audit.record(&entry)?;
+    //The file open in the Config view now matches what is on the server.
+    if let Some(editor) = &mut state.ini_editor {
+        if editor.host == update.host && editor.path == update.path {
+            editor.doc = IniDocument::parse(&update.after);
+            editor.loaded = Some(update.after);
+        }
+    }
+    state.notification = Some(format!("Wrote {}", update.path.display()));
+    Ok(())
+}
+
+//The diff as plain text for the audit log.
+fn diff_text(diff: &[DiffLine]) -> String {
+    diff.iter()
+        .map(|line| match line {
+            DiffLine::Same(text) => format!("  {}", text),
+            DiffLine::Removed(text) => format!("- {}", text),
+            DiffLine::Added(text) => format!("+ {}", text),
+            DiffLine::Skipped(count) => format!("  ... {} unchanged line(s)", count),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("Setting{}={}", i, i)).collect()
+    }
+
+    //The old text is the unchanged and removed lines in order, the new one the unchanged and added lines.
+    fn check_edit_script(old: &[&str], new: &[&str]) -> usize {
+        let lines = diff_lines(old, new);
+        let kept_old: Vec<&str> = lines.iter().filter_map(|line| match line {
+            DiffLine::Same(text) | DiffLine::Removed(text) => Some(text.as_str()),
+            _ => None,
+        }).collect();
+        let kept_new: Vec<&str> = lines.iter().filter_map(|line| match line {
+            DiffLine::Same(text) | DiffLine::Added(text) => Some(text.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(kept_old, old);
+        assert_eq!(kept_new, new);
+        lines.iter().filter(|line| !matches!(line, DiffLine::Same(_))).count()
+    }
+
+    fn lcs_len(old: &[&str], new: &[&str]) -> usize {
+        let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i][j] = if old[i] == new[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+            }
+        }
+        common[0][0]
+    }
+
+    #[test]
+    fn identical_texts_have_no_diff() {
+        assert!(diff("", "").is_empty());
+        assert!(diff("a\nb\n", "a\nb\n").is_empty());
+    }
+
+    #[test]
+    fn change_shows_context_and_skips_the_rest() {
+        let before = numbered(10).join("\n");
+        let after = before.replace("Setting5=5", "Setting5=50");
+        assert_eq!(diff(&before, &after), vec![
+            DiffLine::Skipped(3),
+            DiffLine::Same("Setting3=3".to_string()),
+            DiffLine::Same("Setting4=4".to_string()),
+            DiffLine::Removed("Setting5=5".to_string()),
+            DiffLine::Added("Setting5=50".to_string()),
+            DiffLine::Same("Setting6=6".to_string()),
+            DiffLine::Same("Setting7=7".to_string()),
+            DiffLine::Skipped(2),
+        ]);
+    }
+
+    #[test]
+    fn edit_scripts_are_shortest() {
+        //Small texts over a tiny alphabet, so lines repeat and there are many ways to line them up.
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as usize
+        };
+        let words = ["a", "b", "c", "d"];
+        for _ in 0..500 {
+            let old: Vec<&str> = (0..next() % 9).map(|_| words[next() % words.len()]).collect();
+            let new: Vec<&str> = (0..next() % 9).map(|_| words[next() % words.len()]).collect();
+            let edits = check_edit_script(&old, &new);
+            assert_eq!(edits, old.len() + new.len() - 2 * lcs_len(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn scattered_edits_in_large_file() {
+        let before = numbered(5000);
+        let mut after = before.clone();
+        after[10] = "Setting10=changed".to_string();
+        after.insert(2500, "Inserted=1".to_string());
+        after.remove(4990);
+        let before: Vec<&str> = before.iter().map(String::as_str).collect();
+        let after: Vec<&str> = after.iter().map(String::as_str).collect();
+        assert_eq!(check_edit_script(&before, &after), 4);
+        let shown = diff(&before.join("\n"), &after.join("\n"));
+        assert_eq!(shown.iter().filter(|line| !matches!(line, DiffLine::Same(_) | DiffLine::Skipped(_))).count(), 4);
+    }
+
+    #[test]
+    fn rewritten_file_is_shown_replaced() {
+        let before = numbered(2000);
+        let after: Vec<String> = before.iter().map(|line| format!("{}0", line)).collect();
+        let before: Vec<&str> = before.iter().map(String::as_str).collect();
+        let after: Vec<&str> = after.iter().map(String::as_str).collect();
+        assert_eq!(check_edit_script(&before, &after), 4000);
+    }
+}
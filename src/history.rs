@@ -0,0 +1,285 @@
+use crate::ark::{ArkServer, ArkServerMod, Error};
+use crate::audit::AuditEntry;
+use crate::db::{MemoryStore, ServerStore};
+
+use serde_json::json;
+
+//A reversible change to the fleet.
+//Each variant carries the full records involved, so it can be applied again or inverted without looking anything up.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum Change {
+    InsertServer { index: usize, server: ArkServer },
+    DeleteServer { index: usize, server: ArkServer },
+    UpdateServer { before: ArkServer, after: ArkServer },
+    InsertMod { server_id: usize, index: usize, ark_mod: ArkServerMod },
+    DeleteMod { server_id: usize, index: usize, ark_mod: ArkServerMod },
+    UpdateMod { server_id: usize, before: ArkServerMod, after: ArkServerMod },
+    //A mod taken out of the load order at one index and put back at another.
+    MoveMod { server_id: usize, ark_mod: ArkServerMod, from: usize, to: usize },
+    //Several changes made, undone and audited as one, e.g. an import. Applied in order, or not at all.
+    Batch { label: String, changes: Vec<Change> },
+}
+
+impl Change {
+    pub fn apply(&self, db: &mut MemoryStore) -> Result<(), Error> {
+        match self {
+            Change::InsertServer { index, server } => db.insert_server(*index, server.clone()).map(|_| ()),
+            Change::DeleteServer { server, .. } => db.delete_server(server.id).map(|_| ()),
+            Change::UpdateServer { before, after } => db.update_server(before.id, after.clone()),
+            Change::InsertMod { server_id, index, ark_mod } => db.insert_mod(*server_id, *index, ark_mod.clone()),
+            Change::DeleteMod { server_id, ark_mod, .. } => db.delete_mod(*server_id, ark_mod.id).map(|_| ()),
+            Change::UpdateMod { server_id, before, after } => db.update_mod(*server_id, before.id, after.clone()),
+            Change::MoveMod { server_id, ark_mod, to, .. } => {
+                db.delete_mod(*server_id, ark_mod.id)?;
+                db.insert_mod(*server_id, *to, ark_mod.clone())
+            }
+            //Changes can depend on the ones before them, so work on a copy and only keep it if all of them apply.
+            Change::Batch { changes, .. } => {
+                let mut scratch = db.clone();
+                for change in changes {
+                    change.apply(&mut scratch)?;
+                }
+                *db = scratch;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn inverse(&self) -> Change {
+        match self.clone() {
+            Change::InsertServer { index, server } => Change::DeleteServer { index, server },
+            Change::DeleteServer { index, server } => Change::InsertServer { index, server },
+            Change::UpdateServer { before, after } => Change::UpdateServer { before: after, after: before },
+            Change::InsertMod { server_id, index, ark_mod } => Change::DeleteMod { server_id, index, ark_mod },
+            Change::DeleteMod { server_id, index, ark_mod } => Change::InsertMod { server_id, index, ark_mod },
+            Change::UpdateMod { server_id, before, after } => Change::UpdateMod { server_id, before: after, after: before },
+            Change::MoveMod { server_id, ark_mod, from, to } => Change::MoveMod { server_id, ark_mod, from: to, to: from },
+            //Keeps the label, the undo audit entry already says it was undone.
+            Change::Batch { label, changes } => Change::Batch { label, changes: changes.iter().rev().map(Change::inverse).collect() },
+        }
+    }
+
+    //One line summary for the history panel.
+    pub fn describe(&self) -> String {
+        match self {
+            Change::InsertServer { server, .. } => format!("Add server {}", server.name),
+            Change::DeleteServer { server, .. } => format!("Delete server {}", server.name),
+            Change::UpdateServer { after, .. } => format!("Edit server {}", after.name),
+            Change::InsertMod { server_id, ark_mod, .. } => format!("Add mod {} to server {}", ark_mod.name, server_id),
+            Change::DeleteMod { server_id, ark_mod, .. } => format!("Delete mod {} from server {}", ark_mod.name, server_id),
+            Change::UpdateMod { server_id, before, after } if before.enabled != after.enabled => {
+                let verb = if after.enabled { "Enable" } else { "Disable" };
+                format!("{} mod {} on server {}", verb, after.name, server_id)
+            }
+            Change::UpdateMod { server_id, after, .. } => format!("Edit mod {} on server {}", after.name, server_id),
+            Change::MoveMod { server_id, ark_mod, from, to } => {
+                format!("Move mod {} on server {} from position {} to {}", ark_mod.name, server_id, from + 1, to + 1)
+            }
+            Change::Batch { label, .. } => label.clone(),
+        }
+    }
+
+    pub fn audit_entry(&self) -> AuditEntry {
+        let (before, after) = match self {
+            Change::InsertServer { server, .. } => (None, serde_json::to_value(server).ok()),
+            Change::DeleteServer { server, .. } => (serde_json::to_value(server).ok(), None),
+            Change::UpdateServer { before, after } => (serde_json::to_value(before).ok(), serde_json::to_value(after).ok()),
+            Change::InsertMod { ark_mod, .. } => (None, serde_json::to_value(ark_mod).ok()),
+            Change::DeleteMod { ark_mod, .. } => (serde_json::to_value(ark_mod).ok(), None),
+            Change::UpdateMod { before, after, .. } => (serde_json::to_value(before).ok(), serde_json::to_value(after).ok()),
+            Change::MoveMod { ark_mod, from, to, .. } => (
+                Some(json!({ "id": ark_mod.id, "position": from + 1 })),
+                Some(json!({ "id": ark_mod.id, "position": to + 1 })),
+            ),
+            //One entry for the lot, listing each change with its own before and after.
+            Change::Batch { changes, .. } => {
+                let entries: Vec<AuditEntry> = changes.iter().map(Change::audit_entry).collect();
+                (
+                    Some(entries.iter().map(|entry| json!({ "action": entry.action, "before": entry.before })).collect()),
+                    Some(entries.iter().map(|entry| json!({ "action": entry.action, "after": entry.after })).collect()),
+                )
+            }
+        };
+        AuditEntry { before, after, ..AuditEntry::new(self.describe()) }
+    }
+}
+
+//Undo and redo stacks for the current session. Recording a new change discards anything that was undone.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    pub fn record(&mut self, change: Change) {
+        self.undo.push(change);
+        self.redo.clear();
+    }
+
+    //Revert the most recent change and return what was applied to do so, or None if there is nothing to undo.
+    pub fn undo(&mut self, db: &mut MemoryStore) -> Result<Option<Change>, Error> {
+        match self.undo.pop() {
+            Some(change) => {
+                let inverse = change.inverse();
+                if let Err(e) = inverse.apply(db) {
+                    self.undo.push(change);
+                    return Err(e)
+                }
+                self.redo.push(change);
+                Ok(Some(inverse))
+            }
+            None => Ok(None),
+        }
+    }
+
+    //Apply the most recently undone change again and return it, or None if there is nothing to redo.
+    pub fn redo(&mut self, db: &mut MemoryStore) -> Result<Option<Change>, Error> {
+        match self.redo.pop() {
+            Some(change) => {
+                if let Err(e) = change.apply(db) {
+                    self.redo.push(change);
+                    return Err(e)
+                }
+                self.undo.push(change.clone());
+                Ok(Some(change))
+            }
+            None => Ok(None),
+        }
+    }
+
+    //Newest first, as shown in the history panel.
+    pub fn undo_entries(&self) -> impl Iterator<Item = &Change> {
+        self.undo.iter().rev()
+    }
+
+    //Undone changes, the one furthest from the current state first.
+    pub fn redo_entries(&self) -> impl Iterator<Item = &Change> {
+        self.redo.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    fn ark_mod(id: usize, name: &str) -> ArkServerMod {
+        ArkServerMod { id, ..ArkServerMod::named(name) }
+    }
+
+    fn fleet() -> MemoryStore {
+        let island = ArkServer {
+            id: 1,
+            mods: vec![ark_mod(731604991, "Structures Plus"), ark_mod(751991809, "Death Helper"), ark_mod(889745138, "Awesome Teleporters")],
+            ..ArkServer::named("Island")
+        };
+        MemoryStore::new(vec![island, ArkServer { id: 2, ..ArkServer::named("Ragnarok") }], 3)
+    }
+
+    fn snapshot(db: &MemoryStore) -> Value {
+        serde_json::to_value(db.servers()).expect("serializes")
+    }
+
+    fn every_change(db: &MemoryStore) -> Vec<Change> {
+        let island = db.get_server(1).expect("exists");
+        let ragnarok = db.get_server(2).expect("exists");
+        let death_helper = db.get_mod(1, 751991809).expect("exists");
+        vec![
+            Change::InsertServer { index: 1, server: ArkServer { id: 3, ..ArkServer::named("Valguero") } },
+            Change::DeleteServer { index: 1, server: ragnarok.clone() },
+            Change::UpdateServer { before: ragnarok.clone(), after: ArkServer { name: "Ragnarok PvE".to_string(), ..ragnarok } },
+            Change::InsertMod { server_id: 1, index: 1, ark_mod: ark_mod(1404697612, "Super Spyglass") },
+            Change::DeleteMod { server_id: 1, index: 1, ark_mod: death_helper.clone() },
+            Change::UpdateMod { server_id: 1, before: death_helper.clone(), after: ArkServerMod { enabled: !death_helper.enabled, ..death_helper.clone() } },
+            Change::MoveMod { server_id: 1, ark_mod: island.mods[0].clone(), from: 0, to: 2 },
+            Change::Batch {
+                label: "Import 2 change(s) from fleet.toml".to_string(),
+                changes: vec![
+                    Change::InsertServer { index: 0, server: ArkServer { id: 4, ..ArkServer::named("Extinction") } },
+                    Change::DeleteMod { server_id: 1, index: 0, ark_mod: island.mods[0].clone() },
+                ],
+            },
+        ]
+    }
+
+    #[test]
+    fn inverse_undoes_every_change() {
+        let original = fleet();
+        for change in every_change(&original) {
+            let mut db = original.clone();
+            change.apply(&mut db).expect("applies");
+            assert_ne!(snapshot(&db), snapshot(&original), "{}", change.describe());
+            change.inverse().apply(&mut db).expect("inverse applies");
+            assert_eq!(snapshot(&db), snapshot(&original), "{}", change.describe());
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_history() {
+        let mut db = fleet();
+        let mut history = History::default();
+        let changes = every_change(&db);
+        let mut states = vec![snapshot(&db)];
+        //Every kind of change except the two deletes, whose records the others still use.
+        for change in [0, 2, 3, 5, 6].iter().map(|&i| &changes[i]) {
+            change.apply(&mut db).expect("applies");
+            history.record(change.clone());
+            states.push(snapshot(&db));
+        }
+        for state in states.iter().rev().skip(1) {
+            assert!(history.undo(&mut db).expect("undoes").is_some());
+            assert_eq!(&snapshot(&db), state);
+        }
+        assert!(history.undo(&mut db).expect("nothing to undo").is_none());
+        for state in states.iter().skip(1) {
+            assert!(history.redo(&mut db).expect("redoes").is_some());
+            assert_eq!(&snapshot(&db), state);
+        }
+        assert!(history.redo(&mut db).expect("nothing to redo").is_none());
+    }
+
+    #[test]
+    fn recording_a_change_clears_redo() {
+        let mut db = fleet();
+        let mut history = History::default();
+        let changes = every_change(&db);
+        changes[0].apply(&mut db).expect("applies");
+        history.record(changes[0].clone());
+        history.undo(&mut db).expect("undoes");
+        assert_eq!(history.redo_entries().count(), 1);
+
+        changes[1].apply(&mut db).expect("applies");
+        history.record(changes[1].clone());
+        assert_eq!(history.redo_entries().count(), 0);
+        assert!(history.redo(&mut db).expect("nothing to redo").is_none());
+        assert_eq!(history.undo_entries().map(Change::describe).collect::<Vec<_>>(), ["Delete server Ragnarok"]);
+    }
+
+    #[test]
+    fn failed_undo_keeps_the_change() {
+        let mut db = fleet();
+        let mut history = History::default();
+        history.record(Change::DeleteServer { index: 0, server: db.get_server(1).expect("exists") });
+        //The server is still there, so putting it back clashes with its ID.
+        assert!(matches!(history.undo(&mut db), Err(Error::DuplicateIdError(_))));
+        assert_eq!(history.undo_entries().count(), 1);
+        assert_eq!(history.redo_entries().count(), 0);
+    }
+
+    #[test]
+    fn failed_batch_applies_nothing() {
+        let original = fleet();
+        let mut db = original.clone();
+        let changes = every_change(&original);
+        let batch = Change::Batch {
+            label: "Import 2 change(s) from fleet.toml".to_string(),
+            //The second change clashes with the server the first one added.
+            changes: vec![changes[0].clone(), changes[0].clone()],
+        };
+        assert!(matches!(batch.apply(&mut db), Err(Error::DuplicateIdError(_))));
+        assert_eq!(snapshot(&db), snapshot(&original));
+    }
+}
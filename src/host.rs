@@ -0,0 +1,447 @@
+use crate::ark::Error;
+use crate::db::sibling_path;
+use crate::service::{command_error, describe_command, run_command, spawn_command};
+
+use chrono::prelude::*;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{chown, MetadataExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+//One master connection per host is shared by every command, and kept open for a while after the last one.
+//BatchMode stops ssh from asking for a password, there is no terminal to type it into while the TUI runs.
+const SSH_OPTIONS: [&str; 8] = [
+    "-o", "BatchMode=yes",
+    "-o", "ControlMaster=auto",
+    "-o", "ControlPath=~/.ssh/arkmanager-%C",
+    "-o", "ControlPersist=10m",
+];
+
+//Servers on other machines are reached by running every command and file operation through ssh, so the usual
+//~/.ssh/config applies. An empty host means this machine.
+//ARKMANAGER_SSH replaces the ssh program, e.g. with a script that runs the command locally for testing.
+pub fn on_host(host: &str, command: Command) -> Command {
+    if host.is_empty() {
+        return command
+    }
+    let mut remote = Command::new(ssh_program());
+    remote.args(SSH_OPTIONS).arg(host).arg("--");
+    //ssh hands the rest to the remote shell as one string, so each word has to survive being parsed again.
+    remote.arg(shell_quote(&command.get_program().to_string_lossy()));
+    remote.args(command.get_args().map(|arg| shell_quote(&arg.to_string_lossy())));
+    remote
+}
+
+fn ssh_program() -> OsString {
+    #[cfg(test)]
+    if let Some(ssh) = TEST_SSH.with(|ssh| ssh.borrow().clone()) {
+        return ssh.into_os_string()
+    }
+    env::var_os("ARKMANAGER_SSH").unwrap_or_else(|| "ssh".into())
+}
+
+//Tests point this at a fake ssh for their own thread, rather than setting ARKMANAGER_SSH for the whole process.
+#[cfg(test)]
+thread_local! {
+    static TEST_SSH: std::cell::RefCell<Option<std::path::PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+pub fn shell_quote(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c)) {
+        return word.to_string()
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+//Split a command line into words the way sh would, without expanding anything: 'single' and "double" quotes
+//group words and a backslash escapes the next character. None if a quote is left open.
+pub fn shell_split(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        //Inside double quotes a backslash only escapes the characters that are special there.
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+//A shell snippet run on the host, with the arguments as $1, $2 and so on.
+pub fn script(host: &str, script: &str, args: &[&str]) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script).arg("sh").args(args);
+    on_host(host, command)
+}
+
+//The contents of a file, or None if it does not exist.
+pub fn read_file(host: &str, path: &Path) -> Result<Option<String>, Error> {
+    Ok(read_bytes(host, path)?.map(|content| String::from_utf8_lossy(&content).to_string()))
+}
+
+//The raw contents of a file, or None if it does not exist. For binary files such as a mod's mod.info.
+pub fn read_bytes(host: &str, path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    if host.is_empty() {
+        return match fs::read(path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    let mut command = script(host, r#"if [ -e "$1" ]; then cat -- "$1"; else exit 3; fi"#, &[&path.to_string_lossy()]);
+    let output = spawn_command(&mut command)?;
+    match output.status.code() {
+        Some(0) => Ok(Some(output.stdout)),
+        Some(3) => Ok(None),
+        _ => Err(command_error(&command, &output)),
+    }
+}
+
+//The directories in a directory, each with the total size of the files under it, or None if it does not exist.
+pub fn dir_sizes(host: &str, path: &Path) -> Result<Option<Vec<(String, u64)>>, Error> {
+    if host.is_empty() {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sizes = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                sizes.push((entry.file_name().to_string_lossy().to_string(), disk_usage(&entry.path())?));
+            }
+        }
+        return Ok(Some(sizes))
+    }
+    let mut command = script(
+        host,
+        r#"cd -- "$1" 2>/dev/null || exit 3; for d in */; do [ -d "$d" ] && du -sb -- "$d"; done; exit 0"#,
+        &[&path.to_string_lossy()],
+    );
+    let output = spawn_command(&mut command)?;
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (size, name) = line.split_once('\t')?;
+                Some((name.trim_end_matches('/').to_string(), size.parse().ok()?))
+            })
+            .collect())),
+        Some(3) => Ok(None),
+        _ => Err(command_error(&command, &output)),
+    }
+}
+
+//Apparent size of a local directory and everything under it, not following symlinks. Counts the same as du -sb
+//does on remote hosts.
+fn disk_usage(path: &Path) -> Result<u64, Error> {
+    let mut total = fs::symlink_metadata(path)?.len();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        total += if meta.is_dir() { disk_usage(&entry.path())? } else { meta.len() };
+    }
+    Ok(total)
+}
+
+//Replace a file in one step, so a server never sees it half written. The new file gets the mode and, where
+//allowed, the owner of the one it replaces. Only root can give a file away, anyone else ends up owning it.
+pub fn write_file(host: &str, path: &Path, content: &str) -> Result<(), Error> {
+    let tmp = sibling_path(path, "tmp");
+    if host.is_empty() {
+        fs::write(&tmp, content)?;
+        if let Ok(original) = fs::metadata(path) {
+            fs::set_permissions(&tmp, original.permissions())?;
+            let _ = chown(&tmp, Some(original.uid()), Some(original.gid()));
+        }
+        fs::rename(&tmp, path)?;
+        return Ok(())
+    }
+    let mut command = script(
+        host,
+        r#"cat > "$2" && { [ ! -e "$1" ] || { chown --reference="$1" -- "$2" 2>/dev/null; chmod --reference="$1" -- "$2"; }; } && mv -- "$2" "$1""#,
+        &[&path.to_string_lossy(), &tmp.to_string_lossy()],
+    );
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| Error::SpawnError { command: describe_command(&command), source })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(command_error(&command, &output))
+    }
+    Ok(())
+}
+
+pub fn remove_file(host: &str, path: &Path) -> Result<(), Error> {
+    if host.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+    run_command(&mut script(host, r#"rm -f -- "$1""#, &[&path.to_string_lossy()])).map(|_| ())
+}
+
+pub fn create_dir_all(host: &str, path: &Path) -> Result<(), Error> {
+    if host.is_empty() {
+        return Ok(fs::create_dir_all(path)?)
+    }
+    run_command(&mut script(host, r#"mkdir -p -- "$1""#, &[&path.to_string_lossy()])).map(|_| ())
+}
+
+pub fn rename(host: &str, from: &Path, to: &Path) -> Result<(), Error> {
+    if host.is_empty() {
+        return Ok(fs::rename(from, to)?)
+    }
+    run_command(&mut script(host, r#"mv -- "$1" "$2""#, &[&from.to_string_lossy(), &to.to_string_lossy()])).map(|_| ())
+}
+
+//Copy a file and then empty the original in place, for logs a process still has open.
+pub fn copy_truncate(host: &str, from: &Path, to: &Path) -> Result<(), Error> {
+    if host.is_empty() {
+        fs::copy(from, to)?;
+        fs::File::create(from)?;
+        return Ok(())
+    }
+    run_command(&mut script(host, r#"cp -- "$1" "$2" && : > "$1""#, &[&from.to_string_lossy(), &to.to_string_lossy()])).map(|_| ())
+}
+
+//Size and modification time of a file, or None if it does not exist.
+pub fn metadata(host: &str, path: &Path) -> Result<Option<(u64, DateTime<Utc>)>, Error> {
+    if host.is_empty() {
+        return match fs::metadata(path) {
+            Ok(meta) => Ok(Some((meta.len(), DateTime::<Utc>::from(meta.modified()?)))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    let mut command = script(host, r#"if [ -e "$1" ]; then stat -c '%s %Y' -- "$1"; else exit 3; fi"#, &[&path.to_string_lossy()]);
+    let output = spawn_command(&mut command)?;
+    if output.status.code() == Some(3) {
+        return Ok(None)
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.split_whitespace().map(|field| field.parse::<i64>().ok());
+    match (fields.next().flatten(), fields.next().flatten()) {
+        (Some(size), Some(mtime)) => Ok(Utc.timestamp_opt(mtime, 0).single().map(|mtime| (size as u64, mtime))),
+        _ => Err(command_error(&command, &output)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::sync::Once;
+
+    //Stands in for ssh: drops the options and host and runs the command line here, through a shell like sshd does.
+    const FAKE_SSH: &str = r#"#!/bin/sh
+while [ "$1" = -o ]; do shift 2; done
+shift
+[ "$1" = -- ] && shift
+exec sh -c "$*"
+"#;
+
+    const HOST: &str = "remote.example";
+
+    static FAKE_SSH_WRITTEN: Once = Once::new();
+
+    //Run this thread's remote commands through FAKE_SSH.
+    fn fake_ssh() {
+        let script = env::temp_dir().join(format!("ark-manager-host {}-ssh", std::process::id())).join("ssh");
+        FAKE_SSH_WRITTEN.call_once(|| {
+            test_dir("ssh");
+            fs::write(&script, FAKE_SSH).expect("can write fake ssh");
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("can make fake ssh executable");
+        });
+        TEST_SSH.with(|ssh| *ssh.borrow_mut() = Some(script));
+    }
+
+    //A fresh directory per test, with a space in its name to catch missing quotes.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ark-manager-host {}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("can create test dir");
+        dir
+    }
+
+    fn args(command: &Command) -> Vec<String> {
+        command.get_args().map(|arg| arg.to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn shell_quote_leaves_plain_words() {
+        assert_eq!(shell_quote("ShooterGameServer"), "ShooterGameServer");
+        assert_eq!(shell_quote("/srv/ark/ShooterGame/Saved"), "/srv/ark/ShooterGame/Saved");
+        assert_eq!(shell_quote("-Port=7777"), "-Port=7777");
+    }
+
+    #[test]
+    fn shell_quote_quotes_special_words() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("My Server"), "'My Server'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn shell_split_words() {
+        let split = |line| shell_split(line).expect("quotes are closed");
+        assert_eq!(split("  -Port=7777   -NoBattlEye "), ["-Port=7777", "-NoBattlEye"]);
+        assert_eq!(split(""), Vec::<String>::new());
+        assert_eq!(split("TheIsland?SessionName='My Server'?Port=7777"), ["TheIsland?SessionName=My Server?Port=7777"]);
+        assert_eq!(split(r#""SessionName=My Server" -log"#), ["SessionName=My Server", "-log"]);
+        assert_eq!(split(r#"a\ b "c\"d\n" 'e\f' '' """#), ["a b", "c\"d\\n", "e\\f", "", ""]);
+    }
+
+    #[test]
+    fn shell_split_rejects_open_quotes() {
+        assert_eq!(shell_split("SessionName='My Server"), None);
+        assert_eq!(shell_split(r#""SessionName=My Server"#), None);
+    }
+
+    #[test]
+    fn shell_split_undoes_shell_quote() {
+        let words = ["My Server", "it's", "$HOME", "", "-Port=7777"];
+        let line = words.iter().map(|word| shell_quote(word)).collect::<Vec<_>>().join(" ");
+        assert_eq!(shell_split(&line).expect("quotes are closed"), words);
+    }
+
+    #[test]
+    fn on_host_leaves_local_commands() {
+        let mut command = Command::new("systemctl");
+        command.arg("start").arg("ark server");
+        let command = on_host("", command);
+        assert_eq!(command.get_program(), "systemctl");
+        assert_eq!(args(&command), vec!["start", "ark server"]);
+    }
+
+    #[test]
+    fn on_host_quotes_remote_words() {
+        let mut command = Command::new("systemctl");
+        command.arg("start").arg("ark server");
+        let args = args(&on_host(HOST, command));
+        assert_eq!(args[..SSH_OPTIONS.len()], SSH_OPTIONS);
+        assert_eq!(args[SSH_OPTIONS.len()..], ["remote.example", "--", "systemctl", "start", "'ark server'"]);
+    }
+
+    #[test]
+    fn on_host_words_survive_remote_shell() {
+        fake_ssh();
+        let words = ["a b", "it's", "$HOME", "", "*", "x;y"];
+        let mut command = Command::new("printf");
+        command.arg("%s\\n").args(words);
+        let output = run_command(&mut on_host(HOST, command)).expect("runs");
+        assert_eq!(output.lines().collect::<Vec<_>>(), words);
+    }
+
+    #[test]
+    fn read_bytes_remote() {
+        fake_ssh();
+        let dir = test_dir("read");
+        let path = dir.join("mod.info");
+        fs::write(&path, b"\x05\x00\x00\x00Test\x00").expect("can write file");
+        assert_eq!(read_bytes(HOST, &path).expect("reads"), Some(b"\x05\x00\x00\x00Test\x00".to_vec()));
+        assert_eq!(read_bytes(HOST, &dir.join("missing")).expect("reads"), None);
+        assert_eq!(read_file(HOST, &dir.join("missing")).expect("reads"), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn metadata_remote() {
+        fake_ssh();
+        let dir = test_dir("metadata");
+        let path = dir.join("Game.ini");
+        fs::write(&path, "[/script/shootergame.shootergamemode]\n").expect("can write file");
+        let (size, modified) = metadata(HOST, &path).expect("reads").expect("exists");
+        let local = metadata("", &path).expect("reads").expect("exists");
+        assert_eq!(size, 38);
+        assert_eq!(modified.timestamp(), local.1.timestamp());
+        assert_eq!(metadata(HOST, &dir.join("missing")).expect("reads"), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dir_sizes_remote_matches_local() {
+        fake_ssh();
+        let dir = test_dir("sizes");
+        fs::create_dir_all(dir.join("731604991").join("Content")).expect("can create mod dir");
+        fs::write(dir.join("731604991").join("mod.info"), vec![0; 100]).expect("can write file");
+        fs::write(dir.join("731604991").join("Content").join("a.uasset"), vec![0; 1000]).expect("can write file");
+        fs::create_dir_all(dir.join("Empty Mod")).expect("can create mod dir");
+        fs::write(dir.join("not a dir"), "x").expect("can write file");
+
+        let mut remote = dir_sizes(HOST, &dir).expect("reads").expect("exists");
+        let mut local = dir_sizes("", &dir).expect("reads").expect("exists");
+        remote.sort();
+        local.sort();
+        assert_eq!(remote, local);
+        assert_eq!(remote.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["731604991", "Empty Mod"]);
+        assert!(remote[0].1 >= 1100);
+        assert_eq!(dir_sizes(HOST, &dir.join("missing")).expect("reads"), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn write_file_keeps_mode() {
+        fake_ssh();
+        let dir = test_dir("write");
+        for host in ["", HOST] {
+            let path = dir.join(format!("GameUserSettings{}.ini", host.len()));
+            fs::write(&path, "[ServerSettings]\n").expect("can write file");
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).expect("can set mode");
+            write_file(host, &path, "[ServerSettings]\nServerPVE=True\n").expect("writes");
+            assert_eq!(fs::read_to_string(&path).expect("can read file"), "[ServerSettings]\nServerPVE=True\n");
+            assert_eq!(fs::metadata(&path).expect("exists").permissions().mode() & 0o777, 0o640, "host {:?}", host);
+            assert!(!sibling_path(&path, "tmp").exists());
+        }
+        write_file(HOST, &dir.join("new.ini"), "x").expect("writes a new file");
+        assert_eq!(fs::read_to_string(dir.join("new.ini")).expect("can read file"), "x");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,405 @@
+//Unreal Engine style INI files as ARK reads them: case insensitive section and key names, keys that repeat,
+//and array operators in front of keys, e.g. +ConfigOverrideItemMaxQuantity=(...) adds an entry to an array.
+//Every line is kept as it was read, so a file that is parsed and written back without edits is unchanged byte
+//for byte, and an edit only rewrites the lines it touches.
+
+//What the prefix of a key asks the engine to do with the value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EntryOp {
+    //Key=Value replaces the value.
+    Set,
+    //+Key=Value adds the value to an array unless it is already in it.
+    Add,
+    //.Key=Value adds the value to an array even if it is already in it.
+    AddDuplicate,
+    //-Key=Value removes the value from an array.
+    Remove,
+    //Clears the array, written as !Key.
+    Clear,
+}
+
+impl EntryOp {
+    fn from_prefix(c: char) -> Option<EntryOp> {
+        match c {
+            '+' => Some(EntryOp::Add),
+            '.' => Some(EntryOp::AddDuplicate),
+            '-' => Some(EntryOp::Remove),
+            '!' => Some(EntryOp::Clear),
+            _ => None,
+        }
+    }
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            EntryOp::Set => "",
+            EntryOp::Add => "+",
+            EntryOp::AddDuplicate => ".",
+            EntryOp::Remove => "-",
+            EntryOp::Clear => "!",
+        }
+    }
+}
+
+//A key and its value, and the line it was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub line: usize,
+    pub op: EntryOp,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum LineKind {
+    //Blank lines, comments and anything else that is not understood, kept for writing back.
+    Other,
+    Section(String),
+    //value_start is where the value begins in the raw line, so editing it keeps the spacing around the =.
+    Entry { op: EntryOp, key: String, value_start: usize },
+}
+
+#[derive(Clone, Debug)]
+struct Line {
+    raw: String,
+    kind: LineKind,
+    //"\r\n" or "\n" as read, so files that mix them are written back the same. Empty on a last line without one.
+    ending: &'static str,
+}
+
+#[derive(Clone, Debug)]
+pub struct IniDocument {
+    lines: Vec<Line>,
+    //Ending for added lines, the one the file's first line uses.
+    newline: &'static str,
+    bom: bool,
+}
+
+impl IniDocument {
+    pub fn parse(content: &str) -> IniDocument {
+        let bom = content.starts_with('\u{feff}');
+        let content = content.trim_start_matches('\u{feff}');
+        let lines: Vec<Line> = content
+            .split_inclusive('\n')
+            .map(|text| {
+                let (raw, ending) = match text.strip_suffix('\n') {
+                    Some(raw) => match raw.strip_suffix('\r') {
+                        Some(raw) => (raw, "\r\n"),
+                        None => (raw, "\n"),
+                    },
+                    None => (text, ""),
+                };
+                Line { ending, ..parse_line(raw) }
+            })
+            .collect();
+        IniDocument {
+            newline: lines.first().map(|line| line.ending).filter(|ending| !ending.is_empty()).unwrap_or("\n"),
+            lines,
+            bom,
+        }
+    }
+
+    //The file as it should be written back.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if self.bom {
+            text.push('\u{feff}');
+        }
+        for line in &self.lines {
+            text += &line.raw;
+            text += line.ending;
+        }
+        text
+    }
+
+    //Section names in the order they first appear. A section split over several blocks is listed once, spelled
+    //as in its first block. Settings above the first section header are listed under "".
+    pub fn sections(&self) -> Vec<String> {
+        let mut sections: Vec<String> = Vec::new();
+        for line in &self.lines {
+            match &line.kind {
+                LineKind::Section(name) if !sections.iter().any(|s| s.eq_ignore_ascii_case(name)) => sections.push(name.clone()),
+                LineKind::Entry { .. } if sections.is_empty() => sections.push(String::new()),
+                _ => {}
+            }
+        }
+        sections
+    }
+
+    //Every entry of the section, in file order, including repeated keys.
+    pub fn entries(&self, section: &str) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut current = "";
+        for (i, line) in self.lines.iter().enumerate() {
+            match &line.kind {
+                LineKind::Section(name) => current = name,
+                LineKind::Entry { op, key, value_start } if current.eq_ignore_ascii_case(section) => entries.push(Entry {
+                    line: i,
+                    op: *op,
+                    key: key.clone(),
+                    value: line.raw[*value_start..].to_string(),
+                }),
+                _ => {}
+            }
+        }
+        entries
+    }
+
+    //The values a key ends up with, following the array operators in order. Usually one, more for arrays.
+    pub fn get(&self, section: &str, key: &str) -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+        for entry in self.entries(section).into_iter().filter(|e| e.key.eq_ignore_ascii_case(key)) {
+            match entry.op {
+                EntryOp::Set => values = vec![entry.value],
+                EntryOp::Add if values.contains(&entry.value) => {}
+                EntryOp::Add | EntryOp::AddDuplicate => values.push(entry.value),
+                EntryOp::Remove => values.retain(|v| *v != entry.value),
+                EntryOp::Clear => values.clear(),
+            }
+        }
+        values
+    }
+
+    //Give a single valued key a new value. The first plain Key= line is rewritten and any repeats of it are
+    //dropped. A missing key goes after the last setting of its section, a missing section at the end of the file.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let existing: Vec<usize> = self.entries(section)
+            .into_iter()
+            .filter(|e| e.op == EntryOp::Set && e.key.eq_ignore_ascii_case(key))
+            .map(|e| e.line)
+            .collect();
+        match existing.split_first() {
+            Some((&first, rest)) => {
+                self.set_value(first, value);
+                for &line in rest.iter().rev() {
+                    self.lines.remove(line);
+                }
+            }
+            None => self.insert(section, EntryOp::Set, key, value),
+        }
+    }
+
+    //Replace the value on an entry's line, keeping its key exactly as written. !Key lines have no value.
+    pub fn set_value(&mut self, line: usize, value: &str) {
+        if let Some(Line { raw, kind: LineKind::Entry { op, value_start, .. }, .. }) = self.lines.get_mut(line) {
+            if *op == EntryOp::Clear {
+                return
+            }
+            raw.truncate(*value_start);
+            raw.push_str(value);
+        }
+    }
+
+    //Add an entry after the last setting of the section, even if the key is already there.
+    pub fn insert(&mut self, section: &str, op: EntryOp, key: &str, value: &str) {
+        let raw = format!("{}{}={}", op.prefix(), key, value);
+        let mut current = "";
+        let mut last_in_section = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            match &line.kind {
+                LineKind::Section(name) => {
+                    current = name;
+                    if current.eq_ignore_ascii_case(section) {
+                        last_in_section = Some(i);
+                    }
+                }
+                LineKind::Entry { .. } if current.eq_ignore_ascii_case(section) => last_in_section = Some(i),
+                _ => {}
+            }
+        }
+        let at = match last_in_section {
+            Some(i) => i + 1,
+            None if section.is_empty() => 0,
+            None => {
+                if self.lines.last().is_some_and(|line| !line.raw.trim().is_empty()) {
+                    self.push_line("");
+                }
+                self.push_line(&format!("[{}]", section));
+                self.lines.len()
+            }
+        };
+        if at == self.lines.len() {
+            self.push_line(&raw);
+        } else {
+            self.lines.insert(at, Line { ending: self.newline, ..parse_line(&raw) });
+        }
+    }
+
+    //Removing the last line leaves the one before it last, with the ending the file had, or lacked, at the end.
+    pub fn remove(&mut self, line: usize) {
+        if line < self.lines.len() {
+            let removed = self.lines.remove(line);
+            if line == self.lines.len() {
+                if let Some(last) = self.lines.last_mut() {
+                    last.ending = removed.ending;
+                }
+            }
+        }
+    }
+
+    //Add a line at the end of the file, ending the previous last line if it had no ending.
+    fn push_line(&mut self, raw: &str) {
+        let newline = self.newline;
+        let ending = match self.lines.last_mut() {
+            Some(last) if last.ending.is_empty() => {
+                last.ending = newline;
+                ""
+            }
+            _ => newline,
+        };
+        self.lines.push(Line { ending, ..parse_line(raw) });
+    }
+}
+
+//Split key=value text typed by the user, e.g. "+ConfigOverrideItemMaxQuantity=(...)", into its parts.
+pub fn parse_entry(text: &str) -> Option<(EntryOp, String, String)> {
+    match parse_line(text).kind {
+        LineKind::Entry { op, key, value_start } => Some((op, key, text[value_start..].to_string())),
+        _ => None,
+    }
+}
+
+fn parse_line(raw: &str) -> Line {
+    let trimmed = raw.trim();
+    let kind = if trimmed.starts_with('[') && trimmed.ends_with(']') && trimmed.len() > 1 {
+        LineKind::Section(trimmed[1..trimmed.len() - 1].trim().to_string())
+    } else if trimmed.starts_with(';') || trimmed.starts_with('#') {
+        LineKind::Other
+    } else {
+        match raw.find('=') {
+            Some(eq) => {
+                let mut key = raw[..eq].trim();
+                let op = match key.chars().next().and_then(EntryOp::from_prefix) {
+                    Some(op) => {
+                        key = key[1..].trim_start();
+                        op
+                    }
+                    None => EntryOp::Set,
+                };
+                let value_start = eq + 1 + (raw[eq + 1..].len() - raw[eq + 1..].trim_start().len());
+                match key.is_empty() {
+                    true => LineKind::Other,
+                    false => LineKind::Entry { op, key: key.to_string(), value_start },
+                }
+            }
+            //A bare !Key clears an array.
+            None if trimmed.starts_with('!') && trimmed.len() > 1 => LineKind::Entry {
+                op: EntryOp::Clear,
+                key: trimmed[1..].trim().to_string(),
+                value_start: raw.len(),
+            },
+            None => LineKind::Other,
+        }
+    };
+    Line { raw: raw.to_string(), kind, ending: "" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME_USER_SETTINGS: &str = "[ServerSettings]\nServerPassword=\nDifficultyOffset=0.5\n\n[/Script/ShooterGame.ShooterGameUserSettings]\nActiveMods=731604991,889745138\n";
+
+    fn round_trip(content: &str) {
+        assert_eq!(IniDocument::parse(content).to_text(), content);
+    }
+
+    #[test]
+    fn round_trips_lf() {
+        round_trip(GAME_USER_SETTINGS);
+    }
+
+    #[test]
+    fn round_trips_crlf() {
+        round_trip(&GAME_USER_SETTINGS.replace('\n', "\r\n"));
+    }
+
+    #[test]
+    fn round_trips_mixed_endings() {
+        round_trip("[ServerSettings]\r\nServerPassword=\nDifficultyOffset=0.5\r\n\n[SessionSettings]\nSessionName=My Server\r\n");
+    }
+
+    #[test]
+    fn round_trips_bom() {
+        let content = format!("\u{feff}{}", GAME_USER_SETTINGS);
+        let doc = IniDocument::parse(&content);
+        assert_eq!(doc.sections()[0], "ServerSettings");
+        assert_eq!(doc.to_text(), content);
+    }
+
+    #[test]
+    fn round_trips_missing_trailing_newline() {
+        round_trip(GAME_USER_SETTINGS.trim_end());
+        round_trip("");
+        round_trip("\n");
+    }
+
+    #[test]
+    fn round_trips_odd_lines() {
+        round_trip("; comment\n  [ ServerSettings ]  \n  Key = Value  \n=no key\nnot a setting\n# also a comment\n");
+    }
+
+    #[test]
+    fn edits_keep_each_line_ending() {
+        let mut doc = IniDocument::parse("[ServerSettings]\r\nDifficultyOffset=0.5\nServerPassword=\r\n");
+        doc.set("ServerSettings", "DifficultyOffset", "1.0");
+        doc.set("ServerSettings", "MaxPlayers", "70");
+        assert_eq!(doc.to_text(), "[ServerSettings]\r\nDifficultyOffset=1.0\nServerPassword=\r\nMaxPlayers=70\r\n");
+    }
+
+    #[test]
+    fn appending_ends_last_line_without_newline() {
+        let mut doc = IniDocument::parse("[ServerSettings]\nServerPassword=");
+        doc.set("SessionSettings", "SessionName", "My Server");
+        assert_eq!(doc.to_text(), "[ServerSettings]\nServerPassword=\n\n[SessionSettings]\nSessionName=My Server");
+        doc.remove(4);
+        assert_eq!(doc.to_text(), "[ServerSettings]\nServerPassword=\n\n[SessionSettings]");
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let content = "[ServerSettings]\nDifficultyOffset=0.5\nDifficultyOffset=1.0\n[serversettings]\nDifficultyOffset=2.0\n";
+        let mut doc = IniDocument::parse(content);
+        assert_eq!(doc.to_text(), content);
+        assert_eq!(doc.sections(), ["ServerSettings"]);
+        assert_eq!(doc.entries("ServerSettings").len(), 3);
+        assert_eq!(doc.get("ServerSettings", "difficultyoffset"), ["2.0"]);
+        doc.set("ServerSettings", "DifficultyOffset", "3.0");
+        assert_eq!(doc.to_text(), "[ServerSettings]\nDifficultyOffset=3.0\n[serversettings]\n");
+    }
+
+    #[test]
+    fn array_operators() {
+        let content = "[/script/shootergame.shootergamemode]\n\
+            +ConfigOverrideItemMaxQuantity=(ItemClassString=\"PrimalItemResource_Stone_C\")\n\
+            .ConfigOverrideItemMaxQuantity=(ItemClassString=\"PrimalItemResource_Stone_C\")\n\
+            +OverrideNamedEngramEntries=A\n\
+            +OverrideNamedEngramEntries=A\n\
+            +OverrideNamedEngramEntries=B\n\
+            -OverrideNamedEngramEntries=A\n\
+            !ConfigAddNPCSpawnEntriesContainer\n\
+            +ConfigAddNPCSpawnEntriesContainer=(NPCSpawnEntriesContainerClassString=\"DinoSpawnEntriesBeach_C\")\n";
+        let doc = IniDocument::parse(content);
+        assert_eq!(doc.to_text(), content);
+        let section = "/Script/ShooterGame.ShooterGameMode";
+        let ops: Vec<EntryOp> = doc.entries(section).iter().map(|e| e.op).collect();
+        assert_eq!(ops, [EntryOp::Add, EntryOp::AddDuplicate, EntryOp::Add, EntryOp::Add, EntryOp::Add, EntryOp::Remove, EntryOp::Clear, EntryOp::Add]);
+        assert_eq!(doc.get(section, "ConfigOverrideItemMaxQuantity").len(), 2);
+        assert_eq!(doc.get(section, "OverrideNamedEngramEntries"), ["B"]);
+        assert_eq!(doc.get(section, "ConfigAddNPCSpawnEntriesContainer"), ["(NPCSpawnEntriesContainerClassString=\"DinoSpawnEntriesBeach_C\")"]);
+    }
+
+    #[test]
+    fn set_value_keeps_spacing_and_prefix() {
+        let mut doc = IniDocument::parse("[S]\n+Key =  old\n!Cleared\n");
+        doc.set_value(1, "new");
+        doc.set_value(2, "ignored");
+        assert_eq!(doc.to_text(), "[S]\n+Key =  new\n!Cleared\n");
+    }
+
+    #[test]
+    fn parse_entry_splits_prefix() {
+        assert_eq!(parse_entry("+Key=(A=1)"), Some((EntryOp::Add, "Key".to_string(), "(A=1)".to_string())));
+        assert_eq!(parse_entry("!Key"), Some((EntryOp::Clear, "Key".to_string(), String::new())));
+        assert_eq!(parse_entry("no equals sign"), None);
+    }
+}
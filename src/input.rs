@@ -1,23 +1,95 @@
-use crate::ark::{ProgState, ArkServerMod, Error, MenuItem, Event, InputEvent};
-use crate::service::{start_ark_server, restart_ark_server, stop_ark_server,status_ark_server};
-use crate::db::{get_server, get_server_mod, get_server_mod_properties, get_server_mod_property, get_num_servers, get_num_server_mods, add_ark_server_mod_to_db, remove_ark_server_mod_at_index, get_servers, add_ark_server_to_db, remove_ark_server_at_index, set_server_mod_property, get_server_property, set_server_property};
+use crate::ark::{ProgState, Error, MenuItem, Event, InputEvent};
+use crate::audit::filter_entries;
+use crate::gameini::{apply_ini_update, delete_ini_entry, finish_ini_edit, open_ini_editor, preview_active_mods, preview_ini_edits, reload_ini_editor, start_ini_edit, switch_ini_file};
+use crate::workshop::{finish_mod_scan, scan_mods};
+use crate::bulk::{advance_bulk, record_bulk_outcome, start_bulk};
+use crate::logtail::{follow_recent_logs, jump_to_match, open_logs, scroll_logs};
+use crate::service::{dispatch_service_action, finish_service_action, ServiceAction};
+use crate::db::{undo, redo, switch_profile, get_server, server_display_order, toggle_server_mod, set_all_server_mods_enabled, move_server_mod, selected_server_id, selected_mod_id, get_server_mod_property, get_num_servers, get_num_server_mods, add_ark_server_mod_to_db, remove_ark_server_mod, add_ark_server_to_db, remove_ark_server, set_server_mod_property, get_server_property, set_server_property};
 use tui::{ widgets::{ListState, TableState} };
-use crossterm::{ event::{KeyCode} };
+use crossterm::{ event::{KeyCode, KeyModifiers} };
 
 //Process user input
 pub fn process_input(state: &mut ProgState, input: Event<crossterm::event::KeyEvent>) -> Result<InputEvent, Error> {
+    let input = match input {
+        Event::ServiceDone(outcome) => {
+            if record_bulk_outcome(state, &outcome) {
+                //Failures in a bulk run are listed in its summary instead.
+                let _ = finish_service_action(state, *outcome);
+            } else {
+                notify_on_error(state, |state| finish_service_action(state, *outcome));
+            }
+            advance_bulk(state);
+            return Ok(InputEvent::Other)
+        }
+        Event::ModScanDone(outcome) => {
+            notify_on_error(state, |state| finish_mod_scan(state, *outcome));
+            return Ok(InputEvent::Other)
+        }
+        Event::Tick => {
+            state.spinner_frame = state.spinner_frame.wrapping_add(1);
+            advance_bulk(state);
+            state.status_poller.watch(&state.backends, state.fleet.servers());
+            Event::Tick
+        }
+        Event::Input(event) => {
+            state.notification = None;
+            if event.code != KeyCode::Char('q') {
+                state.confirm_quit = false;
+            }
+            //Any key dismisses the summary of a finished bulk action.
+            if state.bulk_summary.take().is_some() {
+                return Ok(InputEvent::Other)
+            }
+            //y writes the previewed INI file, any other key drops it.
+            if state.ini_update.is_some() {
+                if event.code == KeyCode::Char('y') {
+                    notify_on_error(state, apply_ini_update);
+                } else {
+                    state.ini_update = None;
+                    state.notification = Some("Nothing was written".to_string());
+                }
+                return Ok(InputEvent::Other)
+            }
+            Event::Input(event)
+        }
+    };
     if state.editing_server {
         process_server_edits(state, input).expect("Server edit processed");
     } else if state.editing_mod {
         process_mod_edits(state, input).expect("Mod edit processed");
+    } else if state.editing_audit_filter {
+        process_audit_filter_edits(state, input).expect("Audit filter edit processed");
+    } else if state.editing_log_search {
+        process_log_search_edits(state, input).expect("Log search edit processed");
+    } else if state.editing_ini {
+        process_ini_edits(state, input).expect("INI edit processed");
     } else {
         match input {
             Event::Input(event) => match event.code {
+                //Quitting abandons anything still running, so ask first.
+                KeyCode::Char('q') if (!state.pending_actions.is_empty() || state.bulk.is_some() || unsaved_ini_edits(state)) && !state.confirm_quit => {
+                    state.confirm_quit = true;
+                    state.notification = Some(match (&state.bulk, &state.ini_editor) {
+                        (Some(bulk), _) => format!("{} still running, press q again to quit anyway", bulk.progress()),
+                        _ if !state.pending_actions.is_empty() => format!(
+                            "{} service action(s) still running, press q again to quit anyway",
+                            state.pending_actions.len()
+                        ),
+                        (None, Some(editor)) => format!(
+                            "{} has unsaved edits, press q again to quit anyway",
+                            editor.file.file_name()
+                        ),
+                        (None, None) => "press q again to quit".to_string(),
+                    });
+                }
                 KeyCode::Char('q') => {
                     return Ok(InputEvent::Exit);
                 }
                 KeyCode::Char('h') => state.active_menu_item = MenuItem::Home,
                 KeyCode::Char('l') => state.active_menu_item = MenuItem::Servers,
+                KeyCode::Char('u') => notify_on_error(state, undo),
+                KeyCode::Char('r') if event.modifiers.contains(KeyModifiers::CONTROL) => notify_on_error(state, redo),
                 _ => {
                     match state.active_menu_item {
                         MenuItem::Home => process_home(state, event).expect("Processed home"),
@@ -27,12 +99,20 @@ pub fn process_input(state: &mut ProgState, input: Event<crossterm::event::KeyEv
                         MenuItem::EditServer => process_edit_server(state, event).expect("Processed edit server"),
                         MenuItem::ServerMods => process_server_mods(state, event).expect("Processed server mods"),
                         MenuItem::Servers => process_servers(state, event).expect("Processed servers"),
+                        MenuItem::Audit => process_audit(state, event).expect("Processed audit"),
+                        MenuItem::Logs => process_logs(state, event).expect("Processed logs"),
+                        MenuItem::GameConfig => process_game_config(state, event).expect("Processed game config"),
                     }
                 }
             },
-            Event::Tick => {}
+            Event::Tick | Event::ServiceDone(_) | Event::ModScanDone(_) => {}
         }
     }
+    //Stop following the log once its view is left, whichever key left it.
+    if !matches!(state.active_menu_item, MenuItem::Logs) {
+        state.log_tail = None;
+    }
+    follow_recent_logs(state);
     Ok(InputEvent::Other)
 }
 
@@ -40,17 +120,27 @@ pub fn process_server_edits(state: &mut ProgState, input: Event<crossterm::event
     match input {
         Event::Input(event) => match event.code {
             KeyCode::Enter => {
-                set_server_property(state).expect("Property set successfully");
+                let server_id = selected_server_id(state)?;
+                match set_server_property(state, server_id) {
+                    Ok(()) => {
+                        state.edit_error = None;
+                        state.editing_server = false;
+                    }
+                    Err(e) => state.edit_error = Some(e.to_string()),
+                }
+            }
+            KeyCode::Esc => {
+                state.edit_error = None;
                 state.editing_server = false;
             }
             KeyCode::Backspace => {
                 state.tmp_server_field.pop();
             }
             _ => {
-                state.tmp_server_field += get_input_char(event.code);
+                state.tmp_server_field += &get_input_char(event.code);
             }
         },
-        Event::Tick => {}
+        Event::Tick | Event::ServiceDone(_) | Event::ModScanDone(_) => {}
     }
     Ok(InputEvent::Other)
 }
@@ -59,23 +149,231 @@ pub fn process_mod_edits(state: &mut ProgState, input: Event<crossterm::event::K
     match input {
         Event::Input(event) => match event.code {
             KeyCode::Enter => {
-                set_server_mod_property(state).expect("Property set successfully");
+                let server_id = selected_server_id(state)?;
+                let mod_id = selected_mod_id(state)?;
+                match set_server_mod_property(state, server_id, mod_id) {
+                    Ok(()) => {
+                        state.edit_error = None;
+                        state.editing_mod = false;
+                    }
+                    Err(e) => state.edit_error = Some(e.to_string()),
+                }
+            }
+            KeyCode::Esc => {
+                state.edit_error = None;
                 state.editing_mod = false;
             }
             KeyCode::Backspace => {
                 state.tmp_mod_field.pop();
             }
             _ => {
-                state.tmp_mod_field += get_input_char(event.code);
+                state.tmp_mod_field += &get_input_char(event.code);
+            }
+        },
+        Event::Tick | Event::ServiceDone(_) | Event::ModScanDone(_) => {}
+    }
+    Ok(InputEvent::Other)
+}
+
+pub fn process_audit_filter_edits(state: &mut ProgState, input: Event<crossterm::event::KeyEvent>) -> Result<InputEvent, Error> {
+    match input {
+        Event::Input(event) => match event.code {
+            KeyCode::Enter | KeyCode::Esc => {
+                state.editing_audit_filter = false;
+            }
+            KeyCode::Backspace => {
+                state.audit_filter.pop();
+                state.audit_list_state.select(Some(0));
+            }
+            _ => {
+                state.audit_filter += &get_input_char(event.code);
+                state.audit_list_state.select(Some(0));
             }
         },
-        Event::Tick => {}
+        Event::Tick | Event::ServiceDone(_) | Event::ModScanDone(_) => {}
     }
     Ok(InputEvent::Other)
 }
 
+pub fn process_log_search_edits(state: &mut ProgState, input: Event<crossterm::event::KeyEvent>) -> Result<InputEvent, Error> {
+    match input {
+        Event::Input(event) => match event.code {
+            KeyCode::Enter => {
+                state.editing_log_search = false;
+                state.log_match = None;
+                jump_to_match(state, false);
+            }
+            KeyCode::Esc => {
+                state.editing_log_search = false;
+                state.log_search.clear();
+                state.log_match = None;
+            }
+            KeyCode::Backspace => {
+                state.log_search.pop();
+            }
+            _ => {
+                state.log_search += &get_input_char(event.code);
+            }
+        },
+        Event::Tick | Event::ServiceDone(_) | Event::ModScanDone(_) => {}
+    }
+    Ok(InputEvent::Other)
+}
+
+pub fn process_ini_edits(state: &mut ProgState, input: Event<crossterm::event::KeyEvent>) -> Result<InputEvent, Error> {
+    match input {
+        Event::Input(event) => match event.code {
+            KeyCode::Enter => match finish_ini_edit(state) {
+                Ok(()) => {
+                    state.edit_error = None;
+                    state.editing_ini = false;
+                }
+                Err(e) => state.edit_error = Some(e.to_string()),
+            },
+            KeyCode::Esc => {
+                state.edit_error = None;
+                state.editing_ini = false;
+                if let Some(editor) = &mut state.ini_editor {
+                    editor.adding = false;
+                }
+            }
+            KeyCode::Backspace => {
+                state.tmp_ini_field.pop();
+            }
+            _ => {
+                state.tmp_ini_field += &get_input_char(event.code);
+            }
+        },
+        Event::Tick | Event::ServiceDone(_) | Event::ModScanDone(_) => {}
+    }
+    Ok(InputEvent::Other)
+}
+
+//Left and Right move between the sections and their keys, Tab between GameUserSettings.ini and Game.ini.
+pub fn process_game_config(state: &mut ProgState, event: crossterm::event::KeyEvent) -> Result<(), Error> {
+    match event.code {
+        KeyCode::Char('b') => {
+            state.active_menu_item = MenuItem::ViewServer
+        }
+        KeyCode::Tab => notify_on_error(state, switch_ini_file),
+        KeyCode::Char('r') => notify_on_error(state, reload_ini_editor),
+        KeyCode::Char('w') => notify_on_error(state, preview_ini_edits),
+        KeyCode::Char('a') => notify_on_error(state, |state| start_ini_edit(state, true)),
+        KeyCode::Char('d') => notify_on_error(state, delete_ini_entry),
+        KeyCode::Enter if state.ini_editor.as_ref().is_some_and(|editor| editor.entries_focused) => {
+            notify_on_error(state, |state| start_ini_edit(state, false))
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            if let Some(editor) = &mut state.ini_editor {
+                editor.entries_focused = true;
+            }
+        }
+        KeyCode::Left => {
+            if let Some(editor) = &mut state.ini_editor {
+                editor.entries_focused = false;
+            }
+        }
+        KeyCode::Down | KeyCode::Up => {
+            if let Some(editor) = &mut state.ini_editor {
+                if editor.entries_focused {
+                    let num_entries = editor.selected_section().map_or(0, |section| editor.doc.entries(&section).len());
+                    if num_entries > 0 {
+                        try_change_table_state(event.code, &mut editor.entry_state, num_entries - 1);
+                    }
+                } else {
+                    try_change_list_state(event.code, &mut editor.section_state, editor.doc.sections().len());
+                    editor.entry_state.select(Some(0));
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn process_logs(state: &mut ProgState, event: crossterm::event::KeyEvent) -> Result<(), Error> {
+    let page = state.log_height.max(1) as isize;
+    match event.code {
+        KeyCode::Char('b') => {
+            state.active_menu_item = MenuItem::ViewServer
+        }
+        KeyCode::Char('p') | KeyCode::Char(' ') => {
+            state.log_paused = match state.log_paused.take() {
+                Some(_) => None,
+                None => state.log_tail.as_ref().map(|tail| tail.snapshot()),
+            };
+        }
+        KeyCode::Char('f') | KeyCode::Char('G') | KeyCode::End => {
+            state.log_follow = true;
+            state.log_match = None;
+        }
+        KeyCode::Char('g') | KeyCode::Home => {
+            state.log_follow = false;
+            state.log_scroll = 0;
+        }
+        KeyCode::Char('s') | KeyCode::Char('/') => {
+            state.log_search.clear();
+            state.editing_log_search = true;
+        }
+        KeyCode::Char('n') => jump_to_match(state, true),
+        KeyCode::Char('N') => jump_to_match(state, false),
+        KeyCode::Up => scroll_logs(state, -1),
+        KeyCode::Down => scroll_logs(state, 1),
+        KeyCode::PageUp => scroll_logs(state, -page),
+        KeyCode::PageDown => scroll_logs(state, page),
+        _ => {}
+    }
+    Ok(())
+}
+
 pub fn process_home(state: &mut ProgState, event: crossterm::event::KeyEvent) -> Result<(), Error> {
     match event.code {
+        KeyCode::Char('p') => {
+            state.config.refresh_profiles();
+            let profiles = state.config.profiles.clone();
+            //The one after the current profile, skipping it, or the first when the DB was given as a path.
+            let next = match &state.config.profile {
+                Some(current) => profiles.iter().position(|p| p == current).map(|i| (i + 1) % profiles.len()),
+                None => Some(0),
+            };
+            match next.and_then(|next| profiles.get(next)) {
+                Some(profile) if state.config.profile.as_ref() != Some(profile) => {
+                    notify_on_error(state, |state| switch_profile(state, profile))
+                }
+                _ => state.notification = Some("There is no other profile to switch to".to_string()),
+            }
+        }
+        KeyCode::Char('a') => notify_on_error(state, |state| {
+            state.audit_entries = state.audit.read_all()?;
+            state.audit_list_state.select(Some(0));
+            state.active_menu_item = MenuItem::Audit;
+            Ok(())
+        }),
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn process_audit(state: &mut ProgState, event: crossterm::event::KeyEvent) -> Result<(), Error> {
+    match event.code {
+        KeyCode::Char('b') => {
+            state.active_menu_item = MenuItem::Home
+        }
+        KeyCode::Char('f') | KeyCode::Char('/') => {
+            state.editing_audit_filter = true;
+        }
+        KeyCode::Char('r') => notify_on_error(state, |state| {
+            state.audit_entries = state.audit.read_all()?;
+            Ok(())
+        }),
+        KeyCode::Down => {
+            let num_entries = filter_entries(&state.audit_entries, &state.audit_filter).len();
+            try_change_table_state(KeyCode::Down, &mut state.audit_list_state, num_entries.saturating_sub(1));
+        }
+        KeyCode::Up => {
+            let num_entries = filter_entries(&state.audit_entries, &state.audit_filter).len();
+            try_change_table_state(KeyCode::Up, &mut state.audit_list_state, num_entries.saturating_sub(1));
+        }
         _ => {}
     }
     Ok(())
@@ -93,14 +391,25 @@ pub fn process_view_server(state: &mut ProgState, event: crossterm::event::KeyEv
         KeyCode::Char('e') => {
             state.active_menu_item = MenuItem::EditServer
         }
+        KeyCode::Char('t') => notify_on_error(state, open_logs),
+        KeyCode::Char('c') => notify_on_error(state, open_ini_editor),
         KeyCode::Char('s') => {
-            start_ark_server(&state).expect("can start ark_server");
+            notify_on_error(state, |state| {
+                let server_id = selected_server_id(state)?;
+                dispatch_service_action(state, server_id, ServiceAction::Start)
+            });
         }
         KeyCode::Char('k') => {
-            stop_ark_server(&state).expect("can start ark_server");
+            notify_on_error(state, |state| {
+                let server_id = selected_server_id(state)?;
+                dispatch_service_action(state, server_id, ServiceAction::Stop)
+            });
         }
         KeyCode::Char('r') => {
-            restart_ark_server(&state).expect("can restart ark_server");
+            notify_on_error(state, |state| {
+                let server_id = selected_server_id(state)?;
+                dispatch_service_action(state, server_id, ServiceAction::Restart)
+            });
         }
         _ => {}
     }
@@ -109,31 +418,41 @@ pub fn process_view_server(state: &mut ProgState, event: crossterm::event::KeyEv
 
 pub fn process_servers(state: &mut ProgState, event: crossterm::event::KeyEvent) -> Result<(), Error> {
     match event.code {
-        KeyCode::Char('a') => {
-            add_ark_server_to_db().expect("can add new random ark_server");
-        }
-        KeyCode::Char('d') => {
-            remove_ark_server_at_index(state).expect("can remove ark_server");
-        }
-        KeyCode::Char('s') => {
-            start_ark_server(&state).expect("can start ark_server");
-        }
-        KeyCode::Char('k') => {
-            stop_ark_server(&state).expect("can start ark_server");
+        KeyCode::Char('a') => notify_on_error(state, |state| add_ark_server_to_db(state).map(|_| ())),
+        KeyCode::Char('d') if get_num_servers(state)? > 0 => {
+            let server_id = selected_server_id(state)?;
+            notify_on_error(state, |state| remove_ark_server(state, server_id));
+        }
+        KeyCode::Char(' ') => {
+            if let Ok(server_id) = selected_server_id(state) {
+                if !state.marked_servers.remove(&server_id) {
+                    state.marked_servers.insert(server_id);
+                }
+            }
         }
-        KeyCode::Char('r') => {
-            restart_ark_server(&state).expect("can restart ark_server");
+        KeyCode::Char('*') => {
+            if state.marked_servers.is_empty() {
+                state.marked_servers = state.fleet.servers().iter().map(|s| s.id).collect();
+            } else {
+                state.marked_servers.clear();
+            }
         }
-        KeyCode::Enter => {
+        KeyCode::Char('s') => notify_on_error(state, |state| act_on_selection(state, ServiceAction::Start)),
+        KeyCode::Char('k') => notify_on_error(state, |state| act_on_selection(state, ServiceAction::Stop)),
+        KeyCode::Char('r') => notify_on_error(state, |state| act_on_selection(state, ServiceAction::Restart)),
+        KeyCode::Char('S') => notify_on_error(state, |state| act_on_all(state, ServiceAction::Start)),
+        KeyCode::Char('K') => notify_on_error(state, |state| act_on_all(state, ServiceAction::Stop)),
+        KeyCode::Char('R') => notify_on_error(state, |state| act_on_all(state, ServiceAction::Restart)),
+        KeyCode::Enter if get_num_servers(state)? > 0 => {
             state.active_menu_item = MenuItem::ViewServer;
         }
-        KeyCode::Down => {
-            let num_ark_servers = get_num_servers().expect("can fetch ark_server list length");
-            try_change_list_state(KeyCode::Down, &mut state.ark_server_list_state, num_ark_servers);
-        }
-        KeyCode::Up => {
-            let num_ark_servers = get_num_servers().expect("can fetch ark_server list length");
-            try_change_list_state(KeyCode::Up, &mut state.ark_server_list_state, num_ark_servers);
+        KeyCode::Down | KeyCode::Up => {
+            //Move through the servers as they are listed, grouped by host, rather than in fleet order.
+            let order = server_display_order(state);
+            let mut list_state = ListState::default();
+            list_state.select(state.ark_server_list_state.selected().and_then(|i| order.iter().position(|&j| j == i)));
+            try_change_list_state(event.code, &mut list_state, order.len());
+            state.ark_server_list_state.select(list_state.selected().and_then(|position| order.get(position).copied()));
         }
         _ => {}
     }
@@ -143,23 +462,63 @@ pub fn process_servers(state: &mut ProgState, event: crossterm::event::KeyEvent)
 pub fn process_server_mods(state: &mut ProgState, event: crossterm::event::KeyEvent) -> Result<(), Error> {
     match event.code {
         KeyCode::Char('a') => {
-            add_ark_server_mod_to_db(&state).expect("can add new random ark_server");
-        }
-        KeyCode::Char('d') => {
-            remove_ark_server_mod_at_index(state).expect("can remove ark_server mod");
-        }
+            let server_id = selected_server_id(state)?;
+            notify_on_error(state, |state| add_ark_server_mod_to_db(state, server_id).map(|_| ()));
+        }
+        KeyCode::Char('d') if get_num_server_mods(state)? > 0 => {
+            let server_id = selected_server_id(state)?;
+            let mod_id = selected_mod_id(state)?;
+            notify_on_error(state, |state| remove_ark_server_mod(state, server_id, mod_id));
+        }
+        KeyCode::Char('t') if get_num_server_mods(state)? > 0 => {
+            notify_on_error(state, |state| {
+                let server_id = selected_server_id(state)?;
+                let mod_id = selected_mod_id(state)?;
+                toggle_server_mod(state, server_id, mod_id)
+            });
+        }
+        //Enable them all if any are disabled, otherwise disable them all.
+        KeyCode::Char('T') => {
+            notify_on_error(state, |state| {
+                let server_id = selected_server_id(state)?;
+                let any_disabled = get_server(state)?.mods.iter().any(|m| !m.enabled);
+                set_all_server_mods_enabled(state, server_id, any_disabled)
+            });
+        }
+        //Reorder the load order: < and > (or Shift+Up and Shift+Down) move the mod one place, ^ to the top.
+        KeyCode::Char('<') | KeyCode::Char('>') | KeyCode::Char('^') => {
+            let index = state.ark_server_mod_list_state.selected().unwrap_or(0);
+            let to = match event.code {
+                KeyCode::Char('<') => index.saturating_sub(1),
+                KeyCode::Char('>') => index + 1,
+                _ => 0,
+            };
+            move_selected_mod(state, to);
+        }
+        KeyCode::Up if event.modifiers.contains(KeyModifiers::SHIFT) => {
+            let index = state.ark_server_mod_list_state.selected().unwrap_or(0);
+            move_selected_mod(state, index.saturating_sub(1));
+        }
+        KeyCode::Down if event.modifiers.contains(KeyModifiers::SHIFT) => {
+            let index = state.ark_server_mod_list_state.selected().unwrap_or(0);
+            move_selected_mod(state, index + 1);
+        }
+        //Read names, sizes and versions from the installed Workshop content and flag what does not match.
+        KeyCode::Char('s') => notify_on_error(state, scan_mods),
+        //Preview the enabled mods as the ActiveMods line of GameUserSettings.ini, y in the preview writes it.
+        KeyCode::Char('w') => notify_on_error(state, preview_active_mods),
         KeyCode::Char('b') => {
             state.active_menu_item = MenuItem::ViewServer
         }
-        KeyCode::Enter => {
+        KeyCode::Enter if get_num_server_mods(state)? > 0 => {
             state.active_menu_item = MenuItem::ViewMod;
         }
         KeyCode::Down => {
-            let num_ark_server_mods = get_num_server_mods(&state).expect("Can get seleted server");
+            let num_ark_server_mods = get_num_server_mods(state).expect("Can get seleted server");
             try_change_list_state(KeyCode::Down, &mut state.ark_server_mod_list_state, num_ark_server_mods);
         }
         KeyCode::Up => {
-            let num_ark_server_mods = get_num_server_mods(&state).expect("Can get seleted server");
+            let num_ark_server_mods = get_num_server_mods(state).expect("Can get seleted server");
             try_change_list_state(KeyCode::Up, &mut state.ark_server_mod_list_state, num_ark_server_mods);
         }
         _ => {}
@@ -215,7 +574,11 @@ pub fn process_view_mod(state: &mut ProgState, event: crossterm::event::KeyEvent
             state.active_menu_item = MenuItem::ServerMods
         }
         KeyCode::Char('t') => {
-            //TODO Toggle server mod
+            notify_on_error(state, |state| {
+                let server_id = selected_server_id(state)?;
+                let mod_id = selected_mod_id(state)?;
+                toggle_server_mod(state, server_id, mod_id)
+            });
         }
         KeyCode::Char('e') => {
             state.active_menu_item = MenuItem::EditMod
@@ -225,47 +588,55 @@ pub fn process_view_mod(state: &mut ProgState, event: crossterm::event::KeyEvent
     Ok(())
 }
 
-//There has to be a better way to do this but I can't find it.
-fn get_input_char(code: KeyCode) -> &'static str {
+//Run the action on the marked servers as a bulk action, or on the selected server if none are marked.
+fn act_on_selection(state: &mut ProgState, action: ServiceAction) -> Result<(), Error> {
+    if state.marked_servers.is_empty() {
+        let server_id = selected_server_id(state)?;
+        return dispatch_service_action(state, server_id, action)
+    }
+    let server_ids = server_display_order(state)
+        .into_iter()
+        .map(|i| state.fleet.servers()[i].id)
+        .filter(|id| state.marked_servers.contains(id))
+        .collect();
+    start_bulk(state, action, server_ids)?;
+    state.marked_servers.clear();
+    Ok(())
+}
+
+//Run the action on the whole fleet, in list order.
+fn act_on_all(state: &mut ProgState, action: ServiceAction) -> Result<(), Error> {
+    let server_ids = server_display_order(state).into_iter().map(|i| state.fleet.servers()[i].id).collect();
+    start_bulk(state, action, server_ids)
+}
+
+fn move_selected_mod(state: &mut ProgState, to: usize) {
+    notify_on_error(state, |state| {
+        let server_id = selected_server_id(state)?;
+        let mod_id = selected_mod_id(state)?;
+        move_server_mod(state, server_id, mod_id, to)
+    });
+}
+
+fn unsaved_ini_edits(state: &ProgState) -> bool {
+    state.ini_editor.as_ref().is_some_and(|editor| editor.is_modified())
+}
+
+//Run an action and show its error, if any, in the notification bar instead of failing.
+fn notify_on_error<F>(state: &mut ProgState, action: F)
+where
+    F: FnOnce(&mut ProgState) -> Result<(), Error>,
+{
+    if let Err(e) = action(state) {
+        state.notification = Some(e.to_string());
+    }
+}
+
+//The text a key press adds to the field being edited.
+fn get_input_char(code: KeyCode) -> String {
     match code {
-        KeyCode::Char('a') => return "a",
-        KeyCode::Char('b') => return "b",
-        KeyCode::Char('c') => return "c",
-        KeyCode::Char('d') => return "d",
-        KeyCode::Char('e') => return "e",
-        KeyCode::Char('f') => return "f",
-        KeyCode::Char('g') => return "g",
-        KeyCode::Char('h') => return "h",
-        KeyCode::Char('i') => return "i",
-        KeyCode::Char('j') => return "j",
-        KeyCode::Char('k') => return "k",
-        KeyCode::Char('l') => return "l",
-        KeyCode::Char('m') => return "m",
-        KeyCode::Char('n') => return "n",
-        KeyCode::Char('o') => return "o",
-        KeyCode::Char('p') => return "p",
-        KeyCode::Char('q') => return "q",
-        KeyCode::Char('r') => return "r",
-        KeyCode::Char('s') => return "s",
-        KeyCode::Char('t') => return "t",
-        KeyCode::Char('u') => return "u",
-        KeyCode::Char('v') => return "v",
-        KeyCode::Char('w') => return "w",
-        KeyCode::Char('x') => return "x",
-        KeyCode::Char('y') => return "y",
-        KeyCode::Char('z') => return "z",
-        KeyCode::Char('0') => return "0",
-        KeyCode::Char('1') => return "1",
-        KeyCode::Char('2') => return "2",
-        KeyCode::Char('3') => return "3",
-        KeyCode::Char('4') => return "4",
-        KeyCode::Char('5') => return "5",
-        KeyCode::Char('6') => return "6",
-        KeyCode::Char('7') => return "7",
-        KeyCode::Char('8') => return "8",
-        KeyCode::Char('9') => return "9",
-        KeyCode::Char(' ') => return " ",
-        _ => return "",
+        KeyCode::Char(c) => c.to_string(),
+        _ => String::new(),
     }
 }
 
@@ -274,9 +645,7 @@ fn try_change_list_state(code: KeyCode, list_state: &mut ListState, list_size: u
     match code {
         KeyCode::Down => {
             if let Some(selected) = list_state.selected() {
-                if list_size < 1 {
-                    list_state.select(Some(0));
-                } else if selected >= list_size- 1 {
+                if list_size < 1 || selected >= list_size - 1 {
                     list_state.select(Some(0));
                 } else {
                     list_state.select(Some(selected + 1));
@@ -294,7 +663,7 @@ fn try_change_list_state(code: KeyCode, list_state: &mut ListState, list_size: u
                 }
             }
         }
-        _ => return
+        _ => ()
     }
 }
 
@@ -303,9 +672,7 @@ fn try_change_table_state(code: KeyCode, table_state: &mut TableState, table_siz
     match code {
         KeyCode::Down => {
             if let Some(selected) = table_state.selected() {
-                if table_size < 1 {
-                    table_state.select(Some(0));
-                } else if selected >= table_size {
+                if table_size < 1 || selected >= table_size {
                     table_state.select(Some(0));
                 } else {
                     table_state.select(Some(selected + 1));
@@ -323,6 +690,6 @@ fn try_change_table_state(code: KeyCode, table_state: &mut TableState, table_siz
                 }
             }
         }
-        _ => return
+        _ => ()
     }
 }
@@ -0,0 +1,244 @@
+use crate::ark::{ArkServer, Error, MenuItem, ProgState};
+use crate::db::get_server;
+use crate::service::{describe_command, Backends};
+
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//How much of the existing log is loaded when the Logs view opens.
+const BACKLOG: usize = 500;
+//Lines kept in memory while following a log, older ones are dropped.
+const MAX_LINES: usize = 10_000;
+//How much of the log the server view keeps for its Recent Logs pane.
+const RECENT_LINES: usize = 100;
+//How often the log is fetched again for backends that cannot stream it.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+//How bad a log line looks, going by the words in it. Used to colour the Logs view.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Debug,
+    Info,
+}
+
+impl LogLevel {
+    pub fn of(line: &str) -> LogLevel {
+        let line = line.to_ascii_lowercase();
+        if ["error", "fatal", "crash", "critical", "panic", "assert"].iter().any(|word| line.contains(word)) {
+            LogLevel::Error
+        } else if line.contains("warn") {
+            LogLevel::Warning
+        } else if line.contains("debug") || line.contains("trace") {
+            LogLevel::Debug
+        } else {
+            LogLevel::Info
+        }
+    }
+}
+
+//A server's log, collected on worker threads while the Logs view is open.
+//Backends that can follow their log (journalctl -f, tail -F, docker logs -f) are streamed, the rest are polled.
+//Dropping it stops the stream.
+pub struct LogTail {
+    pub server_id: usize,
+    lines: Arc<Mutex<Vec<String>>>,
+    child: Option<Child>,
+    stop: Arc<AtomicBool>,
+}
+
+impl LogTail {
+    //Start following the server's log, beginning with the last backlog lines.
+    pub fn open(backends: Arc<Backends>, ark_server: ArkServer, backlog: usize) -> Result<LogTail, Error> {
+        let mut tail = LogTail {
+            server_id: ark_server.id,
+            lines: Arc::new(Mutex::new(Vec::new())),
+            child: None,
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        let follow = backends.get(ark_server.backend).follow(&ark_server, backlog);
+        match follow {
+            Some(mut command) => {
+                let mut child = command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .map_err(|source| Error::SpawnError { command: describe_command(&command), source })?;
+                let description = describe_command(&command);
+                if let Some(stdout) = child.stdout.take() {
+                    let lines = Arc::clone(&tail.lines);
+                    thread::spawn(move || {
+                        read_lines(stdout, &lines);
+                        push_line(&lines, format!("-- {} ended --", description));
+                    });
+                }
+                //journalctl and docker explain what went wrong on stderr, show it in line with the rest.
+                if let Some(stderr) = child.stderr.take() {
+                    let lines = Arc::clone(&tail.lines);
+                    thread::spawn(move || read_lines(stderr, &lines));
+                }
+                tail.child = Some(child);
+            }
+            None => {
+                let lines = Arc::clone(&tail.lines);
+                let stop = Arc::clone(&tail.stop);
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let fetched = match backends.get(ark_server.backend).logs(&ark_server, backlog) {
+                            Ok(text) => text.lines().map(str::to_string).collect(),
+                            Err(e) => vec![e.to_string()],
+                        };
+                        *lines.lock().expect("log tail lock poisoned") = fetched;
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                });
+            }
+        }
+        Ok(tail)
+    }
+
+    //A tail that could not be started, holding only the reason, so it is shown where the log would be.
+    pub fn failed(server_id: usize, error: &Error) -> LogTail {
+        LogTail {
+            server_id,
+            lines: Arc::new(Mutex::new(vec![error.to_string()])),
+            child: None,
+            stop: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    //Everything collected so far, oldest first.
+    pub fn snapshot(&self) -> Vec<String> {
+        self.lines.lock().expect("log tail lock poisoned").clone()
+    }
+}
+
+impl Drop for LogTail {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+//Server output is not always valid UTF-8, so read raw lines and convert them lossily.
+fn read_lines(reader: impl Read, lines: &Mutex<Vec<String>>) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    while let Ok(read) = reader.read_until(b'\n', &mut buf) {
+        if read == 0 {
+            break
+        }
+        push_line(lines, String::from_utf8_lossy(&buf).trim_end().to_string());
+        buf.clear();
+    }
+}
+
+fn push_line(lines: &Mutex<Vec<String>>, line: String) {
+    let mut lines = lines.lock().expect("log tail lock poisoned");
+    lines.push(line);
+    if lines.len() > MAX_LINES {
+        let excess = lines.len() - MAX_LINES;
+        lines.drain(..excess);
+    }
+}
+
+//Indexes of the lines containing the search text, ignoring ASCII case.
+pub fn find_matches(lines: &[String], search: &str) -> Vec<usize> {
+    if search.is_empty() {
+        return Vec::new()
+    }
+    let search = search.to_ascii_lowercase();
+    lines.iter()
+        .enumerate()
+        .filter(|(_, line)| line.to_ascii_lowercase().contains(&search))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+//Follow the selected server's log while the server view is open, so the Recent Logs pane is drawn from what the
+//tail has collected instead of asking the backend every frame. Stopped once the view is left.
+pub fn follow_recent_logs(state: &mut ProgState) {
+    let ark_server = match state.active_menu_item {
+        MenuItem::ViewServer => get_server(state).ok(),
+        _ => None,
+    };
+    match ark_server {
+        Some(ark_server) if state.recent_logs.as_ref().is_some_and(|tail| tail.server_id == ark_server.id) => {}
+        Some(ark_server) => {
+            let server_id = ark_server.id;
+            state.recent_logs = Some(
+                LogTail::open(Arc::clone(&state.backends), ark_server, RECENT_LINES)
+                    .unwrap_or_else(|e| LogTail::failed(server_id, &e)),
+            );
+        }
+        None => state.recent_logs = None,
+    }
+}
+
+//Open the Logs view on the selected server, following the end of its log.
+pub fn open_logs(state: &mut ProgState) -> Result<(), Error> {
+    let ark_server = get_server(state)?;
+    state.log_tail = Some(LogTail::open(Arc::clone(&state.backends), ark_server, BACKLOG)?);
+    state.log_scroll = 0;
+    state.log_follow = true;
+    state.log_paused = None;
+    state.log_search.clear();
+    state.log_match = None;
+    state.active_menu_item = MenuItem::Logs;
+    Ok(())
+}
+
+//The lines the Logs view is showing: the paused copy, or whatever the tail has collected.
+pub fn displayed_log_lines(state: &ProgState) -> Vec<String> {
+    match (&state.log_paused, &state.log_tail) {
+        (Some(paused), _) => paused.clone(),
+        (None, Some(tail)) => tail.snapshot(),
+        (None, None) => Vec::new(),
+    }
+}
+
+//Index of the top line on screen. Following keeps the last line at the bottom.
+pub fn log_top(state: &ProgState, num_lines: usize) -> usize {
+    let bottom = num_lines.saturating_sub(state.log_height);
+    if state.log_follow {
+        bottom
+    } else {
+        state.log_scroll.min(bottom)
+    }
+}
+
+//Move the view by delta lines. Scrolling up stops following, scrolling back to the end picks it up again.
+pub fn scroll_logs(state: &mut ProgState, delta: isize) {
+    let num_lines = displayed_log_lines(state).len();
+    let bottom = num_lines.saturating_sub(state.log_height);
+    let top = log_top(state, num_lines) as isize + delta;
+    state.log_scroll = top.clamp(0, bottom as isize) as usize;
+    state.log_follow = state.log_scroll >= bottom && delta > 0;
+}
+
+//Jump to the next search match after the current one, or the previous one before it, wrapping around.
+//Without a current match, the search starts from the end of the log.
+pub fn jump_to_match(state: &mut ProgState, forward: bool) {
+    let lines = displayed_log_lines(state);
+    let matches = find_matches(&lines, &state.log_search);
+    let next = match (state.log_match, forward) {
+        (Some(current), true) => matches.iter().find(|&&i| i > current).or_else(|| matches.first()),
+        (Some(current), false) => matches.iter().rev().find(|&&i| i < current).or_else(|| matches.last()),
+        (None, _) => matches.last(),
+    };
+    if let Some(&line) = next {
+        state.log_match = Some(line);
+        state.log_follow = false;
+        //Put the match in the middle of the view where possible.
+        state.log_scroll = line.saturating_sub(state.log_height / 2).min(lines.len().saturating_sub(state.log_height));
+    }
+}
@@ -1,31 +1,76 @@
+mod audit;
+mod bulk;
+mod config;
+mod container;
 mod render;
 mod db;
+mod gameini;
+mod history;
+mod ini;
+mod host;
 mod service;
+mod supervisor;
 mod ark;
 mod input;
+mod logtail;
+mod migrate;
+mod poller;
+mod transfer;
+mod validate;
+mod workshop;
 
 use crate::ark::{Event, ProgState, InputEvent};
+use crate::config::Config;
+use crate::db::JsonStore;
 use crate::render::{render};
 use crate::input::{process_input};
+use crate::poller::StatusPoller;
 
 use crossterm::{
     event::{self, Event as CEvent},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
 use std::io;
+use std::process;
 use std::sync::mpsc;
 use std::thread;
 use std::time::{Duration, Instant};
 use tui::{ backend::CrosstermBackend, Terminal };
 
 
-const DB_PATH: &str = "./data/db.json";
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (mut state, command) = match Config::from_env().and_then(|(config, command)| {
+        let store = JsonStore::open(&config.db_path)?;
+        let mut state = ProgState::new(config, Box::new(store));
+        state.load()?;
+        Ok((state, command))
+    }) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
+    let notice = state.config.notice.take();
+    if let Some(command) = command {
+        if let Some(notice) = notice {
+            eprintln!("{}", notice);
+        }
+        if let Err(e) = transfer::run(&mut state, command) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return Ok(())
+    }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+    state.notification = notice;
     enable_raw_mode().expect("can run in raw mode");
 
     let (tx, rx) = mpsc::channel();
+    state.events = Some(tx.clone());
+    StatusPoller::spawn(&state.status_poller);
+    state.status_poller.watch(&state.backends, state.fleet.servers());
     let tick_rate = Duration::from_millis(200);
     thread::spawn(move || {
         let mut last_tick = Instant::now();
@@ -40,10 +85,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
 
-            if last_tick.elapsed() >= tick_rate {
-                if let Ok(_) = tx.send(ark::Event::Tick) {
-                    last_tick = Instant::now();
-                }
+            if last_tick.elapsed() >= tick_rate && tx.send(ark::Event::Tick).is_ok() {
+                last_tick = Instant::now();
             }
         }
     });
@@ -54,21 +97,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     terminal.clear()?;
 
 
-    let mut state = ProgState::new();
-
     loop {
         render(&mut terminal, &mut state).expect("Renders successfully");
 
         let input = rx.recv().expect("Recieved input");
         let processed = process_input(&mut state, input).expect("Input processed");
-        match processed {
-            InputEvent::Exit => {
-                disable_raw_mode().expect("Raw mode disabled");
-                terminal.show_cursor()?;
-                terminal.clear()?;
-                break
-            }
-            _ => {}
+        if let InputEvent::Exit = processed {
+            state.save()?;
+            disable_raw_mode().expect("Raw mode disabled");
+            terminal.show_cursor()?;
+            terminal.clear()?;
+            break
         }
     }
 
@@ -0,0 +1,269 @@
+use crate::ark::Error;
+
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+//Version written by this build. Bump it and append a step to MIGRATIONS whenever the DB layout changes.
+pub const SCHEMA_VERSION: u64 = 4;
+
+//MIGRATIONS[n] upgrades a document from version n to version n + 1.
+const MIGRATIONS: [fn(Value) -> Result<Value, Error>; 4] = [
+    wrap_in_envelope,
+    rename_mod_description,
+    assign_unique_ids,
+    enable_all_mods,
+];
+
+//Files written before versioning was introduced are a bare array of servers.
+pub fn schema_version(doc: &Value) -> u64 {
+    if doc.is_array() {
+        return 0
+    }
+    doc.get("schema_version").and_then(Value::as_u64).unwrap_or(0)
+}
+
+//Upgrade a parsed DB document to SCHEMA_VERSION, one step at a time.
+pub fn migrate(mut doc: Value) -> Result<Value, Error> {
+    let version = schema_version(&doc);
+    if version > SCHEMA_VERSION {
+        return Err(Error::SchemaVersionError(version))
+    }
+    for step in &MIGRATIONS[version as usize..] {
+        doc = step(doc)?;
+    }
+    Ok(doc)
+}
+
+//v0 -> v1: move the bare server array into a versioned envelope.
+fn wrap_in_envelope(doc: Value) -> Result<Value, Error> {
+    Ok(json!({
+        "schema_version": 1,
+        "servers": doc,
+    }))
+}
+
+//v1 -> v2: fix the misspelled "descripton" field on mods.
+fn rename_mod_description(mut doc: Value) -> Result<Value, Error> {
+    if let Some(servers) = doc["servers"].as_array_mut() {
+        for server in servers {
+            if let Some(mods) = server["mods"].as_array_mut() {
+                for ark_mod in mods.iter_mut().filter_map(Value::as_object_mut) {
+                    let description = ark_mod.remove("descripton").unwrap_or_else(|| json!(""));
+                    ark_mod.entry("description").or_insert(description);
+                }
+            }
+        }
+    }
+    doc["schema_version"] = json!(2);
+    Ok(doc)
+}
+
+//v2 -> v3: give every server a unique non-zero ID and record the next one to hand out.
+//Mods are keyed by Workshop ID from here on. Mods added before they could be given one have ID 0 and get a
+//placeholder ID each, as New Mod does now. The same Workshop ID twice on a server has to be sorted out by hand,
+//the upgrade stops rather than pick which one to keep.
+fn assign_unique_ids(mut doc: Value) -> Result<Value, Error> {
+    let mut next_server_id = 1;
+    if let Some(servers) = doc["servers"].as_array_mut() {
+        let max_id = servers.iter().filter_map(|s| s["id"].as_u64()).max().unwrap_or(0);
+        next_server_id = max_id + 1;
+        let mut seen_servers = HashSet::new();
+        for server in servers {
+            let id = server["id"].as_u64().unwrap_or(0);
+            if id == 0 || !seen_servers.insert(id) {
+                server["id"] = json!(next_server_id);
+                next_server_id += 1;
+            }
+            let name = server["name"].as_str().unwrap_or("").to_string();
+            if let Some(mods) = server["mods"].as_array_mut() {
+                let mut seen_mods = HashSet::new();
+                for ark_mod in mods.iter().filter_map(|m| m["id"].as_u64()).filter(|&id| id != 0) {
+                    if !seen_mods.insert(ark_mod) {
+                        return Err(Error::DuplicateIdError(format!(
+                            "mod {} is listed twice on server {}, remove one of them from the DB file and start again",
+                            ark_mod, name
+                        )))
+                    }
+                }
+                let mut placeholder = 1;
+                for ark_mod in mods.iter_mut().filter(|m| m["id"].as_u64().unwrap_or(0) == 0) {
+                    while seen_mods.contains(&placeholder) {
+                        placeholder += 1;
+                    }
+                    ark_mod["id"] = json!(placeholder);
+                    seen_mods.insert(placeholder);
+                }
+            }
+        }
+    }
+    doc["next_server_id"] = json!(next_server_id);
+    doc["schema_version"] = json!(3);
+    Ok(doc)
+}
+
+//v3 -> v4: mods could not be disabled before, so whatever the stored flag says every mod was in use.
+fn enable_all_mods(mut doc: Value) -> Result<Value, Error> {
+    if let Some(servers) = doc["servers"].as_array_mut() {
+        for server in servers {
+            if let Some(mods) = server["mods"].as_array_mut() {
+                for ark_mod in mods.iter_mut().filter_map(Value::as_object_mut) {
+                    ark_mod.insert("enabled".to_string(), json!(true));
+                }
+            }
+        }
+    }
+    doc["schema_version"] = json!(4);
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{sibling_path, JsonStore};
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    //A fresh directory per test so tests running in parallel do not share DB files.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ark-manager-migrate-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("can create test dir");
+        dir
+    }
+
+    fn v0_doc() -> Value {
+        json!([
+            {"id": 0, "name": "Island", "mods": [{"id": 731604991, "name": "Structures Plus", "descripton": "S+", "enabled": false}]},
+            {"id": 0, "name": "Ragnarok", "mods": []},
+        ])
+    }
+
+    #[test]
+    fn schema_version_of_bare_array_is_zero() {
+        assert_eq!(schema_version(&json!([])), 0);
+        assert_eq!(schema_version(&json!({"servers": []})), 0);
+        assert_eq!(schema_version(&json!({"schema_version": 3, "servers": []})), 3);
+    }
+
+    #[test]
+    fn wrap_in_envelope_keeps_servers() {
+        let doc = wrap_in_envelope(v0_doc()).expect("migrates");
+        assert_eq!(doc["schema_version"], json!(1));
+        assert_eq!(doc["servers"], v0_doc());
+    }
+
+    #[test]
+    fn rename_mod_description_moves_misspelled_field() {
+        let doc = rename_mod_description(json!({
+            "schema_version": 1,
+            "servers": [{"mods": [{"id": 1, "descripton": "old"}, {"id": 2, "description": "kept"}, {"id": 3}]}],
+        })).expect("migrates");
+        let mods = &doc["servers"][0]["mods"];
+        assert_eq!(mods[0], json!({"id": 1, "description": "old"}));
+        assert_eq!(mods[1], json!({"id": 2, "description": "kept"}));
+        assert_eq!(mods[2], json!({"id": 3, "description": ""}));
+        assert_eq!(doc["schema_version"], json!(2));
+    }
+
+    #[test]
+    fn assign_unique_ids_replaces_zero_and_repeated_server_ids() {
+        let doc = assign_unique_ids(json!({
+            "schema_version": 2,
+            "servers": [{"id": 0, "mods": []}, {"id": 5, "mods": []}, {"id": 5, "mods": []}, {"mods": []}],
+        })).expect("migrates");
+        let ids: Vec<u64> = doc["servers"].as_array().expect("servers").iter().map(|s| s["id"].as_u64().expect("id")).collect();
+        assert_eq!(ids, vec![6, 5, 7, 8]);
+        assert_eq!(doc["next_server_id"], json!(9));
+        assert_eq!(doc["schema_version"], json!(3));
+    }
+
+    #[test]
+    fn assign_unique_ids_gives_unnamed_mods_placeholder_ids() {
+        let doc = assign_unique_ids(json!({
+            "schema_version": 2,
+            "servers": [{"id": 1, "name": "Island", "mods": [{"id": 0}, {"id": 2}, {"id": 0}, {"id": 731604991}]}],
+        })).expect("migrates");
+        let ids: Vec<u64> = doc["servers"][0]["mods"].as_array().expect("mods").iter().map(|m| m["id"].as_u64().expect("id")).collect();
+        assert_eq!(ids, vec![1, 2, 3, 731604991]);
+    }
+
+    #[test]
+    fn assign_unique_ids_stops_on_repeated_mod() {
+        let result = assign_unique_ids(json!({
+            "schema_version": 2,
+            "servers": [{"id": 1, "name": "Island", "mods": [{"id": 731604991, "name": "a"}, {"id": 731604991, "name": "b"}]}],
+        }));
+        assert!(matches!(result, Err(Error::DuplicateIdError(message)) if message.contains("731604991") && message.contains("Island")));
+    }
+
+    #[test]
+    fn enable_all_mods_enables_every_mod() {
+        let doc = enable_all_mods(json!({
+            "schema_version": 3,
+            "servers": [{"mods": [{"id": 1, "enabled": false}, {"id": 2}]}],
+        })).expect("migrates");
+        assert_eq!(doc["servers"][0]["mods"], json!([{"id": 1, "enabled": true}, {"id": 2, "enabled": true}]));
+        assert_eq!(doc["schema_version"], json!(4));
+    }
+
+    #[test]
+    fn migrate_v0_to_current() {
+        let doc = migrate(v0_doc()).expect("migrates");
+        assert_eq!(schema_version(&doc), SCHEMA_VERSION);
+        assert_eq!(doc["next_server_id"], json!(3));
+        let servers = doc["servers"].as_array().expect("servers");
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0]["id"], json!(1));
+        assert_eq!(servers[1]["id"], json!(2));
+        assert_eq!(servers[0]["mods"][0]["description"], json!("S+"));
+        assert_eq!(servers[0]["mods"][0]["enabled"], json!(true));
+        assert!(servers[0]["mods"][0].get("descripton").is_none());
+    }
+
+    #[test]
+    fn migrate_current_is_unchanged() {
+        let doc = json!({"schema_version": SCHEMA_VERSION, "next_server_id": 2, "servers": [{"id": 1, "mods": []}]});
+        assert_eq!(migrate(doc.clone()).expect("migrates"), doc);
+    }
+
+    #[test]
+    fn migrate_rejects_newer_schema() {
+        let doc = json!({"schema_version": SCHEMA_VERSION + 1, "servers": []});
+        assert!(matches!(migrate(doc), Err(Error::SchemaVersionError(version)) if version == SCHEMA_VERSION + 1));
+    }
+
+    #[test]
+    fn open_backs_up_old_db_before_upgrading() {
+        let dir = test_dir("backup");
+        let path = dir.join("db.json");
+        //The sample DB shipped with the repo predates versioning.
+        let original = include_str!("../data/db.json");
+        fs::write(&path, original).expect("can write DB");
+
+        let store = JsonStore::open(&path).expect("opens");
+        drop(store);
+
+        assert_eq!(fs::read_to_string(sibling_path(&path, "v0.bak")).expect("backup written"), original);
+        let upgraded: Value = serde_json::from_str(&fs::read_to_string(&path).expect("DB readable")).expect("DB is JSON");
+        assert_eq!(schema_version(&upgraded), SCHEMA_VERSION);
+        let sample: Value = serde_json::from_str(original).expect("sample DB is JSON");
+        assert_eq!(upgraded["servers"].as_array().expect("servers").len(), sample.as_array().expect("bare array").len());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn open_current_db_writes_no_backup() {
+        let dir = test_dir("no-backup");
+        let path = dir.join("db.json");
+        drop(JsonStore::open(&path).expect("opens"));
+        drop(JsonStore::open(&path).expect("opens again"));
+        let backups = fs::read_dir(&dir)
+            .expect("test dir readable")
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".bak"))
+            .count();
+        assert_eq!(backups, 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::ark::ArkServer;
+use crate::service::{format_uptime, Backends, ServiceState, ServiceStatus};
+
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//The last status seen for a server and when it was taken.
+#[derive(Clone, Debug)]
+pub struct PolledStatus {
+    pub state: ServiceState,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl PolledStatus {
+    //e.g. "checked 4s ago".
+    pub fn age(&self) -> String {
+        let seconds = Utc::now().signed_duration_since(self.checked_at).num_seconds().max(0);
+        match seconds {
+            0..=59 => format!("checked {}s ago", seconds),
+            _ => format!("checked {} ago", format_uptime(chrono::Duration::seconds(seconds))),
+        }
+    }
+}
+
+//What the poller should look at next. Replaced by the UI thread whenever the fleet or profile may have changed.
+struct Targets {
+    backends: Arc<Backends>,
+    servers: Vec<ArkServer>,
+    //Set to poll straight away instead of waiting out the interval.
+    refresh: bool,
+}
+
+//Asks every server's backend for its status on a worker thread, so rendering only ever reads the cache.
+//Backends can take a while to answer (systemctl, docker inspect), and a frame is drawn every tick.
+pub struct StatusPoller {
+    interval: Duration,
+    targets: Mutex<Targets>,
+    wake: Condvar,
+    statuses: Mutex<HashMap<usize, PolledStatus>>,
+}
+
+impl StatusPoller {
+    pub fn new(interval: Duration, backends: Arc<Backends>) -> StatusPoller {
+        StatusPoller {
+            interval,
+            targets: Mutex::new(Targets { backends, servers: Vec::new(), refresh: false }),
+            wake: Condvar::new(),
+            statuses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    //Run the polling loop on its own thread for as long as the program runs.
+    pub fn spawn(poller: &Arc<StatusPoller>) {
+        let poller = Arc::clone(poller);
+        thread::spawn(move || loop {
+            let (backends, servers) = poller.wait_for_round();
+            let mut polled = HashMap::new();
+            for ark_server in servers {
+                let state = backends
+                    .get(ark_server.backend)
+                    .status(&ark_server)
+                    .unwrap_or_else(|e| ServiceState::new(ServiceStatus::Unknown(e.to_string())));
+                polled.insert(ark_server.id, PolledStatus { state, checked_at: Utc::now() });
+            }
+            //A round started before a profile switch polled the old fleet, whose IDs mean other servers now.
+            let targets = poller.targets.lock().expect("status poller lock poisoned");
+            if Arc::ptr_eq(&targets.backends, &backends) {
+                //Servers deleted since the round started drop out here.
+                *poller.statuses.lock().expect("status cache lock poisoned") = polled;
+            }
+        });
+    }
+
+    //Block until the interval is up or a refresh is asked for, then hand back what to poll.
+    fn wait_for_round(&self) -> (Arc<Backends>, Vec<ArkServer>) {
+        let targets = self.targets.lock().expect("status poller lock poisoned");
+        let (mut targets, _) = self
+            .wake
+            .wait_timeout_while(targets, self.interval, |targets| !targets.refresh)
+            .expect("status poller lock poisoned");
+        targets.refresh = false;
+        (Arc::clone(&targets.backends), targets.servers.clone())
+    }
+
+    //Point the poller at the current fleet. Polls right away if a server was added, removed or changed backend.
+    pub fn watch(&self, backends: &Arc<Backends>, servers: &[ArkServer]) {
+        let mut targets = self.targets.lock().expect("status poller lock poisoned");
+        let changed = !Arc::ptr_eq(&targets.backends, backends)
+            || targets.servers.len() != servers.len()
+            || targets.servers.iter().zip(servers).any(|(a, b)| {
+                a.id != b.id || a.backend != b.backend || a.service_name != b.service_name || a.container != b.container
+            });
+        targets.backends = Arc::clone(backends);
+        targets.servers = servers.to_vec();
+        if changed {
+            targets.refresh = true;
+            self.wake.notify_one();
+        }
+    }
+
+    //Point the poller at another profile's fleet, dropping the statuses of the old one straight away.
+    pub fn forget(&self, backends: &Arc<Backends>, servers: &[ArkServer]) {
+        self.statuses.lock().expect("status cache lock poisoned").clear();
+        self.watch(backends, servers);
+    }
+
+    //Poll again without waiting for the interval, e.g. once a start or stop has finished.
+    pub fn refresh(&self) {
+        self.targets.lock().expect("status poller lock poisoned").refresh = true;
+        self.wake.notify_one();
+    }
+
+    pub fn get(&self, server_id: usize) -> Option<PolledStatus> {
+        self.statuses.lock().expect("status cache lock poisoned").get(&server_id).cloned()
+    }
+}
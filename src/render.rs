@@ -1,16 +1,20 @@
-use crate::ark::{ProgState, ArkServerMod, Error, MenuItem};
-use crate::db::{get_servers, get_server, get_server_mod, get_server_mod_property, get_server_mod_properties, get_server_mods_str, get_server_properties};
-use crate::service::{status_ark_server};
+use crate::ark::{ProgState, Error, MenuItem};
+use crate::audit::filter_entries;
+use crate::gameini::DiffLine;
+use crate::ini::EntryOp;
+use crate::logtail::{displayed_log_lines, find_matches, log_top, LogLevel};
+use crate::db::{servers_by_host, get_servers, get_server, get_server_mod, get_server_mod_properties, get_server_mods_str, get_server_properties};
+use crate::service::{ServiceState, ServiceStatus, format_bytes};
 use tui::{
     backend::CrosstermBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
     widgets::{
-        Block, BorderType, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table, Tabs,
+        Block, BorderType, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, Tabs, Wrap,
     },
-    Terminal,
 };
+use chrono::Local;
 use std::io;
 
 pub fn render(terminal: &mut tui::Terminal<CrosstermBackend<io::Stdout>>, state: &mut ProgState) -> Result<(), Error> {
@@ -29,16 +33,28 @@ pub fn render(terminal: &mut tui::Terminal<CrosstermBackend<io::Stdout>>, state:
             )
             .split(size);
 
-        let copyright = Paragraph::new("ark_server_manager-CLI 2021 - all rights reserved")
-            .style(Style::default().fg(Color::LightCyan))
-            .alignment(Alignment::Center)
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .style(Style::default().fg(Color::White))
-                    .title("Copyright")
-                    .border_type(BorderType::Plain),
-            );
+        let copyright = match &state.notification {
+            Some(message) => Paragraph::new(message.clone())
+                .style(Style::default().fg(Color::Red))
+                .alignment(Alignment::Center)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .style(Style::default().fg(Color::White))
+                        .title("Notification")
+                        .border_type(BorderType::Plain),
+                ),
+            None => Paragraph::new("ark_server_manager-CLI 2021 - all rights reserved")
+                .style(Style::default().fg(Color::LightCyan))
+                .alignment(Alignment::Center)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .style(Style::default().fg(Color::White))
+                        .title("Copyright")
+                        .border_type(BorderType::Plain),
+                ),
+        };
 
         let menu = state.menu_titles
             .iter()
@@ -66,68 +82,224 @@ pub fn render(terminal: &mut tui::Terminal<CrosstermBackend<io::Stdout>>, state:
         rect.render_widget(tabs, chunks[0]);
         match state.active_menu_item {
             MenuItem::Home => {
-                state.menu_titles = vec!["Home", "List Servers", "Quit"];
+                state.menu_titles = vec!["Home", "List Servers", "Profile", "Audit", "Quit"];
                 state.active_menu_highlight = MenuItem::Home;
                 rect.render_widget(home(state), chunks[1]);
             }
             MenuItem::Servers => {
-                state.menu_titles = vec!["Home", "List Servers", "Add", "Delete", "Quit"];
+                state.menu_titles = vec!["Home", "List Servers", "Add", "Delete", "Undo", "Quit"];
                 state.active_menu_highlight = MenuItem::Servers;
                 let ark_servers_chunks = Layout::default()
                     .direction(Direction::Horizontal)
                     .constraints(
-                        [Constraint::Percentage(20), Constraint::Percentage(80)].as_ref(),
+                        [Constraint::Percentage(20), Constraint::Percentage(55), Constraint::Percentage(25)].as_ref(),
                     )
                     .split(chunks[1]);
-                let (left, right) = ark_servers(&state);
-                rect.render_stateful_widget(left, ark_servers_chunks[0], &mut state.ark_server_list_state);
+                let (left, right, mut list_state) = ark_servers(state);
+                rect.render_stateful_widget(left, ark_servers_chunks[0], &mut list_state);
                 rect.render_widget(right, ark_servers_chunks[1]);
+                rect.render_widget(history(state), ark_servers_chunks[2]);
             }
             MenuItem::ViewServer => {
-                state.menu_titles = vec!["Home", "List Servers", "Mods", "Edit", "Back", "Quit"];
+                state.menu_titles = vec!["Home", "List Servers", "Mods", "Edit", "Config", "Tail Logs", "Back", "Quit"];
                 state.active_menu_highlight = MenuItem::Servers;
-                rect.render_widget(view_ark_server(&state), chunks[1]);
+                //Every detail row plus the borders, the logs get whatever is left.
+                let view_chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(
+                        [Constraint::Length(14), Constraint::Min(3)].as_ref(),
+                    )
+                    .split(chunks[1]);
+                rect.render_widget(view_ark_server(state), view_chunks[0]);
+                rect.render_widget(recent_logs(state, view_chunks[1].height), view_chunks[1]);
             }
             MenuItem::ServerMods => {
-                state.menu_titles = vec!["Home", "List Servers", "Mods", "Add", "Delete", "Back", "Quit"];
+                state.menu_titles = vec!["Home", "List Servers", "Mods", "Add", "Delete", "Toggle", "Write", "Scan", "Undo", "Back", "Quit"];
                 state.active_menu_highlight = MenuItem::ServerMods;
                 let ark_servers_chunks = Layout::default()
                     .direction(Direction::Horizontal)
                     .constraints(
-                        [Constraint::Percentage(20), Constraint::Percentage(80)].as_ref(),
+                        [Constraint::Percentage(20), Constraint::Percentage(55), Constraint::Percentage(25)].as_ref(),
                     )
                     .split(chunks[1]);
-                let (left, right) = ark_server_mods(&state);
+                let (left, right) = ark_server_mods(state);
                 rect.render_stateful_widget(left, ark_servers_chunks[0], &mut state.ark_server_mod_list_state);
                 rect.render_widget(right, ark_servers_chunks[1]);
+                rect.render_widget(history(state), ark_servers_chunks[2]);
             }
             MenuItem::ViewMod => {
                 state.menu_titles = vec!["Home", "List Servers", "Mods", "Toggle", "Edit", "Back", "Quit"];
                 state.active_menu_highlight = MenuItem::ServerMods;
-                rect.render_widget(view_ark_server_mod(&state.ark_server_list_state, &state.ark_server_mod_list_state), chunks[1]);
+                rect.render_widget(view_ark_server_mod(state), chunks[1]);
             }
             MenuItem::EditMod => {
                 state.menu_titles = vec!["Home", "List Servers", "Mods", "Back", "Quit"];
                 state.active_menu_highlight = MenuItem::ServerMods;
-                let left = edit_ark_server_mod(&state);
+                let left = edit_ark_server_mod(state);
                 rect.render_stateful_widget(left, chunks[1], &mut state.ark_server_mod_list_edit_state);
             }
             MenuItem::EditServer => {
                 state.menu_titles = vec!["Home", "List Servers", "Mods", "Back", "Quit"];
                 state.active_menu_highlight = MenuItem::Servers;
-                let left = edit_ark_server(&state);
+                let left = edit_ark_server(state);
                 rect.render_stateful_widget(left, chunks[1], &mut state.ark_server_list_edit_state);
             }
+            MenuItem::Audit => {
+                state.menu_titles = vec!["Home", "List Servers", "Filter", "Audit", "Reload", "Back", "Quit"];
+                state.active_menu_highlight = MenuItem::Audit;
+                let audit_chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(
+                        [Constraint::Percentage(60), Constraint::Percentage(40)].as_ref(),
+                    )
+                    .split(chunks[1]);
+                let (top, bottom) = audit(state);
+                rect.render_stateful_widget(top, audit_chunks[0], &mut state.audit_list_state);
+                rect.render_widget(bottom, audit_chunks[1]);
+            }
+            MenuItem::Logs => {
+                state.menu_titles = vec!["Home", "List Servers", "Pause", "Follow", "Search", "Next", "Back", "Quit"];
+                state.active_menu_highlight = MenuItem::Servers;
+                state.log_height = chunks[1].height.saturating_sub(2) as usize;
+                rect.render_widget(logs(state), chunks[1]);
+            }
+            MenuItem::GameConfig => {
+                state.menu_titles = vec!["Home", "List Servers", "Add", "Delete", "Write", "Reload", "Back", "Quit"];
+                state.active_menu_highlight = MenuItem::Servers;
+                let config_chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints(
+                        [Constraint::Percentage(30), Constraint::Percentage(70)].as_ref(),
+                    )
+                    .split(chunks[1]);
+                let (left, right) = game_config(state);
+                if let Some(editor) = &mut state.ini_editor {
+                    rect.render_stateful_widget(left, config_chunks[0], &mut editor.section_state);
+                    rect.render_stateful_widget(right, config_chunks[1], &mut editor.entry_state);
+                }
+            }
         }
         rect.render_widget(copyright, chunks[2]);
+        if let Some(summary) = bulk_summary(state) {
+            let area = centered(chunks[1], 70, 60);
+            rect.render_widget(Clear, area);
+            rect.render_widget(summary, area);
+        }
+        if let Some(preview) = ini_preview(state) {
+            let area = centered(chunks[1], 80, 70);
+            rect.render_widget(Clear, area);
+            rect.render_widget(preview, area);
+        }
     })?;
     Ok(())
 }
 
-pub fn home<'a>(state: &mut ProgState) -> Paragraph<'a> {
-    let servers = get_servers().expect("servers exist");
-    if servers.len() < 1 {
-        let home = Paragraph::new(vec![
+//A rectangle of the given percentages of the area, in the middle of it.
+fn centered(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ].as_ref())
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ].as_ref())
+        .split(vertical[1])[1]
+}
+
+//How each server fared in the last bulk action, shown over the current view until a key is pressed.
+fn bulk_summary<'a>(state: &ProgState) -> Option<Paragraph<'a>> {
+    let summary = state.bulk_summary.as_ref()?;
+    let failed = summary.results.iter().filter(|r| r.result.is_err()).count();
+    let lines: Vec<Spans> = summary.results
+        .iter()
+        .map(|r| match &r.result {
+            Ok(output) => Spans::from(vec![
+                Span::styled(format!("ok      {}", r.server_name), Style::default().fg(Color::Green)),
+                Span::styled(format!("  {}", output.lines().next().unwrap_or("")), Style::default().fg(Color::DarkGray)),
+            ]),
+            Err(e) => Spans::from(vec![
+                Span::styled(format!("failed  {}", r.server_name), Style::default().fg(Color::Red)),
+                Span::raw(format!("  {}", e)),
+            ]),
+        })
+        .collect();
+    let title = format!(
+        "{} finished: {} ok, {} failed (any key to close)",
+        summary.progress(),
+        summary.results.len() - failed,
+        failed
+    );
+    Some(Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(title)
+            .border_type(BorderType::Plain),
+    ))
+}
+
+//The changes a pending INI rewrite would make, shown over the current view until it is confirmed or dropped.
+fn ini_preview<'a>(state: &ProgState) -> Option<Paragraph<'a>> {
+    let update = state.ini_update.as_ref()?;
+    let lines: Vec<Spans> = update.diff
+        .iter()
+        .map(|line| match line {
+            DiffLine::Same(text) => Spans::from(vec![Span::raw(format!("  {}", text))]),
+            DiffLine::Removed(text) => Spans::from(vec![Span::styled(format!("- {}", text), Style::default().fg(Color::Red))]),
+            DiffLine::Added(text) => Spans::from(vec![Span::styled(format!("+ {}", text), Style::default().fg(Color::Green))]),
+            DiffLine::Skipped(count) => Spans::from(vec![Span::styled(
+                format!("  ... {} unchanged line(s)", count),
+                Style::default().fg(Color::DarkGray),
+            )]),
+        })
+        .collect();
+    let action = match update.before {
+        Some(_) => "update",
+        None => "create",
+    };
+    let title = format!(
+        "{} {} on {} (y to write, any other key to cancel)",
+        action,
+        update.path.display(),
+        host_label(&update.host)
+    );
+    Some(Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::White))
+            .title(title)
+            .border_type(BorderType::Plain),
+    ))
+}
+
+//Which fleet is loaded, shown at the top of the Home tab.
+fn profile_spans<'a>(state: &ProgState) -> Vec<Spans<'a>> {
+    let current = match &state.config.profile {
+        Some(profile) => format!("Profile: {}", profile),
+        None => format!("DB: {}", state.config.db_path.display()),
+    };
+    let mut lines = vec![Spans::from(vec![Span::styled(current, Style::default().fg(Color::LightCyan))])];
+    let profiles = &state.config.profiles;
+    if profiles.len() > 1 {
+        lines.push(Spans::from(vec![Span::raw(format!("Profiles: {}", profiles.join(", ")))]));
+    }
+    lines.push(Spans::from(vec![Span::raw("")]));
+    lines
+}
+
+pub fn home<'a>(state: &ProgState) -> Paragraph<'a> {
+    let servers = get_servers(state).expect("servers exist");
+    let mut lines = profile_spans(state);
+    if servers.is_empty() {
+        lines.extend(vec![
             Spans::from(vec![Span::raw("")]),
             Spans::from(vec![Span::raw("Welcome")]),
             Spans::from(vec![Span::raw("")]),
@@ -138,7 +310,8 @@ pub fn home<'a>(state: &mut ProgState) -> Paragraph<'a> {
                 Style::default().fg(Color::LightBlue),
             )]),
             Spans::from(vec![Span::raw("")]),
-        ])
+        ]);
+        let home = Paragraph::new(lines)
         .alignment(Alignment::Center)
         .block(
             Block::default()
@@ -150,14 +323,27 @@ pub fn home<'a>(state: &mut ProgState) -> Paragraph<'a> {
         home
     }
     else {
-        let mut server_status = Vec::<Spans>::new();
-        let mut i = 0;
-        for server in servers {
-            state.ark_server_list_state.select(Some(i));
-            let status = status_ark_server(state).unwrap();
-            let line = server.name + ":     " + &status.to_string();
-            server_status.push(Spans::from(vec![Span::raw(line)]));
-            i = i+1;
+        let mut server_status = lines;
+        let groups = servers_by_host(state);
+        let show_hosts = groups.iter().any(|(host, _)| !host.is_empty());
+        for (host, indexes) in groups {
+            if show_hosts {
+                server_status.push(Spans::from(vec![Span::styled(
+                    host_label(&host).to_string(),
+                    Style::default().fg(Color::LightCyan).add_modifier(Modifier::BOLD),
+                )]));
+            }
+            for i in indexes {
+                let server = &servers[i];
+                let status = cached_status(state, server.id);
+                let indent = if show_hosts { "  " } else { "" };
+                let mut line = vec![Span::raw(format!("{}{}:     ", indent, server.name)), status_span(&status)];
+                if let Some(uptime) = status.uptime() {
+                    line.push(Span::styled(format!("  up {}", uptime), Style::default().fg(Color::DarkGray)));
+                }
+                line.extend(pending_span(state, server.id));
+                server_status.push(Spans::from(line));
+            }
         }
         let home = Paragraph::new(server_status)
         .block(
@@ -171,10 +357,248 @@ pub fn home<'a>(state: &mut ProgState) -> Paragraph<'a> {
     }
 }
 
+//The audit log newest first, narrowed by the filter, with the full record of the selected entry below.
+pub fn audit<'a>(state: &ProgState) -> (Table<'a>, Paragraph<'a>) {
+    let entries = filter_entries(&state.audit_entries, &state.audit_filter);
+    let rows: Vec<Row> = entries
+        .iter()
+        .map(|entry| {
+            Row::new(vec![
+                Cell::from(Span::raw(entry.timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())),
+                Cell::from(Span::raw(entry.user.clone())),
+                Cell::from(Span::raw(entry.action.clone())),
+            ])
+        })
+        .collect();
+
+    let filter_title = if state.editing_audit_filter {
+        format!("Audit Log (filter: {}_)", state.audit_filter)
+    } else if state.audit_filter.is_empty() {
+        "Audit Log (/: filter)".to_string()
+    } else {
+        format!("Audit Log (filter: {})", state.audit_filter)
+    };
+    let table = Table::new(rows)
+        .header(Row::new(vec![
+            Cell::from(Span::styled("Time", Style::default().add_modifier(Modifier::BOLD))),
+            Cell::from(Span::styled("User", Style::default().add_modifier(Modifier::BOLD))),
+            Cell::from(Span::styled("Action", Style::default().add_modifier(Modifier::BOLD))),
+        ]))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(filter_title)
+                .border_type(BorderType::Plain),
+        )
+        .highlight_style(
+            Style::default()
+                .bg(Color::Yellow)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD),
+        )
+        .widths(&[
+            Constraint::Percentage(20),
+            Constraint::Percentage(10),
+            Constraint::Percentage(70),
+        ]);
+
+    let mut lines = Vec::new();
+    if let Some(entry) = state.audit_list_state.selected().and_then(|i| entries.get(i)) {
+        let fields = [("Before", entry.before.as_ref()), ("After", entry.after.as_ref())];
+        for (label, value) in fields.iter() {
+            if let Some(value) = value {
+                lines.push(Spans::from(vec![Span::styled(format!("{}:", label), Style::default().add_modifier(Modifier::BOLD))]));
+                lines.push(Spans::from(vec![Span::raw(value.to_string())]));
+            }
+        }
+        if let Some(output) = &entry.output {
+            lines.push(Spans::from(vec![Span::styled("Output:", Style::default().add_modifier(Modifier::BOLD))]));
+            lines.extend(output.lines().map(|line| Spans::from(vec![Span::raw(line.to_string())])));
+        }
+    }
+    let detail = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title("Entry Detail")
+                .border_type(BorderType::Plain),
+        );
+
+    (table, detail)
+}
+
+//Undoable changes newest first, with undone changes that can still be redone greyed out above them.
+pub fn history<'a>(state: &ProgState) -> Paragraph<'a> {
+    let mut lines: Vec<Spans> = state.history
+        .redo_entries()
+        .map(|change| Spans::from(vec![Span::styled(change.describe(), Style::default().fg(Color::DarkGray))]))
+        .collect();
+    lines.extend(
+        state.history
+            .undo_entries()
+            .map(|change| Spans::from(vec![Span::raw(change.describe())]))
+    );
+    Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title("History (u: undo, ^R: redo)")
+                .border_type(BorderType::Plain),
+        )
+}
+
+//The last few lines the selected server's backend has logged, as many as fit the pane.
+pub fn recent_logs<'a>(state: &ProgState, height: u16) -> Paragraph<'a> {
+    let lines = state.recent_logs.as_ref().map(|tail| tail.snapshot()).unwrap_or_default();
+    let logs = lines[lines.len().saturating_sub(height.saturating_sub(2) as usize)..].join("\n");
+    Paragraph::new(logs)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title("Recent Logs")
+                .border_type(BorderType::Plain),
+        )
+}
+
+//The followed log, one screen of it, coloured by level with search matches picked out.
+pub fn logs<'a>(state: &ProgState) -> Paragraph<'a> {
+    let lines = displayed_log_lines(state);
+    let matches = find_matches(&lines, &state.log_search);
+    let top = log_top(state, lines.len());
+    let shown: Vec<Spans> = lines
+        .iter()
+        .enumerate()
+        .skip(top)
+        .take(state.log_height)
+        .map(|(i, line)| {
+            let mut style = match LogLevel::of(line) {
+                LogLevel::Error => Style::default().fg(Color::Red),
+                LogLevel::Warning => Style::default().fg(Color::Yellow),
+                LogLevel::Debug => Style::default().fg(Color::DarkGray),
+                LogLevel::Info => Style::default().fg(Color::White),
+            };
+            if state.log_match == Some(i) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            log_line_spans(line, &state.log_search, style)
+        })
+        .collect();
+
+    let server_name = state.log_tail
+        .as_ref()
+        .and_then(|tail| state.fleet.servers().iter().find(|s| s.id == tail.server_id))
+        .map_or(String::new(), |s| s.name.clone());
+    let mut title = vec![format!("Logs: {}", server_name)];
+    title.push(if state.log_follow {
+        "following".to_string()
+    } else {
+        format!("line {} of {}", (top + 1).min(lines.len()), lines.len())
+    });
+    if let (Some(paused), Some(tail)) = (&state.log_paused, &state.log_tail) {
+        title.push(format!("paused, {} new lines", tail.snapshot().len().saturating_sub(paused.len())));
+    }
+    if state.editing_log_search {
+        title.push(format!("search: {}_", state.log_search));
+    } else if !state.log_search.is_empty() {
+        title.push(format!("search: {}, {} matches, n/N: next/previous", state.log_search, matches.len()));
+    }
+    Paragraph::new(shown)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(title.join(" | "))
+                .border_type(BorderType::Plain),
+        )
+}
+
+//A log line with every occurrence of the search text highlighted, ignoring ASCII case.
+fn log_line_spans<'a>(line: &str, search: &str, style: Style) -> Spans<'a> {
+    if search.is_empty() {
+        return Spans::from(vec![Span::styled(line.to_string(), style)])
+    }
+    let lowercase = line.to_ascii_lowercase();
+    let search = search.to_ascii_lowercase();
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while let Some(found) = lowercase[start..].find(&search) {
+        let found = start + found;
+        spans.push(Span::styled(line[start..found].to_string(), style));
+        spans.push(Span::styled(line[found..found + search.len()].to_string(), style.bg(Color::Yellow).fg(Color::Black)));
+        start = found + search.len();
+    }
+    spans.push(Span::styled(line[start..].to_string(), style));
+    Spans::from(spans)
+}
+
+//A spinner and e.g. "stopping..." while a service action on the server is running in the background.
+fn pending_span<'a>(state: &ProgState, server_id: usize) -> Option<Span<'a>> {
+    const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+    state.pending_actions.get(&server_id).map(|action| {
+        Span::styled(
+            format!("  {} {}", SPINNER[state.spinner_frame % SPINNER.len()], action.in_progress()),
+            Style::default().fg(Color::Yellow),
+        )
+    })
+}
+
+fn enabled_str(enabled: bool) -> String {
+    match enabled {
+        true => "yes".to_string(),
+        false => "no".to_string(),
+    }
+}
+
+//Empty until a scan has found the mod on disk.
+fn size_str(size: u64) -> String {
+    match size {
+        0 => "".to_string(),
+        size => format_bytes(size),
+    }
+}
+
+fn host_label(host: &str) -> &str {
+    match host {
+        "" => "local",
+        host => host,
+    }
+}
+
+//The last status the poller saw, or unknown until its first round for the server is in.
+fn cached_status(state: &ProgState, server_id: usize) -> ServiceState {
+    state.status_poller
+        .get(server_id)
+        .map(|polled| polled.state)
+        .unwrap_or_else(|| ServiceState::new(ServiceStatus::Unknown("checking".to_string())))
+}
+
+//The status word, coloured so a stopped or failed server stands out in a list.
+fn status_span<'a>(status: &ServiceState) -> Span<'a> {
+    let color = match status.status {
+        ServiceStatus::Active => Color::Green,
+        ServiceStatus::Inactive => Color::DarkGray,
+        ServiceStatus::Failed => Color::Red,
+        ServiceStatus::Activating | ServiceStatus::Deactivating => Color::Yellow,
+        ServiceStatus::Unknown(_) => Color::Magenta,
+    };
+    Span::styled(status.status.to_string(), Style::default().fg(color))
+}
+
 pub fn view_ark_server<'a>(state: &ProgState) -> Table<'a> {
     let selected_ark_server = get_server(state).expect("Server exists");
     let mods_str = get_server_mods_str(state).expect("Mod str exists");
-    let server_status = status_ark_server(state).unwrap();
+    let polled = state.status_poller.get(selected_ark_server.id);
+    let server_status = cached_status(state, selected_ark_server.id);
+    let details = match &polled {
+        Some(polled) if server_status.details().is_empty() => polled.age(),
+        Some(polled) => format!("{} ({})", server_status.details(), polled.age()),
+        None => String::new(),
+    };
 
     let ark_server_detail = Table::new(vec![
         Row::new(vec![
@@ -197,6 +621,22 @@ pub fn view_ark_server<'a>(state: &ProgState) -> Table<'a> {
             Cell::from(Span::raw("Service Name:".to_string())),
             Cell::from(Span::raw(selected_ark_server.service_name.clone())),
         ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Backend:".to_string())),
+            Cell::from(Span::raw(format!("{} on {}", selected_ark_server.backend, host_label(&selected_ark_server.host)))),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Executable:".to_string())),
+            Cell::from(Span::raw(format!("{} {}", selected_ark_server.executable, selected_ark_server.arguments))),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Container:".to_string())),
+            Cell::from(Span::raw(format!("{} {}", selected_ark_server.container, selected_ark_server.image))),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Config Dir:".to_string())),
+            Cell::from(Span::raw(selected_ark_server.config_dir.clone())),
+        ]),
         Row::new(vec![
             Cell::from(Span::raw("Created At:".to_string())),
             Cell::from(Span::raw(selected_ark_server.created_at.to_string())),
@@ -207,7 +647,13 @@ pub fn view_ark_server<'a>(state: &ProgState) -> Table<'a> {
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Status:".to_string())),
-            Cell::from(Span::raw(server_status)),
+            Cell::from(Spans::from(
+                std::iter::once(status_span(&server_status)).chain(pending_span(state, selected_ark_server.id)).collect::<Vec<_>>()
+            )),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Details:".to_string())),
+            Cell::from(Span::raw(details)),
         ]),
     ])
     .block(
@@ -225,6 +671,17 @@ pub fn view_ark_server<'a>(state: &ProgState) -> Table<'a> {
     ark_server_detail
 }
 
+//The value being edited, followed by the reason it was rejected if it was.
+fn edit_cell<'a>(value: &str, error: Option<&String>) -> Cell<'a> {
+    match error {
+        Some(error) => Cell::from(Spans::from(vec![
+            Span::raw(value.to_string()),
+            Span::styled(format!("  <- {}", error), Style::default().fg(Color::Red)),
+        ])),
+        None => Cell::from(Span::raw(value.to_string())),
+    }
+}
+
 pub fn edit_ark_server<'a>(state: &ProgState) -> Table<'a> {
     let sel = state.get_server_edit_index();
 
@@ -233,30 +690,59 @@ pub fn edit_ark_server<'a>(state: &ProgState) -> Table<'a> {
         vals[sel] = state.tmp_server_field.to_string();
     }
 
+    let error = if state.editing_server { state.edit_error.as_ref() } else { None };
     let ark_server_detail = Table::new(vec![
         Row::new(vec![
             Cell::from(Span::raw("ID:".to_string())),
-            Cell::from(Span::raw(vals[0].clone())),
+            edit_cell(&vals[0], error.filter(|_| sel == 0)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Name:".to_string())),
-            Cell::from(Span::raw(vals[1].clone())),
+            edit_cell(&vals[1], error.filter(|_| sel == 1)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Category:".to_string())),
-            Cell::from(Span::raw(vals[2].clone())),
+            edit_cell(&vals[2], error.filter(|_| sel == 2)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Age:".to_string())),
-            Cell::from(Span::raw(vals[3].clone())),
+            edit_cell(&vals[3], error.filter(|_| sel == 3)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Service Name:".to_string())),
-            Cell::from(Span::raw(vals[4].clone())),
+            edit_cell(&vals[4], error.filter(|_| sel == 4)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Backend:".to_string())),
+            edit_cell(&vals[5], error.filter(|_| sel == 5)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Executable:".to_string())),
+            edit_cell(&vals[6], error.filter(|_| sel == 6)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Arguments:".to_string())),
+            edit_cell(&vals[7], error.filter(|_| sel == 7)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Container:".to_string())),
+            edit_cell(&vals[8], error.filter(|_| sel == 8)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Image:".to_string())),
+            edit_cell(&vals[9], error.filter(|_| sel == 9)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Host:".to_string())),
+            edit_cell(&vals[10], error.filter(|_| sel == 10)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Config Dir:".to_string())),
+            edit_cell(&vals[11], error.filter(|_| sel == 11)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Created At:".to_string())),
-            Cell::from(Span::raw(vals[5].clone())),
+            Cell::from(Span::raw(vals[12].clone())),
         ]),
     ])
     .block(
@@ -288,24 +774,23 @@ pub fn edit_ark_server_mod<'a>(state: &ProgState) -> Table<'a> {
         vals[sel] = state.tmp_mod_field.to_string();
     }
 
-
-
+    let error = if state.editing_mod { state.edit_error.as_ref() } else { None };
     let ark_server_mod_detail = Table::new(vec![
         Row::new(vec![
             Cell::from(Span::raw("ID:".to_string())),
-            Cell::from(Span::raw(vals[0].clone())),
+            edit_cell(&vals[0], error.filter(|_| sel == 0)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Name:".to_string())),
-            Cell::from(Span::raw(vals[1].clone())),
+            edit_cell(&vals[1], error.filter(|_| sel == 1)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Category:".to_string())),
-            Cell::from(Span::raw(vals[2].clone())),
+            edit_cell(&vals[2], error.filter(|_| sel == 2)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Age:".to_string())),
-            Cell::from(Span::raw(vals[3].clone())),
+            edit_cell(&vals[3], error.filter(|_| sel == 3)),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Created At:".to_string())),
@@ -333,25 +818,8 @@ pub fn edit_ark_server_mod<'a>(state: &ProgState) -> Table<'a> {
     ark_server_mod_detail
 }
 
-pub fn view_ark_server_mod<'a>(ark_server_list_state: &ListState, ark_server_mod_list_state: &ListState) -> Table<'a> {
-    let ark_server_list = get_servers().expect("can fetch ark_server list");
-    let selected_ark_server = ark_server_list
-        .get(
-            ark_server_list_state
-                .selected()
-                .expect("there is always a selected ark_server"),
-        )
-        .expect("exists")
-        .clone();
-
-    let selected_ark_server_mod = selected_ark_server.mods
-        .get(
-            ark_server_mod_list_state
-                .selected()
-                .expect("there is always a selected ark_server"),
-        )
-        .expect("exists")
-        .clone();
+pub fn view_ark_server_mod<'a>(state: &ProgState) -> Table<'a> {
+    let selected_ark_server_mod = get_server_mod(state).expect("server mod exists");
 
     let ark_server_mod_detail = Table::new(vec![
         Row::new(vec![
@@ -370,6 +838,18 @@ pub fn view_ark_server_mod<'a>(ark_server_list_state: &ListState, ark_server_mod
             Cell::from(Span::raw("Age:".to_string())),
             Cell::from(Span::raw(selected_ark_server_mod.age.to_string())),
         ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Enabled:".to_string())),
+            Cell::from(Span::raw(enabled_str(selected_ark_server_mod.enabled))),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Version:".to_string())),
+            Cell::from(Span::raw(selected_ark_server_mod.version)),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Size:".to_string())),
+            Cell::from(Span::raw(size_str(selected_ark_server_mod.size))),
+        ]),
         Row::new(vec![
             Cell::from(Span::raw("Created At:".to_string())),
             Cell::from(Span::raw(selected_ark_server_mod.created_at.to_string())),
@@ -390,11 +870,84 @@ pub fn view_ark_server_mod<'a>(ark_server_list_state: &ListState, ark_server_mod
     ark_server_mod_detail
 }
 
+//The sections of the open INI file on the left and the keys of the selected one on the right.
+//The pane that Up and Down move through is highlighted, the other one only shows where it is.
+pub fn game_config<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
+    let focused = Style::default().bg(Color::Yellow).fg(Color::Black).add_modifier(Modifier::BOLD);
+    let unfocused = Style::default().bg(Color::DarkGray);
+    let editor = match &state.ini_editor {
+        Some(editor) => editor,
+        None => return (List::new(Vec::<ListItem>::new()), Table::new(Vec::<Row>::new())),
+    };
+
+    let items: Vec<_> = editor.doc
+        .sections()
+        .into_iter()
+        .map(|section| match section.as_str() {
+            "" => ListItem::new(Spans::from(vec![Span::styled("(no section)", Style::default().fg(Color::DarkGray))])),
+            _ => ListItem::new(Spans::from(vec![Span::raw(section)])),
+        })
+        .collect();
+    let modified = if editor.is_modified() { " (modified, w to write)" } else { "" };
+    let missing = if editor.loaded.is_none() { " (new file)" } else { "" };
+    let sections = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(format!("{} on {}{}{}", editor.file.file_name(), host_label(&editor.host), missing, modified))
+                .border_type(BorderType::Plain),
+        )
+        .highlight_style(if editor.entries_focused { unfocused } else { focused });
+
+    let section = editor.selected_section().unwrap_or_default();
+    let selected = editor.entry_state.selected();
+    let error = state.edit_error.as_ref().filter(|_| state.editing_ini);
+    let mut rows: Vec<Row> = editor.doc
+        .entries(&section)
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let value = if state.editing_ini && !editor.adding && selected == Some(i) {
+                edit_cell(&state.tmp_ini_field, error)
+            } else {
+                Cell::from(Span::raw(entry.value))
+            };
+            Row::new(vec![Cell::from(Span::raw(format!("{}{}", entry.op.prefix(), entry.key))), value])
+        })
+        .collect();
+    if state.editing_ini && editor.adding {
+        rows.push(Row::new(vec![Cell::from(Span::raw("new Key=Value:")), edit_cell(&state.tmp_ini_field, error)]));
+    }
+    let mut title = vec![section.clone()];
+    //Array keys are built up over several lines, say what they add up to.
+    if let Some(entry) = editor.selected_entry().filter(|entry| entry.op != EntryOp::Set) {
+        title.push(format!("{}: {} value(s) in effect", entry.key, editor.doc.get(&section, &entry.key).len()));
+    }
+    title.push(format!("Tab: {}", editor.file.other().file_name()));
+    title.push("Left/Right: sections/keys".to_string());
+    let entries = Table::new(rows)
+        .header(Row::new(vec![
+            Cell::from(Span::styled("Key", Style::default().add_modifier(Modifier::BOLD))),
+            Cell::from(Span::styled("Value", Style::default().add_modifier(Modifier::BOLD))),
+        ]))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(title.join(" | "))
+                .border_type(BorderType::Plain),
+        )
+        .highlight_style(if editor.entries_focused { focused } else { unfocused })
+        .widths(&[Constraint::Percentage(40), Constraint::Percentage(60)]);
+    (sections, entries)
+}
+
 pub fn ark_server_mods<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
     let ark_server_mods = Block::default()
         .borders(Borders::ALL)
         .style(Style::default().fg(Color::White))
-        .title("Server Mods")
+        .title("Load Order (< > ^ to move)")
         .border_type(BorderType::Plain);
 
 
@@ -402,11 +955,16 @@ pub fn ark_server_mods<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
 
     let items: Vec<_> = selected_ark_server.mods
         .iter()
-        .map(|ark_server| {
-            ListItem::new(Spans::from(vec![Span::styled(
-                ark_server.name.clone(),
-                Style::default(),
-            )]))
+        .enumerate()
+        .map(|(i, ark_server_mod)| {
+            let mut line = vec![match ark_server_mod.enabled {
+                true => Span::styled(format!("{}. [x] {}", i + 1, ark_server_mod.name), Style::default()),
+                false => Span::styled(format!("{}. [ ] {}", i + 1, ark_server_mod.name), Style::default().fg(Color::DarkGray)),
+            }];
+            if state.mod_scan.as_ref().is_some_and(|scan| scan.is_missing(selected_ark_server.id, ark_server_mod.id)) {
+                line.push(Span::styled(" (not installed)", Style::default().fg(Color::Red)));
+            }
+            ListItem::new(Spans::from(line))
         })
         .collect();
 
@@ -417,25 +975,48 @@ pub fn ark_server_mods<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
             .add_modifier(Modifier::BOLD),
     );
 
-    let selected_ark_server_mod = get_server_mod(state).expect("server mod exists");
-
-    let ark_server_mod_detail = if selected_ark_server_mod.id != 0 {
-        Table::new(vec![Row::new(vec![
+    let mut rows = vec![if let Ok(selected_ark_server_mod) = get_server_mod(state) {
+        Row::new(vec![
             Cell::from(Span::raw(selected_ark_server_mod.id.to_string())),
             Cell::from(Span::raw(selected_ark_server_mod.name)),
             Cell::from(Span::raw(selected_ark_server_mod.category)),
             Cell::from(Span::raw(selected_ark_server_mod.age.to_string())),
+            Cell::from(Span::raw(enabled_str(selected_ark_server_mod.enabled))),
+            Cell::from(Span::raw(selected_ark_server_mod.version)),
+            Cell::from(Span::raw(size_str(selected_ark_server_mod.size))),
             Cell::from(Span::raw(selected_ark_server_mod.created_at.to_string())),
-        ])])
+        ])
     } else {
-        Table::new(vec![Row::new(vec![
+        Row::new(vec![
             Cell::from(Span::raw("".to_string())),
             Cell::from(Span::raw("".to_string())),
             Cell::from(Span::raw("".to_string())),
             Cell::from(Span::raw("".to_string())),
             Cell::from(Span::raw("".to_string())),
-        ])])
+            Cell::from(Span::raw("".to_string())),
+            Cell::from(Span::raw("".to_string())),
+            Cell::from(Span::raw("".to_string())),
+        ])
+    }];
+    //Mods the last scan found installed that are not on the list, so they can be added or cleaned up.
+    let mut title = "Mod Detail".to_string();
+    if let Some(scan) = state.mod_scan.as_ref().filter(|scan| scan.server_id == selected_ark_server.id && !scan.unlisted.is_empty()) {
+        title += &format!(" | installed but not listed: {}", scan.unlisted.len());
+        rows.push(Row::new(vec![Cell::from(Span::raw(""))]));
+        for installed in &scan.unlisted {
+            rows.push(Row::new(vec![
+                Cell::from(Span::raw(installed.id.to_string())),
+                Cell::from(Span::raw(installed.name.clone())),
+                Cell::from(Span::raw("")),
+                Cell::from(Span::raw("")),
+                Cell::from(Span::raw("not listed")),
+                Cell::from(Span::raw(installed.version.clone())),
+                Cell::from(Span::raw(size_str(installed.size))),
+                Cell::from(Span::raw("")),
+            ]).style(Style::default().fg(Color::Yellow)));
+        }
     }
+    let ark_server_mod_detail = Table::new(rows)
     .header(Row::new(vec![
         Cell::from(Span::styled(
             "ID",
@@ -453,6 +1034,18 @@ pub fn ark_server_mods<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
             "Age",
             Style::default().add_modifier(Modifier::BOLD),
         )),
+        Cell::from(Span::styled(
+            "Enabled",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Cell::from(Span::styled(
+            "Version",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Cell::from(Span::styled(
+            "Size",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
         Cell::from(Span::styled(
             "Created At",
             Style::default().add_modifier(Modifier::BOLD),
@@ -462,56 +1055,83 @@ pub fn ark_server_mods<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
         Block::default()
             .borders(Borders::ALL)
             .style(Style::default().fg(Color::White))
-            .title("Mod Detail")
+            .title(title)
             .border_type(BorderType::Plain),
     )
     .widths(&[
+        Constraint::Percentage(10),
+        Constraint::Percentage(18),
+        Constraint::Percentage(12),
         Constraint::Percentage(5),
-        Constraint::Percentage(20),
-        Constraint::Percentage(20),
-        Constraint::Percentage(5),
+        Constraint::Percentage(9),
+        Constraint::Percentage(16),
+        Constraint::Percentage(10),
         Constraint::Percentage(20),
     ]);
     (list, ark_server_mod_detail)
 
 }
 
-pub fn ark_servers<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
+//The server list has a heading for each host once servers run on more than one machine, so the selection
+//is handed back as a position in the list rather than in the fleet.
+pub fn ark_servers<'a>(state: &ProgState) -> (List<'a>, Table<'a>, ListState) {
+    let title = match (&state.bulk, state.marked_servers.len()) {
+        (Some(bulk), _) => format!("Servers ({})", bulk.progress()),
+        (None, 0) => "Servers".to_string(),
+        (None, marked) => format!("Servers ({} marked)", marked),
+    };
     let ark_servers = Block::default()
         .borders(Borders::ALL)
         .style(Style::default().fg(Color::White))
-        .title("Servers")
+        .title(title)
         .border_type(BorderType::Plain);
 
-    let ark_server_list = get_servers().expect("Servers exist");
+    let ark_server_list = get_servers(state).expect("Servers exist");
+    let groups = servers_by_host(state);
+    let show_hosts = groups.iter().any(|(host, _)| !host.is_empty());
 
-    let items: Vec<_> = ark_server_list
-        .iter()
-        .map(|ark_server| {
-            ListItem::new(Spans::from(vec![Span::styled(
-                ark_server.name.clone(),
-                Style::default(),
-            )]))
-        })
-        .collect();
+    let mut items = Vec::new();
+    let mut list_state = ListState::default();
+    for (host, indexes) in groups {
+        if show_hosts {
+            items.push(ListItem::new(Spans::from(vec![Span::styled(
+                host_label(&host).to_string(),
+                Style::default().fg(Color::LightCyan).add_modifier(Modifier::BOLD),
+            )])));
+        }
+        for i in indexes {
+            let ark_server = &ark_server_list[i];
+            if state.ark_server_list_state.selected() == Some(i) {
+                list_state.select(Some(items.len()));
+            }
+            let indent = if show_hosts { "  " } else { "" };
+            let mark = if state.marked_servers.contains(&ark_server.id) { "* " } else { "" };
+            let mut line = vec![Span::styled(format!("{}{}{}", indent, mark, ark_server.name), Style::default())];
+            line.extend(pending_span(state, ark_server.id));
+            items.push(ListItem::new(Spans::from(line)));
+        }
+    }
 
-    let selected_ark_server = get_server(state).expect("Server exists");
     let list = List::new(items).block(ark_servers).highlight_style(
         Style::default()
             .bg(Color::Yellow)
             .fg(Color::Black)
             .add_modifier(Modifier::BOLD),
     );
-    let mods_str = selected_ark_server.mods.len().to_string();
-
-    let ark_server_detail = Table::new(vec![Row::new(vec![
-        Cell::from(Span::raw(selected_ark_server.id.to_string())),
-        Cell::from(Span::raw(selected_ark_server.name)),
-        Cell::from(Span::raw(selected_ark_server.category)),
-        Cell::from(Span::raw(selected_ark_server.age.to_string())),
-        Cell::from(Span::raw(selected_ark_server.created_at.to_string())),
-        Cell::from(Span::raw(mods_str)),
-    ])])
+
+    let ark_server_detail = if let Ok(selected_ark_server) = get_server(state) {
+        let mods_str = selected_ark_server.mods.len().to_string();
+        Table::new(vec![Row::new(vec![
+            Cell::from(Span::raw(selected_ark_server.id.to_string())),
+            Cell::from(Span::raw(selected_ark_server.name)),
+            Cell::from(Span::raw(selected_ark_server.category)),
+            Cell::from(Span::raw(selected_ark_server.age.to_string())),
+            Cell::from(Span::raw(selected_ark_server.created_at.to_string())),
+            Cell::from(Span::raw(mods_str)),
+        ])])
+    } else {
+        Table::new(vec![Row::new(vec![Cell::from(Span::raw("".to_string())); 6])])
+    }
     .header(Row::new(vec![
         Cell::from(Span::styled(
             "ID",
@@ -554,7 +1174,7 @@ pub fn ark_servers<'a>(state: &ProgState) -> (List<'a>, Table<'a>) {
         Constraint::Percentage(20),
     ]);
 
-    (list, ark_server_detail)
+    (list, ark_server_detail, list_state)
 }
 
 //    let headings = vec![
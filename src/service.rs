@@ -1,72 +1,678 @@
-use crate::ark::{Error, ProgState};
-use crate::db::{get_server_service_name};
-use std::process::Command;
-
-pub fn restart_ark_server(state: &ProgState) -> Result<String, Error> {
-    let service_name = get_server_service_name(state).expect("Service name exists");
-    let output = Command::new("systemctl")
-                          .arg("restart_ark_server")
-                          .arg(service_name.to_string())
-                          .output()
-                          .expect("command failed to start");
-
-
-    let cow = String::from_utf8_lossy(&output.stdout);
-    let mut s = cow.to_string();
-    if s == "" {
-        let cow2 = String::from_utf8_lossy(&output.stderr);
-        s = cow2.to_string();
-    }
-    Ok(s)
-}
-
-pub fn start_ark_server(state: &ProgState) -> Result<String, Error> {
-    let service_name = get_server_service_name(state).expect("Service name exists");
-    let output = Command::new("systemctl")
-                          .arg("start")
-                          .arg(service_name.to_string())
-                          .output()
-                          .expect("command failed to start");
-    let cow = String::from_utf8_lossy(&output.stdout);
-    let mut s = cow.to_string();
-    if s == "" {
-        let cow2 = String::from_utf8_lossy(&output.stderr);
-        s = cow2.to_string();
-    }
-    Ok(s)
-}
-
-
-pub fn stop_ark_server(state: &ProgState) -> Result<String, Error> {
-    let service_name = get_server_service_name(state).expect("Service name exists");
-    let output = Command::new("systemctl")
-                          .arg("stop")
-                          .arg(service_name.to_string())
-                          .output()
-                          .expect("command failed to start");
-
-
-    let cow = String::from_utf8_lossy(&output.stdout);
-    let mut s = cow.to_string();
-    if s == "" {
-        let cow2 = String::from_utf8_lossy(&output.stderr);
-        s = cow2.to_string();
-    }
-    Ok(s)
-}
-
-pub fn status_ark_server(state: &ProgState) -> Result<String, Error> {
-    let service_name = get_server_service_name(state).expect("Service name exists");
-    let output = Command::new("systemctl")
-                          .arg("is-active")
-                          .arg(service_name.to_string())
-                          .output()
-                          .expect("command failed to start");
-    let cow = String::from_utf8_lossy(&output.stdout);
-    let mut s = cow.to_string();
-    if s == "" {
-        let cow2 = String::from_utf8_lossy(&output.stderr);
-        s = cow2.to_string();
-    }
-    Ok(s)
+use crate::ark::{ArkServer, Error, Event, ProgState};
+use crate::audit::AuditEntry;
+use crate::container::ContainerBackend;
+use crate::db::ServerStore;
+use crate::host::{self, on_host};
+use crate::supervisor::SupervisorBackend;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+//Something that can run an ARK server process on our behalf.
+//Every call returns the text the process manager printed, which is shown to the user and kept in the audit log.
+//Calls can take as long as the server takes to stop, so they are made from worker threads.
+pub trait ServiceBackend: Send + Sync {
+    fn start(&self, ark_server: &ArkServer) -> Result<String, Error>;
+    fn stop(&self, ark_server: &ArkServer) -> Result<String, Error>;
+    fn restart(&self, ark_server: &ArkServer) -> Result<String, Error>;
+    fn status(&self, ark_server: &ArkServer) -> Result<ServiceState, Error>;
+    fn logs(&self, ark_server: &ArkServer, lines: usize) -> Result<String, Error>;
+    //A command that prints the last lines of the log and then keeps printing new ones as they are written.
+    //Backends without one have their logs polled instead.
+    fn follow(&self, _ark_server: &ArkServer, _lines: usize) -> Option<Command> {
+        None
+    }
+}
+
+//Lifecycle state of a server's process, named after systemd's ActiveState values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServiceStatus {
+    Active,
+    Inactive,
+    Failed,
+    Activating,
+    Deactivating,
+    Unknown(String),
+}
+
+impl ServiceStatus {
+    pub fn from_systemd(active_state: &str) -> ServiceStatus {
+        match active_state.trim() {
+            "active" | "reloading" => ServiceStatus::Active,
+            "inactive" => ServiceStatus::Inactive,
+            "failed" => ServiceStatus::Failed,
+            "activating" => ServiceStatus::Activating,
+            "deactivating" => ServiceStatus::Deactivating,
+            other => ServiceStatus::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ServiceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceStatus::Active => write!(f, "active"),
+            ServiceStatus::Inactive => write!(f, "inactive"),
+            ServiceStatus::Failed => write!(f, "failed"),
+            ServiceStatus::Activating => write!(f, "activating"),
+            ServiceStatus::Deactivating => write!(f, "deactivating"),
+            ServiceStatus::Unknown(state) if state.is_empty() => write!(f, "unknown"),
+            ServiceStatus::Unknown(state) => write!(f, "unknown ({})", state),
+        }
+    }
+}
+
+//Everything a backend could find out about a server's process. Details it cannot tell are left as None.
+#[derive(Clone, Debug)]
+pub struct ServiceState {
+    pub status: ServiceStatus,
+    pub main_pid: Option<u32>,
+    pub since: Option<DateTime<Utc>>,
+    pub restarts: Option<u32>,
+    pub memory_bytes: Option<u64>,
+    pub health: Option<String>,
+}
+
+impl ServiceState {
+    pub fn new(status: ServiceStatus) -> ServiceState {
+        ServiceState { status, main_pid: None, since: None, restarts: None, memory_bytes: None, health: None }
+    }
+
+    pub fn uptime(&self) -> Option<String> {
+        match self.status {
+            ServiceStatus::Active => self.since.map(|since| format_uptime(Utc::now().signed_duration_since(since))),
+            _ => None,
+        }
+    }
+
+    //e.g. "PID 1234, up 3h 12m, 2 restarts, 5.1 GiB, healthy".
+    pub fn details(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(pid) = self.main_pid {
+            details.push(format!("PID {}", pid));
+        }
+        if let Some(uptime) = self.uptime() {
+            details.push(format!("up {}", uptime));
+        }
+        if let Some(restarts) = self.restarts {
+            details.push(format!("{} restart{}", restarts, if restarts == 1 { "" } else { "s" }));
+        }
+        if let Some(memory) = self.memory_bytes {
+            details.push(format_bytes(memory));
+        }
+        if let Some(health) = &self.health {
+            details.push(health.clone());
+        }
+        details.join(", ")
+    }
+}
+
+pub fn format_uptime(uptime: chrono::Duration) -> String {
+    let minutes = uptime.num_minutes().max(0);
+    match (minutes / (24 * 60), minutes / 60 % 24, minutes % 60) {
+        (0, 0, m) => format!("{}m", m),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+//Which backend manages a server. Stored on each ArkServer, servers saved before this existed use systemd.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Systemd,
+    Supervisor,
+    Docker,
+    Podman,
+    Fake,
+}
+
+impl BackendKind {
+    pub fn from_name(name: &str) -> Option<BackendKind> {
+        match name.trim().to_lowercase().as_str() {
+            "systemd" => Some(BackendKind::Systemd),
+            "supervisor" => Some(BackendKind::Supervisor),
+            "docker" => Some(BackendKind::Docker),
+            "podman" => Some(BackendKind::Podman),
+            "fake" => Some(BackendKind::Fake),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendKind::Systemd => write!(f, "systemd"),
+            BackendKind::Supervisor => write!(f, "supervisor"),
+            BackendKind::Docker => write!(f, "docker"),
+            BackendKind::Podman => write!(f, "podman"),
+            BackendKind::Fake => write!(f, "fake"),
+        }
+    }
+}
+
+//One instance of every backend, so stateful ones like FakeBackend keep their state between calls.
+pub struct Backends {
+    systemd: SystemdBackend,
+    supervisor: SupervisorBackend,
+    docker: ContainerBackend,
+    podman: ContainerBackend,
+    fake: FakeBackend,
+}
+
+impl Backends {
+    //Backends that keep files of their own put them in run_dir.
+    pub fn new(run_dir: PathBuf) -> Backends {
+        Backends {
+            systemd: SystemdBackend,
+            supervisor: SupervisorBackend::new(run_dir),
+            docker: ContainerBackend::new("docker"),
+            podman: ContainerBackend::new("podman"),
+            fake: FakeBackend::default(),
+        }
+    }
+
+    pub fn get(&self, kind: BackendKind) -> &dyn ServiceBackend {
+        match kind {
+            BackendKind::Systemd => &self.systemd,
+            BackendKind::Supervisor => &self.supervisor,
+            BackendKind::Docker => &self.docker,
+            BackendKind::Podman => &self.podman,
+            BackendKind::Fake => &self.fake,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SystemdBackend;
+
+impl SystemdBackend {
+    fn systemctl(&self, action: &str, ark_server: &ArkServer) -> Result<String, Error> {
+        let mut command = Command::new("systemctl");
+        command.arg(action).arg(&ark_server.service_name);
+        run_command(&mut on_host(&ark_server.host, command))
+    }
+}
+
+impl ServiceBackend for SystemdBackend {
+    fn start(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.systemctl("start", ark_server)
+    }
+
+    fn stop(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.systemctl("stop", ark_server)
+    }
+
+    fn restart(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.systemctl("restart", ark_server)
+    }
+
+    fn status(&self, ark_server: &ArkServer) -> Result<ServiceState, Error> {
+        let mut command = Command::new("systemctl");
+        command
+            .arg("show")
+            .arg(&ark_server.service_name)
+            .arg("--property=ActiveState,MainPID,ActiveEnterTimestampMonotonic,NRestarts,MemoryCurrent");
+        let output = run_command(&mut on_host(&ark_server.host, command))?;
+        let uptime = host::read_file(&ark_server.host, Path::new("/proc/uptime"))?.unwrap_or_default();
+        Ok(parse_systemctl_show(&output, &uptime, Utc::now()))
+    }
+
+    fn logs(&self, ark_server: &ArkServer, lines: usize) -> Result<String, Error> {
+        let mut command = Command::new("journalctl");
+        command
+            .arg("--unit")
+            .arg(&ark_server.service_name)
+            .arg("--lines")
+            .arg(lines.to_string())
+            .arg("--no-pager");
+        run_command(&mut on_host(&ark_server.host, command))
+    }
+
+    fn follow(&self, ark_server: &ArkServer, lines: usize) -> Option<Command> {
+        let mut command = Command::new("journalctl");
+        command.arg("--unit").arg(&ark_server.service_name).arg("--lines").arg(lines.to_string()).arg("--follow").arg("--no-pager");
+        Some(on_host(&ark_server.host, command))
+    }
+}
+
+//The state described by systemctl show's Key=Value lines, given the host's /proc/uptime and the time it was read.
+//The wall clock timestamp comes in the host's local format, the monotonic one is microseconds since its boot.
+fn parse_systemctl_show(output: &str, uptime: &str, now: DateTime<Utc>) -> ServiceState {
+    let properties: HashMap<&str, &str> = output.lines().filter_map(|line| line.split_once('=')).collect();
+    let mut state = match properties.get("ActiveState") {
+        Some(active_state) => ServiceState::new(ServiceStatus::from_systemd(active_state)),
+        None => return ServiceState::new(ServiceStatus::Unknown(output.trim().to_string())),
+    };
+    state.main_pid = properties.get("MainPID").and_then(|pid| pid.parse().ok()).filter(|pid| *pid != 0);
+    state.restarts = properties.get("NRestarts").and_then(|n| n.parse().ok());
+    //Unset memory accounting shows up as [not set] or u64::MAX.
+    state.memory_bytes = properties.get("MemoryCurrent").and_then(|m| m.parse().ok()).filter(|m| *m != u64::MAX);
+    state.since = properties
+        .get("ActiveEnterTimestampMonotonic")
+        .and_then(|usec| usec.parse::<i64>().ok())
+        .filter(|usec| *usec > 0)
+        .and_then(|usec| {
+            let boot_uptime = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+            let active_for = chrono::Duration::milliseconds((boot_uptime * 1000.0) as i64 - usec / 1000);
+            Some(now - active_for)
+        });
+    state
+}
+
+//Pretends to manage servers without touching the system, for trying out the TUI and for tests.
+#[derive(Default)]
+pub struct FakeBackend {
+    //When each running server was started.
+    running: Mutex<HashMap<usize, DateTime<Utc>>>,
+    log: Mutex<Vec<String>>,
+}
+
+impl FakeBackend {
+    fn set_running(&self, ark_server: &ArkServer, running: bool, action: &str) -> Result<String, Error> {
+        let mut servers = self.running.lock().expect("fake backend lock poisoned");
+        if running {
+            servers.insert(ark_server.id, Utc::now());
+        } else {
+            servers.remove(&ark_server.id);
+        }
+        let line = format!("{} {}", action, ark_server.name);
+        self.log.lock().expect("fake backend lock poisoned").push(line.clone());
+        Ok(line)
+    }
+}
+
+impl ServiceBackend for FakeBackend {
+    fn start(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.set_running(ark_server, true, "Started")
+    }
+
+    fn stop(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.set_running(ark_server, false, "Stopped")
+    }
+
+    fn restart(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        self.set_running(ark_server, true, "Restarted")
+    }
+
+    fn status(&self, ark_server: &ArkServer) -> Result<ServiceState, Error> {
+        match self.running.lock().expect("fake backend lock poisoned").get(&ark_server.id) {
+            Some(since) => Ok(ServiceState { since: Some(*since), ..ServiceState::new(ServiceStatus::Active) }),
+            None => Ok(ServiceState::new(ServiceStatus::Inactive)),
+        }
+    }
+
+    fn logs(&self, ark_server: &ArkServer, lines: usize) -> Result<String, Error> {
+        let log = self.log.lock().expect("fake backend lock poisoned");
+        let server_lines: Vec<&String> = log.iter().filter(|line| line.ends_with(&ark_server.name)).collect();
+        let skip = server_lines.len().saturating_sub(lines);
+        Ok(server_lines[skip..].iter().map(|line| format!("{}\n", line)).collect())
+    }
+}
+
+//Run a command to completion and return its stdout, or an error saying why it did not succeed.
+pub fn run_command(command: &mut Command) -> Result<String, Error> {
+    let output = spawn_command(command)?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+    Err(command_error(command, &output))
+}
+
+//Why a command that ran exited unsuccessfully.
+pub fn command_error(command: &Command, output: &Output) -> Error {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let command = describe_command(command);
+    if is_permission_denied(&stderr) {
+        return Error::PermissionDeniedError { command, stderr }
+    }
+    Error::CommandFailedError { command, code: output.status.code(), stderr }
+}
+
+//Run a command whose exit status the caller wants to interpret itself. Only failing to start it is an error.
+pub fn spawn_command(command: &mut Command) -> Result<Output, Error> {
+    command.output().map_err(|source| Error::SpawnError { command: describe_command(command), source })
+}
+
+pub fn describe_command(command: &Command) -> String {
+    let mut words = vec![command.get_program().to_string_lossy().to_string()];
+    words.extend(command.get_args().map(|arg| arg.to_string_lossy().to_string()));
+    words.join(" ")
+}
+
+//systemctl without polkit rights, journalctl outside the journal groups and docker without access to its socket.
+fn is_permission_denied(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    ["access denied", "interactive authentication required", "permission denied", "not permitted"]
+        .iter()
+        .any(|message| stderr.contains(message))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl ServiceAction {
+    //Shown on the server while the action is running, e.g. "stopping...".
+    pub fn in_progress(&self) -> &'static str {
+        match self {
+            ServiceAction::Start => "starting...",
+            ServiceAction::Stop => "stopping...",
+            ServiceAction::Restart => "restarting...",
+        }
+    }
+}
+
+impl fmt::Display for ServiceAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceAction::Start => write!(f, "start"),
+            ServiceAction::Stop => write!(f, "stop"),
+            ServiceAction::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+//Posted back to the UI thread once a worker has finished a service action.
+pub struct ServiceOutcome {
+    pub ark_server: ArkServer,
+    pub action: ServiceAction,
+    pub result: Result<String, Error>,
+}
+
+//Run a service action on a worker thread. The server is marked as pending until finish_service_action sees
+//the outcome, and a second action on the same server is refused until then.
+pub fn dispatch_service_action(state: &mut ProgState, server_id: usize, action: ServiceAction) -> Result<(), Error> {
+    let ark_server = state.fleet.get_server(server_id)?;
+    if let Some(pending) = state.pending_actions.get(&server_id) {
+        return Err(Error::ServiceError(format!("server {} is still {}", ark_server.name, pending.in_progress())))
+    }
+    let backends = Arc::clone(&state.backends);
+    let run = move || {
+        let backend = backends.get(ark_server.backend);
+        let result = match action {
+            ServiceAction::Start => backend.start(&ark_server),
+            ServiceAction::Stop => backend.stop(&ark_server),
+            ServiceAction::Restart => backend.restart(&ark_server),
+        };
+        ServiceOutcome { ark_server, action, result }
+    };
+    match state.events.clone() {
+        Some(events) => {
+            state.pending_actions.insert(server_id, action);
+            thread::spawn(move || {
+                let _ = events.send(Event::ServiceDone(Box::new(run())));
+            });
+            Ok(())
+        }
+        //Without an event loop to report back to there is nothing to gain from a thread.
+        None => finish_service_action(state, run()),
+    }
+}
+
+//Clear the pending mark, log the outcome and hand back the error if the action failed.
+pub fn finish_service_action(state: &mut ProgState, outcome: ServiceOutcome) -> Result<(), Error> {
+    state.pending_actions.remove(&outcome.ark_server.id);
+    state.status_poller.refresh();
+    let output = match &outcome.result {
+        Ok(output) => output.clone(),
+        Err(e) => e.to_string(),
+    };
+    record_service_action(state, &outcome.action.to_string(), &outcome.ark_server, &output)?;
+    outcome.result.map(|_| ())
+}
+
+fn record_service_action(state: &ProgState, action: &str, ark_server: &ArkServer, output: &str) -> Result<(), Error> {
+    let via = match ark_server.service_name.as_str() {
+        "" => ark_server.backend.to_string(),
+        service_name => format!("{} {}", ark_server.backend, service_name),
+    };
+    let via = match ark_server.host.as_str() {
+        "" => via,
+        host => format!("{} on {}", via, host),
+    };
+    let entry = AuditEntry {
+        output: Some(output.to_string()),
+        ..AuditEntry::new(format!("Service {} for server {} via {}", action, ark_server.name, via))
+    };
+    state.audit.record(&entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::MemoryStore;
+    use crate::poller::StatusPoller;
+
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    fn fake_server(id: usize, name: &str) -> ArkServer {
+        ArkServer { id, backend: BackendKind::Fake, ..ArkServer::named(name) }
+    }
+
+    fn test_state(name: &str) -> (PathBuf, ProgState<'static>) {
+        let dir = std::env::temp_dir().join(format!("ark-manager-service-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("can create test dir");
+        let config = Config::for_path(dir.clone(), dir.join("db.json"));
+        let mut state = ProgState::new(config, Box::new(MemoryStore::new(vec![fake_server(1, "Island"), fake_server(2, "Ragnarok")], 3)));
+        state.load().expect("loads");
+        (dir, state)
+    }
+
+    fn status(state: &ProgState, server_id: usize) -> ServiceStatus {
+        let ark_server = state.fleet.get_server(server_id).expect("exists");
+        state.backends.get(ark_server.backend).status(&ark_server).expect("has a status").status
+    }
+
+    #[test]
+    fn fake_backend_tracks_each_server() {
+        let backend = FakeBackend::default();
+        let (island, ragnarok) = (fake_server(1, "Island"), fake_server(2, "Ragnarok"));
+        assert_eq!(backend.start(&island).expect("starts"), "Started Island");
+        assert_eq!(backend.status(&island).expect("has a status").status, ServiceStatus::Active);
+        assert!(backend.status(&island).expect("has a status").since.is_some());
+        assert_eq!(backend.status(&ragnarok).expect("has a status").status, ServiceStatus::Inactive);
+        backend.restart(&island).expect("restarts");
+        backend.stop(&island).expect("stops");
+        assert_eq!(backend.status(&island).expect("has a status").status, ServiceStatus::Inactive);
+        assert_eq!(backend.logs(&island, 2).expect("has logs"), "Restarted Island\nStopped Island\n");
+        assert_eq!(backend.logs(&ragnarok, 10).expect("has logs"), "");
+    }
+
+    #[test]
+    fn dispatch_runs_inline_without_an_event_loop() {
+        let (dir, mut state) = test_state("inline");
+        dispatch_service_action(&mut state, 1, ServiceAction::Start).expect("starts");
+        assert!(state.pending_actions.is_empty());
+        assert_eq!(status(&state, 1), ServiceStatus::Active);
+        assert_eq!(status(&state, 2), ServiceStatus::Inactive);
+        let entries = state.audit.read_all().expect("reads");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "Service start for server Island via fake");
+        assert_eq!(entries[0].output.as_deref(), Some("Started Island"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dispatch_posts_the_outcome_back() {
+        let (dir, mut state) = test_state("threaded");
+        let (tx, rx) = mpsc::channel();
+        state.events = Some(tx);
+        dispatch_service_action(&mut state, 2, ServiceAction::Restart).expect("dispatches");
+        assert_eq!(state.pending_actions.get(&2), Some(&ServiceAction::Restart));
+        assert!(matches!(dispatch_service_action(&mut state, 2, ServiceAction::Stop), Err(Error::ServiceError(_))));
+
+        let outcome = match rx.recv_timeout(Duration::from_secs(5)).expect("outcome posted") {
+            Event::ServiceDone(outcome) => outcome,
+            _ => panic!("expected a service outcome"),
+        };
+        assert_eq!(outcome.ark_server.id, 2);
+        finish_service_action(&mut state, *outcome).expect("succeeded");
+        assert!(state.pending_actions.is_empty());
+        assert_eq!(status(&state, 2), ServiceStatus::Active);
+        assert_eq!(state.audit.read_all().expect("reads")[0].output.as_deref(), Some("Restarted Ragnarok"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn poller_reports_fake_backend_statuses() {
+        let backends = Arc::new(Backends::new(std::env::temp_dir()));
+        let (island, ragnarok) = (fake_server(1, "Island"), fake_server(2, "Ragnarok"));
+        backends.get(BackendKind::Fake).start(&island).expect("starts");
+        let poller = Arc::new(StatusPoller::new(Duration::from_secs(60), Arc::clone(&backends)));
+        StatusPoller::spawn(&poller);
+        poller.watch(&backends, &[island, ragnarok]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while poller.get(2).is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(poller.get(1).expect("polled").state.status, ServiceStatus::Active);
+        assert_eq!(poller.get(2).expect("polled").state.status, ServiceStatus::Inactive);
+        assert!(poller.get(3).is_none());
+    }
+
+    #[test]
+    fn from_systemd_maps_active_states() {
+        assert_eq!(ServiceStatus::from_systemd("active"), ServiceStatus::Active);
+        assert_eq!(ServiceStatus::from_systemd("reloading"), ServiceStatus::Active);
+        assert_eq!(ServiceStatus::from_systemd("inactive\n"), ServiceStatus::Inactive);
+        assert_eq!(ServiceStatus::from_systemd("failed"), ServiceStatus::Failed);
+        assert_eq!(ServiceStatus::from_systemd("activating"), ServiceStatus::Activating);
+        assert_eq!(ServiceStatus::from_systemd("deactivating"), ServiceStatus::Deactivating);
+        assert_eq!(ServiceStatus::from_systemd("maintenance"), ServiceStatus::Unknown("maintenance".to_string()));
+    }
+
+    const UPTIME: &str = "7200.50 14000.00\n";
+
+    fn now() -> DateTime<Utc> {
+        "2021-06-01T12:00:00Z".parse().expect("valid timestamp")
+    }
+
+    #[test]
+    fn parses_active_service() {
+        let output = "ActiveState=active\nMainPID=4242\nActiveEnterTimestampMonotonic=3600500000\nNRestarts=2\nMemoryCurrent=5476083302\n";
+        let state = parse_systemctl_show(output, UPTIME, now());
+        assert_eq!(state.status, ServiceStatus::Active);
+        assert_eq!(state.main_pid, Some(4242));
+        assert_eq!(state.restarts, Some(2));
+        assert_eq!(state.memory_bytes, Some(5476083302));
+        //Up since an hour after boot, the host has been up two hours.
+        assert_eq!(state.since, Some(now() - chrono::Duration::hours(1)));
+    }
+
+    #[test]
+    fn parses_failed_service() {
+        let output = "ActiveState=failed\nMainPID=0\nActiveEnterTimestampMonotonic=3600500000\nNRestarts=5\nMemoryCurrent=[not set]\n";
+        let state = parse_systemctl_show(output, UPTIME, now());
+        assert_eq!(state.status, ServiceStatus::Failed);
+        assert_eq!(state.main_pid, None);
+        assert_eq!(state.restarts, Some(5));
+        assert_eq!(state.memory_bytes, None);
+        assert_eq!(state.uptime(), None);
+    }
+
+    #[test]
+    fn parses_activating_service() {
+        let output = "ActiveState=activating\nMainPID=4243\nActiveEnterTimestampMonotonic=0\nNRestarts=0\nMemoryCurrent=18446744073709551615\n";
+        let state = parse_systemctl_show(output, UPTIME, now());
+        assert_eq!(state.status, ServiceStatus::Activating);
+        assert_eq!(state.main_pid, Some(4243));
+        assert_eq!(state.since, None);
+        assert_eq!(state.memory_bytes, None);
+    }
+
+    #[test]
+    fn parses_inactive_service_with_exit_status() {
+        let output = "ActiveState=inactive\nMainPID=0\nExecMainStatus=143\nActiveEnterTimestampMonotonic=0\nNRestarts=0\nMemoryCurrent=[not set]\n";
+        let state = parse_systemctl_show(output, "", now());
+        assert_eq!(state.status, ServiceStatus::Inactive);
+        assert_eq!(state.main_pid, None);
+        assert_eq!(state.since, None);
+        assert_eq!(state.details(), "0 restarts");
+    }
+
+    #[test]
+    fn unexpected_output_is_unknown() {
+        let state = parse_systemctl_show("Failed to connect to bus\n", UPTIME, now());
+        assert_eq!(state.status, ServiceStatus::Unknown("Failed to connect to bus".to_string()));
+    }
+
+    fn output(status: i32, stderr: &str) -> Output {
+        use std::os::unix::process::ExitStatusExt;
+        Output { status: std::process::ExitStatus::from_raw(status), stdout: Vec::new(), stderr: stderr.as_bytes().to_vec() }
+    }
+
+    #[test]
+    fn command_errors_are_classified() {
+        let mut command = Command::new("systemctl");
+        command.arg("start").arg("arkserver");
+        //Wait statuses: the exit code is in the high byte, a signal in the low one. The expected exit code, or None
+        //for a permission error.
+        let cases = [
+            (1 << 8, "Failed to start arkserver.service: Unit arkserver.service not found.", Some(Some(1))),
+            (5 << 8, "", Some(Some(5))),
+            (9, "", Some(None)),
+            (1 << 8, "Failed to start arkserver.service: Access denied", None),
+            (1 << 8, "Failed to start arkserver.service: Interactive authentication required.\nSee system logs and 'systemctl status arkserver.service' for details.", None),
+            (1 << 8, "Hint: You are currently not seeing messages from other users and the system.\nPermission denied", None),
+            (1 << 8, "permission denied while trying to connect to the Docker daemon socket at unix:///var/run/docker.sock", None),
+            (1 << 8, "kill: (4242): Operation not permitted", None),
+        ];
+        for (status, stderr, expected) in cases {
+            match (command_error(&command, &output(status, stderr)), expected) {
+                (Error::CommandFailedError { command, code, stderr: reported }, Some(expected_code)) => {
+                    assert_eq!(command, "systemctl start arkserver");
+                    assert_eq!(code, expected_code, "{:?}", stderr);
+                    assert_eq!(reported, stderr.trim());
+                }
+                (Error::PermissionDeniedError { command, stderr: reported }, None) => {
+                    assert_eq!(command, "systemctl start arkserver");
+                    assert_eq!(reported, stderr.trim());
+                }
+                (e, _) => panic!("{:?} was classified as {:?}", stderr, e),
+            }
+        }
+    }
+
+    #[test]
+    fn permission_denied_messages() {
+        for (stderr, denied) in [
+            ("Failed to restart arkserver.service: Access denied", true),
+            ("Failed to stop arkserver.service: Interactive authentication required.", true),
+            ("PERMISSION DENIED", true),
+            ("Operation not permitted", true),
+            ("Unit arkserver.service not found.", false),
+            ("Error: No such container: ark-island", false),
+            ("", false),
+        ] {
+            assert_eq!(is_permission_denied(stderr), denied, "{:?}", stderr);
+        }
+    }
 }
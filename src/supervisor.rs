@@ -0,0 +1,328 @@
+use crate::ark::{ArkServer, Error};
+use crate::db::sibling_path;
+use crate::host::{self, on_host, script, shell_split};
+use crate::service::{run_command, ServiceBackend, ServiceState, ServiceStatus};
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//Rotate a server's log once it grows past this, keeping LOG_GENERATIONS old copies.
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+const LOG_GENERATIONS: usize = 5;
+//How long a server gets to save and exit after SIGTERM before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+//Where PID and log files go on remote hosts, relative to the home directory ssh starts in.
+const REMOTE_RUN_DIR: &str = ".arkmanager/run";
+
+//Runs ShooterGameServer directly for hosts without systemd.
+//Each server gets <id>.pid and <id>.log in the run directory next to the DB, so a server started by one
+//arkmanager session can still be seen and stopped by the next one. On remote hosts they go in ~/.arkmanager/run.
+//The PID file also holds the process start time, so a PID reused after a reboot is not taken for the server.
+pub struct SupervisorBackend {
+    run_dir: PathBuf,
+    //Servers started by this session, kept so they can be reaped once they exit.
+    children: Mutex<HashMap<usize, Child>>,
+}
+
+impl SupervisorBackend {
+    pub fn new(run_dir: PathBuf) -> SupervisorBackend {
+        SupervisorBackend { run_dir, children: Mutex::new(HashMap::new()) }
+    }
+
+    fn run_dir(&self, ark_server: &ArkServer) -> PathBuf {
+        match ark_server.host.as_str() {
+            "" => self.run_dir.clone(),
+            _ => PathBuf::from(REMOTE_RUN_DIR),
+        }
+    }
+
+    fn pid_path(&self, ark_server: &ArkServer) -> PathBuf {
+        self.run_dir(ark_server).join(format!("{}.pid", ark_server.id))
+    }
+
+    fn log_path(&self, ark_server: &ArkServer) -> PathBuf {
+        self.run_dir(ark_server).join(format!("{}.log", ark_server.id))
+    }
+
+    //The PID of the server if it is still running, tidying up after it if it is not.
+    fn running_pid(&self, ark_server: &ArkServer) -> Option<u32> {
+        let mut children = self.children.lock().expect("supervisor lock poisoned");
+        if let Some(child) = children.get_mut(&ark_server.id) {
+            if let Ok(Some(_)) = child.try_wait() {
+                children.remove(&ark_server.id);
+                let _ = host::remove_file(&ark_server.host, &self.pid_path(ark_server));
+                return None
+            }
+        }
+        drop(children);
+        let content = host::read_file(&ark_server.host, &self.pid_path(ark_server)).ok()??;
+        let mut fields = content.split_whitespace();
+        let pid = fields.next()?.parse::<u32>().ok()?;
+        let recorded = fields.next().and_then(|field| field.parse::<u64>().ok());
+        let is_server = match (start_time(&ark_server.host, pid), recorded) {
+            (Some(started), Some(recorded)) => started == recorded,
+            //PID files from before start times were recorded, fall back to what the process is running.
+            (Some(_), None) => runs_executable(&ark_server.host, pid, &ark_server.executable),
+            (None, _) => false,
+        };
+        if is_server {
+            Some(pid)
+        } else {
+            let _ = host::remove_file(&ark_server.host, &self.pid_path(ark_server));
+            None
+        }
+    }
+
+    //A process group of its own keeps the server running when the terminal closes or arkmanager exits.
+    fn spawn_local(&self, ark_server: &ArkServer, log_path: &Path) -> Result<u32, Error> {
+        let arguments = arguments(ark_server)?;
+        let log = OpenOptions::new().create(true).append(true).open(log_path)?;
+        let child = Command::new(&ark_server.executable)
+            .args(&arguments)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .process_group(0)
+            .spawn()
+            .map_err(|source| Error::SpawnError { command: ark_server.executable.clone(), source })?;
+        let pid = child.id();
+        self.children.lock().expect("supervisor lock poisoned").insert(ark_server.id, child);
+        Ok(pid)
+    }
+
+    //setsid detaches the server from the ssh session, and exec keeps its PID the one the shell reports.
+    fn spawn_remote(&self, ark_server: &ArkServer, log_path: &Path) -> Result<u32, Error> {
+        let log_path = log_path.to_string_lossy();
+        let arguments = arguments(ark_server)?;
+        let mut args = vec![log_path.as_ref(), ark_server.executable.as_str()];
+        //on_host quotes each one, so they reach the server as the same words.
+        args.extend(arguments.iter().map(String::as_str));
+        let output = run_command(&mut script(
+            &ark_server.host,
+            r#"log="$1"; shift; setsid "$@" < /dev/null >> "$log" 2>&1 & echo $!"#,
+            &args,
+        ))?;
+        output.trim().parse::<u32>().map_err(|_| {
+            Error::ServiceError(format!("could not tell the PID of {} on {} from {:?}", ark_server.name, ark_server.host, output.trim()))
+        })
+    }
+}
+
+impl ServiceBackend for SupervisorBackend {
+    fn start(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        if let Some(pid) = self.running_pid(ark_server) {
+            return Ok(format!("{} is already running as PID {}\n", ark_server.name, pid))
+        }
+        if ark_server.executable.trim().is_empty() {
+            return Err(Error::ServiceError(format!("server {} has no executable to run", ark_server.name)))
+        }
+        host::create_dir_all(&ark_server.host, &self.run_dir(ark_server))?;
+        let log_path = self.log_path(ark_server);
+        rotate_log(&ark_server.host, &log_path)?;
+        let pid = match ark_server.host.as_str() {
+            "" => self.spawn_local(ark_server, &log_path)?,
+            _ => self.spawn_remote(ark_server, &log_path)?,
+        };
+        //Without a start time the server has already exited, and the PID alone is never trusted.
+        let pid_file = match start_time(&ark_server.host, pid) {
+            Some(started) => format!("{} {}\n", pid, started),
+            None => format!("{}\n", pid),
+        };
+        host::write_file(&ark_server.host, &self.pid_path(ark_server), &pid_file)?;
+        Ok(format!("Started {} as PID {}\n", ark_server.name, pid))
+    }
+
+    fn stop(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        let pid = match self.running_pid(ark_server) {
+            Some(pid) => pid,
+            None => return Ok(format!("{} is not running\n", ark_server.name)),
+        };
+        signal(&ark_server.host, pid, "TERM")?;
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while self.running_pid(ark_server).is_some() {
+            if Instant::now() >= deadline {
+                signal(&ark_server.host, pid, "KILL")?;
+                let _ = host::remove_file(&ark_server.host, &self.pid_path(ark_server));
+                return Ok(format!("Killed {} (PID {}) after it ignored SIGTERM\n", ark_server.name, pid))
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        Ok(format!("Stopped {} (PID {})\n", ark_server.name, pid))
+    }
+
+    fn restart(&self, ark_server: &ArkServer) -> Result<String, Error> {
+        let stopped = self.stop(ark_server)?;
+        Ok(stopped + &self.start(ark_server)?)
+    }
+
+    fn status(&self, ark_server: &ArkServer) -> Result<ServiceState, Error> {
+        let pid = match self.running_pid(ark_server) {
+            Some(pid) => pid,
+            None => return Ok(ServiceState::new(ServiceStatus::Inactive)),
+        };
+        rotate_log(&ark_server.host, &self.log_path(ark_server))?;
+        Ok(ServiceState {
+            main_pid: Some(pid),
+            //The PID file is written right after the spawn.
+            since: host::metadata(&ark_server.host, &self.pid_path(ark_server))?.map(|(_, modified)| modified),
+            memory_bytes: resident_memory(&ark_server.host, pid),
+            ..ServiceState::new(ServiceStatus::Active)
+        })
+    }
+
+    fn logs(&self, ark_server: &ArkServer, lines: usize) -> Result<String, Error> {
+        let content = match host::read_file(&ark_server.host, &self.log_path(ark_server))? {
+            Some(content) => content,
+            None => return Ok("-- No entries --\n".to_string()),
+        };
+        let all: Vec<&str> = content.lines().collect();
+        let skip = all.len().saturating_sub(lines);
+        Ok(all[skip..].iter().map(|line| format!("{}\n", line)).collect())
+    }
+
+    //-F keeps following the log when it is truncated by a rotation, or before the server first creates it.
+    fn follow(&self, ark_server: &ArkServer, lines: usize) -> Option<Command> {
+        let mut command = Command::new("tail");
+        command.arg("-n").arg(lines.to_string()).arg("-F").arg(self.log_path(ark_server));
+        Some(on_host(&ark_server.host, command))
+    }
+}
+
+//The server's arguments split into words, honouring quotes, e.g. "SessionName=My Server" stays one argument.
+fn arguments(ark_server: &ArkServer) -> Result<Vec<String>, Error> {
+    shell_split(&ark_server.arguments)
+        .ok_or_else(|| Error::ServiceError(format!("the arguments of server {} have a quote that is not closed", ark_server.name)))
+}
+
+//When a live process started, in clock ticks since boot, or None if it is gone. A zombie still has a /proc entry,
+//so its state is checked as well.
+fn start_time(host: &str, pid: u32) -> Option<u64> {
+    let stat = host::read_file(host, Path::new(&format!("/proc/{}/stat", pid))).ok()??;
+    parse_start_time(&stat)
+}
+
+//The state and start time are fields 3 and 22 of /proc/<pid>/stat. They follow the command name, which is in
+//parentheses and may itself contain spaces.
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    match fields.first() {
+        Some(&"Z") | None => None,
+        Some(_) => fields.get(19)?.parse().ok(),
+    }
+}
+
+fn runs_executable(host: &str, pid: u32, executable: &str) -> bool {
+    match host::read_file(host, Path::new(&format!("/proc/{}/cmdline", pid))) {
+        Ok(Some(cmdline)) => cmdline.split('\0').next() == Some(executable),
+        _ => false,
+    }
+}
+
+fn resident_memory(host: &str, pid: u32) -> Option<u64> {
+    let status = host::read_file(host, Path::new(&format!("/proc/{}/status", pid))).ok()??;
+    let kib = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?.trim().trim_end_matches("kB").trim();
+    kib.parse::<u64>().ok().map(|kib| kib * 1024)
+}
+
+fn signal(host: &str, pid: u32, signal: &str) -> Result<(), Error> {
+    let mut command = Command::new("kill");
+    command.arg("-s").arg(signal).arg(pid.to_string());
+    run_command(&mut on_host(host, command)).map(|_| ())
+}
+
+//Copy the log to <log>.1, shifting older copies up, then truncate it in place.
+//The server holds the log open in append mode, so it carries on writing at the start of the emptied file.
+fn rotate_log(host: &str, path: &Path) -> Result<(), Error> {
+    match host::metadata(host, path)? {
+        Some((size, _)) if size > MAX_LOG_SIZE => {}
+        _ => return Ok(()),
+    }
+    for generation in (1..LOG_GENERATIONS).rev() {
+        let older = sibling_path(path, &generation.to_string());
+        if host::metadata(host, &older)?.is_some() {
+            host::rename(host, &older, &sibling_path(path, &(generation + 1).to_string()))?;
+        }
+    }
+    host::copy_truncate(host, path, &sibling_path(path, "1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn start_passes_quoted_arguments_as_words() {
+        let run_dir = std::env::temp_dir().join(format!("ark-manager-supervisor-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&run_dir);
+        let backend = SupervisorBackend::new(run_dir.clone());
+        let ark_server = ArkServer {
+            executable: "printf".to_string(),
+            arguments: r#"'%s\n' "TheIsland?SessionName=My Server" -Port=7777"#.to_string(),
+            ..ArkServer::named("Test")
+        };
+        backend.start(&ark_server).expect("starts");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backend.running_pid(&ark_server).is_some() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let log = fs::read_to_string(backend.log_path(&ark_server)).expect("log written");
+        assert_eq!(log.lines().collect::<Vec<_>>(), ["TheIsland?SessionName=My Server", "-Port=7777"]);
+        let _ = fs::remove_dir_all(&run_dir);
+    }
+
+    #[test]
+    fn start_refuses_open_quote() {
+        let run_dir = std::env::temp_dir().join(format!("ark-manager-supervisor-quote-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&run_dir);
+        let backend = SupervisorBackend::new(run_dir.clone());
+        let ark_server = ArkServer {
+            executable: "printf".to_string(),
+            arguments: "'SessionName=My Server".to_string(),
+            ..ArkServer::named("Test")
+        };
+        assert!(matches!(backend.start(&ark_server), Err(Error::ServiceError(_))));
+        assert!(!backend.pid_path(&ark_server).exists());
+        let _ = fs::remove_dir_all(&run_dir);
+    }
+
+    #[test]
+    fn parses_start_time_after_command_name() {
+        let stat = "4242 (Shooter Game) S 1 4242 4242 0 -1 4194560 100 0 0 0 5 3 0 0 20 0 12 0 987654 1000 200";
+        assert_eq!(parse_start_time(stat), Some(987654));
+        assert_eq!(parse_start_time(&stat.replace(") S ", ") Z ")), None);
+        assert_eq!(parse_start_time("4242 (ShooterGame) S 1"), None);
+        assert_eq!(parse_start_time(""), None);
+    }
+
+    #[test]
+    fn running_pid_checks_start_time() {
+        let run_dir = std::env::temp_dir().join(format!("ark-manager-supervisor-pid-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&run_dir);
+        fs::create_dir_all(&run_dir).expect("run dir created");
+        let backend = SupervisorBackend::new(run_dir.clone());
+        let ark_server = ArkServer::named("Test");
+        let pid = std::process::id();
+        let started = start_time("", pid).expect("own start time");
+
+        fs::write(backend.pid_path(&ark_server), format!("{} {}\n", pid, started)).expect("pid file written");
+        assert_eq!(backend.running_pid(&ark_server), Some(pid));
+
+        //Same PID, but a different process than the one that was started.
+        fs::write(backend.pid_path(&ark_server), format!("{} {}\n", pid, started + 1)).expect("pid file written");
+        assert_eq!(backend.running_pid(&ark_server), None);
+        assert!(!backend.pid_path(&ark_server).exists());
+
+        //A PID file without a start time is only trusted if the process runs the server executable.
+        fs::write(backend.pid_path(&ark_server), format!("{}\n", pid)).expect("pid file written");
+        assert_eq!(backend.running_pid(&ark_server), None);
+        let _ = fs::remove_dir_all(&run_dir);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::ark::{ArkServer, Error, ProgState};
+use crate::db::{commit, MemoryStore, ServerStore};
+use crate::history::Change;
+use crate::migrate::{migrate, SCHEMA_VERSION};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//Human friendly formats for keeping fleet definitions under version control.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, Error> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(Error::ConfigError(format!("unknown format {}, expected json, toml or yaml", name))),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| Format::from_name(ext).ok())
+    }
+}
+
+pub enum TransferCommand {
+    //Write the whole fleet, or a single server with its mods, to a file or stdout.
+    Export { format: Option<Format>, server_id: Option<usize>, output: Option<PathBuf> },
+    //Merge servers from a file into the fleet by ID. With replace, servers missing from the file are removed.
+    Import { path: PathBuf, format: Option<Format>, replace: bool, dry_run: bool },
+}
+
+//Same envelope as the DB file, minus the ID counter, so exports go through the usual migrations on import.
+#[derive(Serialize)]
+struct ExportFile<'a> {
+    schema_version: u64,
+    servers: &'a [ArkServer],
+}
+
+#[derive(Deserialize)]
+struct ImportFile {
+    servers: Vec<ArkServer>,
+}
+
+pub fn run(state: &mut ProgState, command: TransferCommand) -> Result<(), Error> {
+    match command {
+        TransferCommand::Export { format, server_id, output } => {
+            let servers = match server_id {
+                Some(server_id) => vec![state.fleet
+                    .get_server(server_id)
+                    .map_err(|_| Error::ConfigError(format!("there is no server with ID {}", server_id)))?],
+                None => state.fleet.servers().to_vec(),
+            };
+            let format = format
+                .or_else(|| output.as_deref().and_then(Format::from_path))
+                .unwrap_or(Format::Json);
+            let content = export(&servers, format)?;
+            match output {
+                Some(path) => fs::write(path, content)?,
+                None => print!("{}", content),
+            }
+        }
+        TransferCommand::Import { path, format, replace, dry_run } => {
+            let format = format.or_else(|| Format::from_path(&path)).unwrap_or(Format::Json);
+            let incoming = parse(&fs::read_to_string(&path)?, format)?;
+            let changes = plan_import(&state.fleet, incoming, replace)?;
+            if changes.is_empty() {
+                println!("Nothing to import, the fleet already matches {}", path.display());
+                return Ok(())
+            }
+            for change in &changes {
+                println!("{}", preview(change));
+            }
+            if dry_run {
+                println!("Dry run, {} change(s) not applied", changes.len());
+                return Ok(())
+            }
+            let num_changes = changes.len();
+            //One change for the whole import, so a single undo reverts it and a failure applies none of it.
+            let label = format!("Import {} change(s) from {}", num_changes, path.display());
+            commit(state, Change::Batch { label, changes })?;
+            println!("Applied {} change(s)", num_changes);
+        }
+    }
+    Ok(())
+}
+
+pub fn export(servers: &[ArkServer], format: Format) -> Result<String, Error> {
+    let file = ExportFile { schema_version: SCHEMA_VERSION, servers };
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(&file)? + "\n"),
+        Format::Toml => toml::to_string_pretty(&file).map_err(|e| Error::FormatError(e.to_string())),
+        Format::Yaml => serde_yaml::to_string(&file).map_err(|e| Error::FormatError(e.to_string())),
+    }
+}
+
+//Parse into a generic document first so exports and DB files from older versions are migrated like the DB itself.
+pub fn parse(content: &str, format: Format) -> Result<Vec<ArkServer>, Error> {
+    let mut doc: Value = match format {
+        Format::Json => serde_json::from_str(content)?,
+        Format::Toml => toml::from_str(content).map_err(|e| Error::FormatError(e.to_string()))?,
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| Error::FormatError(e.to_string()))?,
+    };
+    //Hand written files may leave the version out, assume they follow the current layout.
+    if let Some(fields) = doc.as_object_mut() {
+        fields.entry("schema_version").or_insert_with(|| SCHEMA_VERSION.into());
+    }
+    let parsed: ImportFile = serde_json::from_value(migrate(doc)?)?;
+    Ok(parsed.servers)
+}
+
+//Work out the changes needed to bring the fleet in line with the imported servers.
+//Servers are matched by ID. Those with ID 0 or an ID not in the fleet are added, with a fresh ID if needed.
+//The changes are applied to a scratch copy as they are planned so IDs and indexes line up when they are committed.
+pub fn plan_import(fleet: &MemoryStore, incoming: Vec<ArkServer>, replace: bool) -> Result<Vec<Change>, Error> {
+    let mut scratch = fleet.clone();
+    let mut changes = Vec::new();
+    let mut imported_ids = Vec::new();
+    for mut server in incoming {
+        if server.id != 0 && imported_ids.contains(&server.id) {
+            return Err(Error::DuplicateIdError(format!("server {} appears more than once in the import", server.id)))
+        }
+        let mut mod_ids = HashSet::new();
+        if let Some(ark_mod) = server.mods.iter().find(|m| !mod_ids.insert(m.id)) {
+            return Err(Error::DuplicateIdError(format!("mod {} is listed twice on imported server {}", ark_mod.id, server.name)))
+        }
+        let change = match scratch.get_server(server.id) {
+            Ok(before) => {
+                if serde_json::to_value(&before)? == serde_json::to_value(&server)? {
+                    imported_ids.push(server.id);
+                    continue
+                }
+                Change::UpdateServer { before, after: server.clone() }
+            }
+            Err(_) => {
+                if server.id == 0 {
+                    server.id = scratch.allocate_server_id();
+                }
+                Change::InsertServer { index: scratch.servers().len(), server: server.clone() }
+            }
+        };
+        imported_ids.push(server.id);
+        change.apply(&mut scratch)?;
+        changes.push(change);
+    }
+    if replace {
+        let removed: Vec<ArkServer> = scratch.servers()
+            .iter()
+            .filter(|s| !imported_ids.contains(&s.id))
+            .cloned()
+            .collect();
+        for server in removed {
+            let index = scratch.servers().iter().position(|s| s.id == server.id).unwrap_or(0);
+            let change = Change::DeleteServer { index, server };
+            change.apply(&mut scratch)?;
+            changes.push(change);
+        }
+    }
+    Ok(changes)
+}
+
+//One line per change, listing the fields that differ when an imported server conflicts with an existing one.
+pub fn preview(change: &Change) -> String {
+    match change {
+        Change::InsertServer { server, .. } => format!("+ add server {} ({})", server.id, server.name),
+        Change::DeleteServer { server, .. } => format!("- remove server {} ({})", server.id, server.name),
+        Change::UpdateServer { before, after } => {
+            let before_fields = serde_json::to_value(before).unwrap_or_default();
+            let after_fields = serde_json::to_value(after).unwrap_or_default();
+            let changed: Vec<String> = after_fields
+                .as_object()
+                .map(|fields| {
+                    fields.iter()
+                        .filter(|(key, value)| before_fields.get(key.as_str()) != Some(*value))
+                        .map(|(key, _)| key.clone())
+                        .collect()
+                })
+                .unwrap_or_default();
+            format!("~ update server {} ({}), conflicting fields: {}", after.id, after.name, changed.join(", "))
+        }
+        _ => change.describe(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ark::ArkServerMod;
+    use crate::config::Config;
+    use crate::db::undo;
+
+    use chrono::prelude::*;
+
+    //Fixed, so records built twice compare equal.
+    fn created_at() -> DateTime<Utc> {
+        "2021-06-01T00:00:00Z".parse().expect("valid timestamp")
+    }
+
+    fn island() -> ArkServer {
+        ArkServer {
+            id: 1,
+            created_at: created_at(),
+            service_name: "ark-island".to_string(),
+            host: "ark1.example".to_string(),
+            arguments: r#"TheIsland?SessionName="My Server" -Port=7777"#.to_string(),
+            mods: vec![
+                ArkServerMod { id: 731604991, created_at: created_at(), ..ArkServerMod::named("Structures Plus") },
+                ArkServerMod { id: 751991809, enabled: false, created_at: created_at(), ..ArkServerMod::named("Death Helper") },
+            ],
+            ..ArkServer::named("Island")
+        }
+    }
+
+    fn ragnarok() -> ArkServer {
+        ArkServer { id: 2, created_at: created_at(), ..ArkServer::named("Ragnarok") }
+    }
+
+    fn values(servers: &[ArkServer]) -> Value {
+        serde_json::to_value(servers).expect("serializes")
+    }
+
+    #[test]
+    fn export_and_parse_round_trip() {
+        let servers = vec![island(), ragnarok()];
+        for format in [Format::Json, Format::Toml, Format::Yaml] {
+            let content = export(&servers, format).expect("exports");
+            let parsed = parse(&content, format).expect("parses");
+            assert_eq!(values(&parsed), values(&servers), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn parse_accepts_files_without_a_version() {
+        let parsed = parse("[[servers]]\nid = 3\nname = \"Valguero\"\ncategory = \"\"\nage = 0\ncreated_at = \"2021-06-01T00:00:00Z\"\nmods = []\nservice_name = \"ark-valguero\"\n", Format::Toml)
+            .expect("parses");
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].name, "Valguero");
+        assert_eq!(parsed[0].service_name, "ark-valguero");
+        assert!(matches!(parse("servers: [", Format::Yaml), Err(Error::FormatError(_))));
+    }
+
+    #[test]
+    fn plan_import_adds_updates_and_skips_unchanged() {
+        let fleet = MemoryStore::new(vec![island(), ragnarok()], 3);
+        let renamed = ArkServer { name: "Ragnarok PvE".to_string(), ..ragnarok() };
+        let fresh = ArkServer { id: 0, ..ArkServer::named("Valguero") };
+        let changes = plan_import(&fleet, vec![island(), renamed, fresh], false).expect("plans");
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(&changes[0], Change::UpdateServer { before, after } if before.name == "Ragnarok" && after.name == "Ragnarok PvE"));
+        assert!(matches!(&changes[1], Change::InsertServer { index: 2, server } if server.id == 3 && server.name == "Valguero"));
+    }
+
+    #[test]
+    fn plan_import_with_replace_removes_missing_servers() {
+        let fleet = MemoryStore::new(vec![island(), ragnarok()], 3);
+        let changes = plan_import(&fleet, vec![ragnarok()], true).expect("plans");
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(&changes[0], Change::DeleteServer { index: 0, server } if server.id == 1));
+        assert!(plan_import(&fleet, vec![island(), ragnarok()], true).expect("plans").is_empty());
+    }
+
+    #[test]
+    fn plan_import_rejects_repeated_ids() {
+        let fleet = MemoryStore::default();
+        assert!(matches!(plan_import(&fleet, vec![island(), island()], false), Err(Error::DuplicateIdError(_))));
+        let mut repeated_mod = island();
+        repeated_mod.mods.push(repeated_mod.mods[0].clone());
+        assert!(matches!(plan_import(&fleet, vec![repeated_mod], false), Err(Error::DuplicateIdError(_))));
+    }
+
+    #[test]
+    fn preview_lists_conflicting_fields() {
+        let moved = ArkServer { host: "ark2.example".to_string(), service_name: "ark-main".to_string(), ..island() };
+        assert_eq!(
+            preview(&Change::UpdateServer { before: island(), after: moved }),
+            "~ update server 1 (Island), conflicting fields: host, service_name"
+        );
+        assert_eq!(preview(&Change::InsertServer { index: 0, server: ragnarok() }), "+ add server 2 (Ragnarok)");
+        assert_eq!(preview(&Change::DeleteServer { index: 0, server: ragnarok() }), "- remove server 2 (Ragnarok)");
+    }
+
+    #[test]
+    fn import_is_undone_in_one_step() {
+        let dir = std::env::temp_dir().join(format!("ark-manager-transfer-{}-import", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("can create test dir");
+        let config = Config::for_path(dir.clone(), dir.join("db.json"));
+        let mut state = ProgState::new(config, Box::new(MemoryStore::new(vec![island(), ragnarok()], 3)));
+        state.load().expect("loads");
+        let before = values(state.fleet.servers());
+
+        let path = dir.join("fleet.yaml");
+        let renamed = ArkServer { name: "Ragnarok PvE".to_string(), ..ragnarok() };
+        fs::write(&path, export(&[renamed, ArkServer { id: 0, ..ArkServer::named("Valguero") }], Format::Yaml).expect("exports"))
+            .expect("can write import");
+        run(&mut state, TransferCommand::Import { path, format: None, replace: true, dry_run: false }).expect("imports");
+        assert_eq!(state.fleet.servers().iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["Ragnarok PvE", "Valguero"]);
+        assert_eq!(state.history.undo_entries().count(), 1);
+        assert_eq!(state.audit.read_all().expect("reads").len(), 1);
+
+        undo(&mut state).expect("undoes");
+        assert_eq!(values(state.fleet.servers()), before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::ark::{ArkServer, ArkServerMod, Error};
+use crate::db::MemoryStore;
+use crate::host::shell_split;
+use crate::service::BackendKind;
+
+//Longest name that still fits the list panes.
+const MAX_NAME_LEN: usize = 64;
+//Ages are counted in days, anything past a century is a typo.
+const MAX_AGE: usize = 36_500;
+//systemd refuses unit names longer than this.
+const MAX_UNIT_NAME_LEN: usize = 255;
+
+//Apply an edit to one field of a server, checking the new value first.
+//Fields are numbered as in the server edit table: ID, name, category, age, service name, backend, executable, arguments,
+//container, image, host, config dir.
+pub fn set_server_field(fleet: &MemoryStore, ark_server: &mut ArkServer, field: usize, value: &str) -> Result<(), Error> {
+    match field {
+        0 => {
+            let id = parse_number("ID", value, 1, usize::MAX)?;
+            if id != ark_server.id && fleet.servers().iter().any(|s| s.id == id) {
+                return Err(invalid("ID", format!("server ID {} is already in use", id)))
+            }
+            ark_server.id = id;
+        }
+        1 => ark_server.name = check_name("name", value)?,
+        2 => ark_server.category = value.trim().to_string(),
+        3 => ark_server.age = parse_number("age", value, 0, MAX_AGE)?,
+        4 => {
+            let unit = check_unit_name(value)?;
+            //Two servers on one host with the same unit would start and stop each other.
+            if let Some(other) = fleet.servers().iter().find(|s| {
+                s.id != ark_server.id && s.host == ark_server.host && !unit.is_empty() && same_unit(&s.service_name, &unit)
+            }) {
+                return Err(invalid("service name", format!("{} is already used by server {} on the same host", unit, other.name)))
+            }
+            ark_server.service_name = unit;
+        }
+        5 => {
+            ark_server.backend = BackendKind::from_name(value)
+                .ok_or_else(|| invalid("backend", format!("{:?} is not one of systemd, supervisor, docker, podman or fake", value)))?;
+        }
+        6 => ark_server.executable = value.trim().to_string(),
+        7 => {
+            if shell_split(value).is_none() {
+                return Err(invalid("arguments", "has a quote that is not closed".to_string()))
+            }
+            ark_server.arguments = value.trim().to_string();
+        }
+        8 => ark_server.container = check_container_name(value)?,
+        9 => {
+            if value.trim().contains(char::is_whitespace) {
+                return Err(invalid("image", "must not contain spaces".to_string()))
+            }
+            ark_server.image = value.trim().to_string();
+        }
+        10 => ark_server.host = check_host(value)?,
+        11 => ark_server.config_dir = value.trim().to_string(),
+        _ => {}
+    }
+    Ok(())
+}
+
+//Apply an edit to one field of a mod, checking the new value first.
+//Fields are numbered as in the mod edit table: ID, name, category, age.
+pub fn set_mod_field(ark_server: &ArkServer, ark_server_mod: &mut ArkServerMod, field: usize, value: &str) -> Result<(), Error> {
+    match field {
+        0 => {
+            let id = parse_number("ID", value, 1, usize::MAX)?;
+            if id != ark_server_mod.id && ark_server.mods.iter().any(|m| m.id == id) {
+                return Err(invalid("ID", format!("mod {} is already on server {}", id, ark_server.name)))
+            }
+            ark_server_mod.id = id;
+        }
+        1 => ark_server_mod.name = check_name("name", value)?,
+        2 => ark_server_mod.category = value.trim().to_string(),
+        3 => ark_server_mod.age = parse_number("age", value, 0, MAX_AGE)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn invalid(field: &'static str, message: String) -> Error {
+    Error::ValidationError(field, message)
+}
+
+fn parse_number(field: &'static str, value: &str, min: usize, max: usize) -> Result<usize, Error> {
+    let number = value
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| invalid(field, format!("{:?} is not a whole number", value)))?;
+    if number < min || number > max {
+        return Err(invalid(field, format!("must be between {} and {}", min, max)))
+    }
+    Ok(number)
+}
+
+fn check_name(field: &'static str, value: &str) -> Result<String, Error> {
+    let name = value.trim();
+    if name.is_empty() {
+        return Err(invalid(field, "must not be empty".to_string()))
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(invalid(field, format!("must be at most {} characters", MAX_NAME_LEN)))
+    }
+    Ok(name.to_string())
+}
+
+//Empty means the server is not managed by a service yet. Otherwise it has to be something systemctl accepts,
+//e.g. arkserver, arkserver.service or arkserver@island.service.
+fn check_unit_name(value: &str) -> Result<String, Error> {
+    let unit = value.trim();
+    if unit.is_empty() {
+        return Ok(String::new())
+    }
+    if unit.len() > MAX_UNIT_NAME_LEN {
+        return Err(invalid("service name", format!("must be at most {} characters", MAX_UNIT_NAME_LEN)))
+    }
+    if let Some(c) = unit.chars().find(|c| !(c.is_ascii_alphanumeric() || ":-_.\\@".contains(*c))) {
+        return Err(invalid("service name", format!("{:?} is not allowed in a systemd unit name", c)))
+    }
+    if unit.starts_with('.') || unit.starts_with('@') || unit.matches('@').count() > 1 {
+        return Err(invalid("service name", format!("{} is not a valid systemd unit name", unit)))
+    }
+    Ok(unit.to_string())
+}
+
+//systemctl adds .service to a unit name without a suffix, so arkserver and arkserver.service are the same unit.
+fn same_unit(a: &str, b: &str) -> bool {
+    a.trim_end_matches(".service") == b.trim_end_matches(".service")
+}
+
+//Empty for servers that do not run in a container. docker and podman both want [a-zA-Z0-9][a-zA-Z0-9_.-]*.
+fn check_container_name(value: &str) -> Result<String, Error> {
+    let name = value.trim();
+    if name.is_empty() {
+        return Ok(String::new())
+    }
+    let valid_start = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric());
+    if !valid_start || !name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+        return Err(invalid("container", format!("{} is not a valid container name", name)))
+    }
+    Ok(name.to_string())
+}
+
+//Empty for this machine. Otherwise a host name or ~/.ssh/config alias, optionally with a user, e.g. ark@node2.
+//A leading - would be taken as an ssh option.
+fn check_host(value: &str) -> Result<String, Error> {
+    let host = value.trim();
+    if host.is_empty() {
+        return Ok(String::new())
+    }
+    if host.starts_with('-') || host.matches('@').count() > 1 || host.ends_with('@') {
+        return Err(invalid("host", format!("{} is not a valid ssh host", host)))
+    }
+    if let Some(c) = host.chars().find(|c| !(c.is_ascii_alphanumeric() || "-_.@".contains(*c))) {
+        return Err(invalid("host", format!("{:?} is not allowed in a host name", c)))
+    }
+    Ok(host.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::ServerStore;
+
+    fn fleet() -> MemoryStore {
+        let island = ArkServer {
+            id: 1,
+            service_name: "arkserver@island.service".to_string(),
+            mods: vec![ArkServerMod { id: 731604991, ..ArkServerMod::named("Structures Plus") }],
+            ..ArkServer::named("Island")
+        };
+        let remote = ArkServer { id: 2, host: "ark2.example".to_string(), service_name: "arkserver".to_string(), ..ArkServer::named("Ragnarok") };
+        MemoryStore::new(vec![island, remote], 3)
+    }
+
+    fn new_server() -> ArkServer {
+        ArkServer { id: 3, ..ArkServer::named("Valguero") }
+    }
+
+    //The field's value after the edit, or the field the error was reported for.
+    fn edit_server(field: usize, value: &str) -> Result<ArkServer, &'static str> {
+        let mut ark_server = new_server();
+        match set_server_field(&fleet(), &mut ark_server, field, value) {
+            Ok(()) => Ok(ark_server),
+            Err(Error::ValidationError(field, _)) => Err(field),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    fn edit_mod(field: usize, value: &str) -> Result<ArkServerMod, &'static str> {
+        let fleet = fleet();
+        let ark_server = fleet.get_server(1).expect("exists");
+        let mut ark_server_mod = ArkServerMod { id: 751991809, ..ArkServerMod::named("Death Helper") };
+        match set_mod_field(&ark_server, &mut ark_server_mod, field, value) {
+            Ok(()) => Ok(ark_server_mod),
+            Err(Error::ValidationError(field, _)) => Err(field),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn server_id() {
+        assert_eq!(edit_server(0, " 7 ").map(|s| s.id), Ok(7));
+        assert_eq!(edit_server(0, "3").map(|s| s.id), Ok(3));
+        assert_eq!(edit_server(0, "1").map(|s| s.id), Err("ID"));
+        assert_eq!(edit_server(0, "0").map(|s| s.id), Err("ID"));
+        assert_eq!(edit_server(0, "-1").map(|s| s.id), Err("ID"));
+        assert_eq!(edit_server(0, "seven").map(|s| s.id), Err("ID"));
+    }
+
+    #[test]
+    fn server_name() {
+        assert_eq!(edit_server(1, "  The Center ").map(|s| s.name), Ok("The Center".to_string()));
+        assert_eq!(edit_server(1, &"x".repeat(MAX_NAME_LEN)).map(|s| s.name.len()), Ok(MAX_NAME_LEN));
+        assert_eq!(edit_server(1, "").map(|s| s.name), Err("name"));
+        assert_eq!(edit_server(1, "   ").map(|s| s.name), Err("name"));
+        assert_eq!(edit_server(1, &"x".repeat(MAX_NAME_LEN + 1)).map(|s| s.name), Err("name"));
+    }
+
+    #[test]
+    fn server_age() {
+        assert_eq!(edit_server(3, "0").map(|s| s.age), Ok(0));
+        assert_eq!(edit_server(3, "36500").map(|s| s.age), Ok(MAX_AGE));
+        assert_eq!(edit_server(3, "36501").map(|s| s.age), Err("age"));
+        assert_eq!(edit_server(3, "1.5").map(|s| s.age), Err("age"));
+        assert_eq!(edit_server(3, "").map(|s| s.age), Err("age"));
+    }
+
+    #[test]
+    fn server_service_name() {
+        assert_eq!(edit_server(4, "").map(|s| s.service_name), Ok(String::new()));
+        assert_eq!(edit_server(4, "arkserver@valguero.service").map(|s| s.service_name), Ok("arkserver@valguero.service".to_string()));
+        //Only clashes on the same host count.
+        assert_eq!(edit_server(4, "arkserver").map(|s| s.service_name), Ok("arkserver".to_string()));
+        assert_eq!(edit_server(4, "arkserver@island.service").map(|s| s.service_name), Err("service name"));
+        assert_eq!(edit_server(4, "arkserver@island").map(|s| s.service_name), Err("service name"));
+        assert_eq!(edit_server(4, "ark server").map(|s| s.service_name), Err("service name"));
+        assert_eq!(edit_server(4, "@island.service").map(|s| s.service_name), Err("service name"));
+        assert_eq!(edit_server(4, "a@b@c").map(|s| s.service_name), Err("service name"));
+        assert_eq!(edit_server(4, &"a".repeat(MAX_UNIT_NAME_LEN + 1)).map(|s| s.service_name), Err("service name"));
+
+        //A server keeps its own unit when it is edited.
+        let fleet = fleet();
+        let mut island = fleet.get_server(1).expect("exists");
+        assert!(set_server_field(&fleet, &mut island, 4, "arkserver@island.service").is_ok());
+    }
+
+    #[test]
+    fn server_backend() {
+        assert_eq!(edit_server(5, "docker").map(|s| s.backend), Ok(BackendKind::Docker));
+        assert_eq!(edit_server(5, "kubernetes").map(|s| s.backend), Err("backend"));
+    }
+
+    #[test]
+    fn server_arguments() {
+        let arguments = r#"TheIsland?SessionName="My Server" -Port=7777"#;
+        assert_eq!(edit_server(7, arguments).map(|s| s.arguments), Ok(arguments.to_string()));
+        assert_eq!(edit_server(7, "TheIsland?SessionName='My Server").map(|s| s.arguments), Err("arguments"));
+    }
+
+    #[test]
+    fn server_container_and_image() {
+        assert_eq!(edit_server(8, "ark-island_1.0").map(|s| s.container), Ok("ark-island_1.0".to_string()));
+        assert_eq!(edit_server(8, "-island").map(|s| s.container), Err("container"));
+        assert_eq!(edit_server(8, "ark/island").map(|s| s.container), Err("container"));
+        assert_eq!(edit_server(9, "hermsi/ark-server:latest").map(|s| s.image), Ok("hermsi/ark-server:latest".to_string()));
+        assert_eq!(edit_server(9, "hermsi/ark-server latest").map(|s| s.image), Err("image"));
+    }
+
+    #[test]
+    fn server_host() {
+        assert_eq!(edit_server(10, "").map(|s| s.host), Ok(String::new()));
+        assert_eq!(edit_server(10, "ark@node2.example").map(|s| s.host), Ok("ark@node2.example".to_string()));
+        assert_eq!(edit_server(10, "-oProxyCommand=sh").map(|s| s.host), Err("host"));
+        assert_eq!(edit_server(10, "ark@").map(|s| s.host), Err("host"));
+        assert_eq!(edit_server(10, "a@b@node2").map(|s| s.host), Err("host"));
+        assert_eq!(edit_server(10, "node2;reboot").map(|s| s.host), Err("host"));
+    }
+
+    #[test]
+    fn mod_fields() {
+        assert_eq!(edit_mod(0, "1404697612").map(|m| m.id), Ok(1404697612));
+        assert_eq!(edit_mod(0, "751991809").map(|m| m.id), Ok(751991809));
+        assert_eq!(edit_mod(0, "731604991").map(|m| m.id), Err("ID"));
+        assert_eq!(edit_mod(0, "0").map(|m| m.id), Err("ID"));
+        assert_eq!(edit_mod(1, " Death Helper 2 ").map(|m| m.name), Ok("Death Helper 2".to_string()));
+        assert_eq!(edit_mod(1, "").map(|m| m.name), Err("name"));
+        assert_eq!(edit_mod(3, "30").map(|m| m.age), Ok(30));
+        assert_eq!(edit_mod(3, "99999").map(|m| m.age), Err("age"));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::ark::{ArkServer, Error, Event, ProgState};
+use crate::db::{commit, get_server, ServerStore};
+use crate::history::Change;
+use crate::host;
+
+use std::path::{Path, PathBuf};
+use std::thread;
+
+//ARK keeps a folder with this ID next to the Workshop mods for its own content, it is not a mod.
+const BUILTIN_MOD_DIR: &str = "111111111";
+
+//A mod found in the server's ShooterGame/Content/Mods.
+#[derive(Clone, Debug)]
+pub struct InstalledMod {
+    pub id: usize,
+    //From mod.info, empty if it could not be read.
+    pub name: String,
+    pub size: u64,
+    pub version: String,
+}
+
+//How the last scan of a server's Workshop content compared with its mod list.
+pub struct ModScan {
+    pub server_id: usize,
+    //Mods on the list that are not installed, by ID.
+    pub missing: Vec<usize>,
+    //Mods that are installed but not on the list.
+    pub unlisted: Vec<InstalledMod>,
+}
+
+impl ModScan {
+    pub fn is_missing(&self, server_id: usize, mod_id: usize) -> bool {
+        self.server_id == server_id && self.missing.contains(&mod_id)
+    }
+}
+
+//ShooterGame/Content/Mods, found from the config dir or executable, whichever is set and inside ShooterGame.
+pub fn mods_dir(ark_server: &ArkServer) -> Result<PathBuf, Error> {
+    [&ark_server.config_dir, &ark_server.executable]
+        .iter()
+        .filter(|path| !path.is_empty())
+        .find_map(|path| {
+            Path::new(path)
+                .ancestors()
+                .find(|dir| dir.file_name().is_some_and(|name| name == "ShooterGame"))
+                .map(|dir| dir.join("Content").join("Mods"))
+        })
+        .ok_or_else(|| Error::ConfigError(format!(
+            "set the config dir or executable of server {} to a path inside ShooterGame first",
+            ark_server.name
+        )))
+}
+
+//Every mod installed in the Mods folder. Each one is a folder named after its Workshop ID.
+pub fn scan_installed(host: &str, dir: &Path) -> Result<Vec<InstalledMod>, Error> {
+    let folders = host::dir_sizes(host, dir)?
+        .ok_or_else(|| Error::ConfigError(format!("{} does not exist on {}", dir.display(), host_name(host))))?;
+    let mut installed = Vec::new();
+    for (folder, size) in folders {
+        let id = match folder.parse::<usize>() {
+            Ok(id) if folder != BUILTIN_MOD_DIR => id,
+            _ => continue,
+        };
+        let mod_dir = dir.join(&folder);
+        let name = host::read_bytes(host, &mod_dir.join("mod.info"))?
+            .and_then(|content| parse_mod_info(&content))
+            .unwrap_or_default();
+        let meta = host::read_bytes(host, &mod_dir.join("modmeta.info"))?
+            .map(|content| parse_modmeta(&content))
+            .unwrap_or_default();
+        let version = match meta.iter().find(|(key, _)| key.eq_ignore_ascii_case("Version") || key.eq_ignore_ascii_case("ModVersion")) {
+            Some((_, version)) => version.clone(),
+            //Most mods do not record a version, when the Workshop last updated them is the next best thing.
+            None => host::metadata(host, &mod_dir.join("mod.info"))?
+                .map(|(_, modified)| format!("updated {}", modified.format("%Y-%m-%d")))
+                .unwrap_or_default(),
+        };
+        installed.push(InstalledMod { id, name, size, version });
+    }
+    installed.sort_by_key(|m| m.id);
+    Ok(installed)
+}
+
+//mod.info starts with the mod's name as an Unreal string, followed by the maps it adds.
+pub fn parse_mod_info(content: &[u8]) -> Option<String> {
+    read_fstring(content, &mut 0).filter(|name| !name.is_empty())
+}
+
+//modmeta.info is a count followed by that many key and value pairs of Unreal strings, e.g. ModType=1.
+pub fn parse_modmeta(content: &[u8]) -> Vec<(String, String)> {
+    let mut pos = 0;
+    let count = read_i32(content, &mut pos).unwrap_or(0).max(0);
+    let mut pairs = Vec::new();
+    for _ in 0..count {
+        match (read_fstring(content, &mut pos), read_fstring(content, &mut pos)) {
+            (Some(key), Some(value)) => pairs.push((key, value)),
+            _ => break,
+        }
+    }
+    pairs
+}
+
+fn read_i32(content: &[u8], pos: &mut usize) -> Option<i32> {
+    let bytes = content.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+//A length followed by a null terminated string: Latin-1 for a positive length, UTF-16 for a negative one.
+fn read_fstring(content: &[u8], pos: &mut usize) -> Option<String> {
+    let len = read_i32(content, pos)?;
+    let text = if len >= 0 {
+        let bytes = content.get(*pos..*pos + len as usize)?;
+        *pos += len as usize;
+        bytes.iter().map(|&b| b as char).collect::<String>()
+    } else {
+        let num_bytes = (len.unsigned_abs() as usize).checked_mul(2)?;
+        let bytes = content.get(*pos..*pos + num_bytes)?;
+        *pos += num_bytes;
+        let units: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    Some(text.trim_end_matches('\0').to_string())
+}
+
+fn host_name(host: &str) -> &str {
+    match host {
+        "" => "this machine",
+        host => host,
+    }
+}
+
+//What a scan on a worker thread found, or why it failed.
+pub struct ModScanOutcome {
+    //The DB the scan was started from. Server IDs mean nothing in another profile's fleet.
+    pub db_path: PathBuf,
+    pub server_id: usize,
+    pub dir: PathBuf,
+    pub result: Result<Vec<InstalledMod>, Error>,
+}
+
+//Scan the selected server's Workshop content on a worker thread, reading every mod's files can take a while over
+//ssh. finish_mod_scan takes the result once it is posted back.
+pub fn scan_mods(state: &mut ProgState) -> Result<(), Error> {
+    let ark_server = get_server(state)?;
+    if state.scanning_mods.is_some() {
+        return Err(Error::ServiceError("wait for the running mod scan to finish".to_string()))
+    }
+    let dir = mods_dir(&ark_server)?;
+    let server_id = ark_server.id;
+    let notification = format!("Scanning {} on {}...", dir.display(), host_name(&ark_server.host));
+    let db_path = state.config.db_path.clone();
+    let run = move || ModScanOutcome {
+        db_path,
+        server_id,
+        result: scan_installed(&ark_server.host, &dir),
+        dir,
+    };
+    match state.events.clone() {
+        Some(events) => {
+            state.scanning_mods = Some(server_id);
+            state.notification = Some(notification);
+            thread::spawn(move || {
+                let _ = events.send(Event::ModScanDone(Box::new(run())));
+            });
+            Ok(())
+        }
+        None => finish_mod_scan(state, run()),
+    }
+}
+
+//Names, sizes and versions of listed mods are updated as one change, so one undo puts them back, and what does not
+//match the list is kept for the Mods view to flag. The server is looked up again as it may have changed meanwhile.
+pub fn finish_mod_scan(state: &mut ProgState, outcome: ModScanOutcome) -> Result<(), Error> {
+    if outcome.db_path != state.config.db_path {
+        return Ok(())
+    }
+    state.scanning_mods = None;
+    let installed = outcome.result?;
+    let before = state.fleet.get_server(outcome.server_id)?;
+    let dir = outcome.dir;
+
+    let mut after = before.clone();
+    let mut missing = Vec::new();
+    for ark_mod in &mut after.mods {
+        match installed.iter().find(|m| m.id == ark_mod.id) {
+            Some(found) => {
+                if !found.name.is_empty() {
+                    ark_mod.name = found.name.clone();
+                }
+                ark_mod.size = found.size;
+                ark_mod.version = found.version.clone();
+            }
+            None => missing.push(ark_mod.id),
+        }
+    }
+    let unlisted: Vec<InstalledMod> = installed
+        .into_iter()
+        .filter(|m| !before.mods.iter().any(|listed| listed.id == m.id))
+        .collect();
+
+    let mut summary = vec![format!("Scanned {}: {} of {} mod(s) installed", dir.display(), after.mods.len() - missing.len(), after.mods.len())];
+    if !missing.is_empty() {
+        summary.push(format!("missing: {}", missing.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")));
+    }
+    if !unlisted.is_empty() {
+        summary.push(format!("installed but not listed: {}", unlisted.iter().map(|m| m.id.to_string()).collect::<Vec<_>>().join(", ")));
+    }
+    if serde_json::to_value(&before)? != serde_json::to_value(&after)? {
+        commit(state, Change::UpdateServer { before, after: after.clone() })?;
+    }
+    state.mod_scan = Some(ModScan { server_id: after.id, missing, unlisted });
+    state.notification = Some(summary.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ark::ArkServerMod;
+    use crate::config::Config;
+    use crate::db::MemoryStore;
+
+    use std::env;
+    use std::fs;
+
+    fn test_state(name: &str) -> ProgState<'static> {
+        let dir = env::temp_dir().join(format!("ark-manager-workshop-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("can create test dir");
+        let ark_server = ArkServer {
+            id: 1,
+            mods: vec![ArkServerMod { id: 731604991, ..ArkServerMod::named("Structures Plus") }],
+            ..ArkServer::named("Island")
+        };
+        let mut state = ProgState::new(Config::for_path(dir.clone(), dir.join("db.json")), Box::new(MemoryStore::new(vec![ark_server], 2)));
+        state.load().expect("loads");
+        state
+    }
+
+    fn outcome(state: &ProgState, db_path: PathBuf) -> ModScanOutcome {
+        ModScanOutcome {
+            db_path,
+            server_id: 1,
+            dir: state.config.data_dir.join("Mods"),
+            result: Ok(vec![InstalledMod { id: 731604991, name: "S+".to_string(), size: 100, version: "3".to_string() }]),
+        }
+    }
+
+    #[test]
+    fn finish_mod_scan_updates_listed_mods() {
+        let mut state = test_state("finish");
+        state.scanning_mods = Some(1);
+        let outcome = outcome(&state, state.config.db_path.clone());
+        finish_mod_scan(&mut state, outcome).expect("finishes");
+        let ark_mod = state.fleet.get_mod(1, 731604991).expect("still listed");
+        assert_eq!((ark_mod.name.as_str(), ark_mod.size, ark_mod.version.as_str()), ("S+", 100, "3"));
+        assert_eq!(state.scanning_mods, None);
+    }
+
+    #[test]
+    fn finish_mod_scan_ignores_other_profiles() {
+        let mut state = test_state("other-profile");
+        let outcome = outcome(&state, state.config.data_dir.join("other.json"));
+        finish_mod_scan(&mut state, outcome).expect("finishes");
+        assert_eq!(state.fleet.get_mod(1, 731604991).expect("still listed").name, "Structures Plus");
+        assert!(state.mod_scan.is_none());
+    }
+
+    //An Unreal string as the editor writes it: Latin-1 with a positive length, UTF-16 with a negative one, both
+    //counting the null terminator.
+    fn fstring(text: &str) -> Vec<u8> {
+        let mut bytes = ((text.len() + 1) as i32).to_le_bytes().to_vec();
+        bytes.extend(text.bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    fn fstring_utf16(text: &str) -> Vec<u8> {
+        let units: Vec<u16> = text.encode_utf16().chain([0]).collect();
+        let mut bytes = (-(units.len() as i32)).to_le_bytes().to_vec();
+        bytes.extend(units.iter().flat_map(|unit| unit.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn read_fstring_latin1_and_utf16() {
+        let mut content = fstring("Structures Plus (S+)");
+        content.extend(fstring_utf16("Awesome Spyglass! 望远镜"));
+        let mut pos = 0;
+        assert_eq!(read_fstring(&content, &mut pos).as_deref(), Some("Structures Plus (S+)"));
+        assert_eq!(read_fstring(&content, &mut pos).as_deref(), Some("Awesome Spyglass! 望远镜"));
+        assert_eq!(pos, content.len());
+        assert_eq!(read_fstring(&content, &mut pos), None);
+        assert_eq!(read_fstring(&0i32.to_le_bytes(), &mut 0).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn read_fstring_rejects_bad_lengths() {
+        let truncated = fstring("Structures Plus");
+        let utf16_truncated = fstring_utf16("Structures Plus");
+        let cases: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            vec![5, 0],
+            truncated[..truncated.len() - 1].to_vec(),
+            utf16_truncated[..utf16_truncated.len() - 1].to_vec(),
+            [i32::MAX.to_le_bytes().as_slice(), b"Structures Plus\0"].concat(),
+            [i32::MIN.to_le_bytes().as_slice(), b"Structures Plus\0"].concat(),
+            [(-1i32).to_le_bytes().as_slice(), b"S"].concat(),
+        ];
+        for content in cases {
+            let mut pos = 0;
+            assert_eq!(read_fstring(&content, &mut pos), None, "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn parse_mod_info_reads_the_name() {
+        let mut content = fstring("Structures Plus (S+)");
+        content.extend(1i32.to_le_bytes());
+        content.extend(fstring("/Game/Mods/StructuresPlus/PrimalGameData_StructuresPlus"));
+        assert_eq!(parse_mod_info(&content).as_deref(), Some("Structures Plus (S+)"));
+        assert_eq!(parse_mod_info(&fstring_utf16("望远镜")).as_deref(), Some("望远镜"));
+        assert_eq!(parse_mod_info(&fstring("")), None);
+        assert_eq!(parse_mod_info(b""), None);
+        assert_eq!(parse_mod_info(&content[..10]), None);
+        assert_eq!(parse_mod_info(&[0xff, 0xff, 0xff, 0x7f, b'S']), None);
+    }
+
+    #[test]
+    fn parse_modmeta_reads_pairs() {
+        let mut content = 2i32.to_le_bytes().to_vec();
+        for text in ["ModType", "1", "GUID", "3ce2b4a1"] {
+            content.extend(fstring(text));
+        }
+        let pairs = vec![("ModType".to_string(), "1".to_string()), ("GUID".to_string(), "3ce2b4a1".to_string())];
+        assert_eq!(parse_modmeta(&content), pairs);
+        //Whatever is complete before the file ends is kept.
+        assert_eq!(parse_modmeta(&content[..content.len() - 3]), pairs[..1]);
+    }
+
+    #[test]
+    fn parse_modmeta_survives_bad_counts_and_lengths() {
+        assert!(parse_modmeta(b"").is_empty());
+        assert!(parse_modmeta(&[1, 0]).is_empty());
+        assert!(parse_modmeta(&(-3i32).to_le_bytes()).is_empty());
+        assert!(parse_modmeta(&i32::MAX.to_le_bytes()).is_empty());
+        let mut oversized = 1i32.to_le_bytes().to_vec();
+        oversized.extend(i32::MAX.to_le_bytes());
+        oversized.extend(b"ModType\0");
+        assert!(parse_modmeta(&oversized).is_empty());
+        let mut negative = 1i32.to_le_bytes().to_vec();
+        negative.extend(fstring("ModType"));
+        negative.extend(i32::MIN.to_le_bytes());
+        assert!(parse_modmeta(&negative).is_empty());
+    }
+}